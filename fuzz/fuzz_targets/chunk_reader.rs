@@ -0,0 +1,29 @@
+#![no_main]
+
+use std::{io::Cursor, str::from_utf8};
+
+use libfuzzer_sys::fuzz_target;
+use one_billion_rows::{parse_line, NumericFormat, StrBuffer};
+
+fuzz_target!(|data: &[u8]| {
+    let mut cursor = Cursor::new(data);
+    let mut read_back = String::new();
+
+    loop {
+        match StrBuffer::read_from(&mut cursor) {
+            Ok(Some(chunk)) => {
+                for line in chunk.lines() {
+                    let _ = parse_line(line, b';', NumericFormat::Strict);
+                    let _ = parse_line(line, b';', NumericFormat::Lenient);
+                }
+                read_back.push_str(&chunk);
+            }
+            Ok(None) => break,
+            // invalid utf8 and too long lines are reported as errors
+            Err(_) => return,
+        }
+    }
+
+    // every byte of valid input must end up in exactly one chunk
+    assert_eq!(from_utf8(data).ok(), Some(read_back.as_str()));
+});
@@ -0,0 +1,151 @@
+use std::{
+    collections::HashMap,
+    fs::{rename, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::CityResult;
+
+/// The state of an interrupted generator run.
+///
+/// Resuming from a checkpoint with the same arguments produces exactly the same
+/// output as an uninterrupted run.
+#[derive(Debug)]
+pub struct Checkpoint {
+    /// The seed of the rng used for the run
+    pub seed: [u8; 32],
+    /// The position of the rng after the last written row
+    pub word_pos: u128,
+    /// The number of rows written so far
+    pub rows: usize,
+    /// The length of the output file after the last written row
+    pub bytes: u64,
+    /// The hash of the output file after the last written row
+    pub hash: u64,
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint and the partial expected result to `path`.
+    ///
+    /// The checkpoint is written to a temporary file first, so that an interruption
+    /// while writing does not corrupt an existing checkpoint.
+    pub fn write(&self, path: &Path, results: Option<&HashMap<String, CityResult>>) -> Result<()> {
+        let tmp_path = path.with_extension("tmp");
+        let file = File::create(&tmp_path).context("failed to create checkpoint file")?;
+        let mut writer = BufWriter::new(file);
+
+        let seed: String = self.seed.iter().map(|b| format!("{b:02x}")).collect();
+        writeln!(writer, "seed {seed}")?;
+        writeln!(writer, "word_pos {}", self.word_pos)?;
+        writeln!(writer, "rows {}", self.rows)?;
+        writeln!(writer, "bytes {}", self.bytes)?;
+        writeln!(writer, "hash {:016x}", self.hash)?;
+        if let Some(results) = results {
+            writeln!(writer, "results {}", results.len())?;
+            for city in results.values() {
+                writeln!(
+                    writer,
+                    "{} {} {} {} {}",
+                    city.count, city.total, city.min, city.max, city.name
+                )?;
+            }
+        }
+        writer.flush().context("failed to write checkpoint file")?;
+        drop(writer);
+
+        rename(&tmp_path, path).context("failed to replace checkpoint file")
+    }
+
+    /// Reads a checkpoint and the partial expected result, if it was stored.
+    pub fn read(path: &Path) -> Result<(Self, Option<HashMap<String, CityResult>>)> {
+        let file = File::open(path).context("failed to open checkpoint file")?;
+        let mut lines = BufReader::new(file).lines();
+
+        let seed_hex = next_value(&mut lines, "seed")?;
+        let mut seed = [0u8; 32];
+        if seed_hex.len() != 64 {
+            bail!("invalid seed in checkpoint file");
+        }
+        for (i, byte) in seed.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&seed_hex[i * 2..i * 2 + 2], 16)
+                .context("invalid seed in checkpoint file")?;
+        }
+        let word_pos = next_value(&mut lines, "word_pos")?
+            .parse()
+            .context("invalid word_pos in checkpoint file")?;
+        let rows = next_value(&mut lines, "rows")?
+            .parse()
+            .context("invalid rows in checkpoint file")?;
+        let bytes = next_value(&mut lines, "bytes")?
+            .parse()
+            .context("invalid bytes in checkpoint file")?;
+        let hash = u64::from_str_radix(&next_value(&mut lines, "hash")?, 16)
+            .context("invalid hash in checkpoint file")?;
+
+        let results = if let Some(line) = lines.next() {
+            let line = line.context("failed to read checkpoint file")?;
+            let count: usize = match line.split_once(' ') {
+                Some(("results", count)) => count
+                    .parse()
+                    .context("invalid result count in checkpoint file")?,
+                _ => bail!("expected results in checkpoint file, found {line:?}"),
+            };
+            let mut results = HashMap::with_capacity(count);
+            for _ in 0..count {
+                let line = next_line(&mut lines)?;
+                let city = parse_city_result(&line)
+                    .with_context(|| format!("invalid result in checkpoint file: {line:?}"))?;
+                results.insert(city.name.clone(), city);
+            }
+            Some(results)
+        } else {
+            None
+        };
+
+        let checkpoint = Checkpoint {
+            seed,
+            word_pos,
+            rows,
+            bytes,
+            hash,
+        };
+        Ok((checkpoint, results))
+    }
+}
+
+fn next_line<I: Iterator<Item = std::io::Result<String>>>(lines: &mut I) -> Result<String> {
+    lines
+        .next()
+        .context("checkpoint file is incomplete")?
+        .context("failed to read checkpoint file")
+}
+
+fn next_value<I: Iterator<Item = std::io::Result<String>>>(
+    lines: &mut I,
+    key: &str,
+) -> Result<String> {
+    let line = next_line(lines)?;
+    match line.split_once(' ') {
+        Some((k, value)) if k == key => Ok(value.to_owned()),
+        _ => bail!("expected {key} in checkpoint file, found {line:?}"),
+    }
+}
+
+fn parse_city_result(line: &str) -> Option<CityResult> {
+    let mut parts = line.splitn(5, ' ');
+    let count = parts.next()?.parse().ok()?;
+    let total = parts.next()?.parse().ok()?;
+    let min = parts.next()?.parse().ok()?;
+    let max = parts.next()?.parse().ok()?;
+    let name = parts.next()?.to_owned();
+    Some(CityResult {
+        name,
+        count,
+        total,
+        min,
+        max,
+    })
+}
@@ -0,0 +1,70 @@
+use anyhow::{bail, Context, Result};
+
+/// The statistics of a city in a result file, see `--from-result`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetCity {
+    pub name: String,
+    pub min: f32,
+    pub mean: f32,
+    pub max: f32,
+}
+
+/// Parses a result in the challenge format `{city=min/mean/max, ...}`.
+pub fn parse_result(text: &str) -> Result<Vec<TargetCity>> {
+    let text = text.trim_end();
+    let Some(inner) = text
+        .strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+    else {
+        bail!("result is not enclosed in `{{}}`");
+    };
+    if inner.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    inner
+        .split(", ")
+        .map(|entry| {
+            let (name, values) = entry
+                .rsplit_once('=')
+                .with_context(|| format!("missing `=` in {entry:?}"))?;
+            let values = values
+                .split('/')
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("invalid value in {entry:?}"))?;
+            let &[min, mean, max] = values.as_slice() else {
+                bail!("expected `min/mean/max` in {entry:?}");
+            };
+            if !(min <= mean && mean <= max) {
+                bail!("expected min <= mean <= max in {entry:?}");
+            }
+            Ok(TargetCity {
+                name: name.to_string(),
+                min,
+                mean,
+                max,
+            })
+        })
+        .collect()
+}
+
+/// `count` scaled values with exactly the given `min` and `max` and a mean as
+/// close to `mean` as integer values allow.
+///
+/// `count` must be at least 2, as min and max are always part of the values.
+pub fn scaled_values(min: i64, mean: f64, max: i64, count: usize) -> Vec<i64> {
+    assert!(count >= 2, "min and max need at least 2 values");
+    let mut values = Vec::with_capacity(count);
+    values.extend([min, max]);
+
+    // spread the remaining sum evenly over the values between min and max
+    let between = (count - 2) as i64;
+    if between > 0 {
+        let sum = (mean * count as f64).round() as i64 - min - max;
+        let sum = sum.clamp(between * min, between * max);
+        let (base, rest) = (sum.div_euclid(between), sum.rem_euclid(between));
+        values.extend((0..between).map(|i| if i < rest { base + 1 } else { base }));
+    }
+    values
+}
@@ -0,0 +1,38 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+
+/// Writes the byte offset of every `interval`th line of the output.
+///
+/// The index starts with a line `interval N`, followed by one offset per line.
+/// Offset `k` is the start of line `k * N`, so the first one is always 0. Readers
+/// can split the output at these offsets without searching for line starts.
+pub struct IndexWriter {
+    writer: BufWriter<File>,
+    interval: u64,
+}
+
+impl IndexWriter {
+    pub fn create(path: &Path, interval: u64) -> Result<Self> {
+        let file = File::create(path).context("failed to create index file")?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "interval {interval}").context("failed to write index file")?;
+        Ok(IndexWriter { writer, interval })
+    }
+
+    /// Records that `line` starts at byte `offset`, if it is one of the indexed lines.
+    pub fn line_start(&mut self, line: u64, offset: u64) -> Result<()> {
+        if line.is_multiple_of(self.interval) {
+            writeln!(self.writer, "{offset}").context("failed to write index file")?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush().context("failed to write index file")
+    }
+}
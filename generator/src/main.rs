@@ -1,22 +1,212 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt::Display,
-    fs::{create_dir_all, File},
-    io::{BufWriter, Write},
-    path::PathBuf,
+    fs::{create_dir_all, read_to_string, remove_file, File, OpenOptions},
+    io::{copy, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use clap::{Parser, ValueEnum};
 use console::Term;
-use rand::{distributions::Alphanumeric, seq::SliceRandom, Rng, SeedableRng};
-use rand_distr::{Binomial, Distribution};
+use rand::{
+    distributions::{uniform::SampleUniform, Alphanumeric, WeightedIndex},
+    seq::SliceRandom,
+    Rng, SeedableRng,
+};
+use rand_chacha::ChaCha12Rng;
+use rand_distr::{Binomial, Distribution, Normal};
+
+use checkpoint::Checkpoint;
+use index::IndexWriter;
+use round_robin::RoundRobinWriter;
+
+mod checkpoint;
+mod from_result;
+mod index;
+mod round_robin;
+mod stations;
 
 #[derive(Debug, ValueEnum, Clone, Copy)]
 enum ArgPreset {
     Full,
     Cities400,
     Test,
+    /// The official challenge data set: 1,000,000,000 rows of its 413 weather
+    /// stations.
+    ///
+    /// The values of every station are normally distributed around its mean
+    /// temperature, see [stations::STATIONS]. `--cities-cache` is not used.
+    Official,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default)]
+enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+enum ColumnOrder {
+    /// `city;value`
+    #[default]
+    CityValue,
+    /// `value;city`
+    ValueCity,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+enum OutputFormat {
+    /// One `city;value` row per line
+    #[default]
+    Text,
+    /// Length prefixed binary records: `u8` name length, the name bytes
+    /// and the value in tenths as a little endian `i16`
+    Binary,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default)]
+enum ResultFormat {
+    /// The challenge output format `{city=min/mean/max, ...}`
+    #[default]
+    Text,
+    /// A json object mapping each city to its min, mean and max
+    Json,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+enum FractionBias {
+    /// Every last fractional digit is equally likely
+    #[default]
+    None,
+    /// The last fractional digit is 0 or 5 more often
+    Half,
+    /// The last fractional digit is 0 more often
+    Zero,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+enum NameStructure {
+    /// Random alphanumeric names
+    #[default]
+    Flat,
+    /// Random alphanumeric names behind one of a few region prefixes, e.g. `QX-Hw3k`
+    Prefixed,
+}
+
+/// A kind of malformed line, see `--malform-rate`
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum MalformKind {
+    /// The city and the value without the `;` between them
+    MissingSeparator,
+    /// A value that is not a number
+    NonNumeric,
+    /// A value from 100 to 999, outside the range of the challenge.
+    ///
+    /// The solver only rejects these with `--expect-min` and `--expect-max` or
+    /// `--spec-strict`, and fails instead of skipping them.
+    OutOfRange,
+    /// A third field after the row
+    ExtraField,
+    /// An empty line
+    EmptyLine,
+}
+
+impl NameStructure {
+    fn as_str(&self) -> &'static str {
+        match self {
+            NameStructure::Flat => "flat",
+            NameStructure::Prefixed => "prefixed",
+        }
+    }
+}
+
+impl FractionBias {
+    /// The weights of the digits 0 to 9, or `None` for a uniform distribution
+    fn weights(&self) -> Option<[u32; 10]> {
+        match self {
+            FractionBias::None => None,
+            FractionBias::Half => Some([3, 1, 1, 1, 1, 3, 1, 1, 1, 1]),
+            FractionBias::Zero => Some([5, 1, 1, 1, 1, 1, 1, 1, 1, 1]),
+        }
+    }
+}
+
+/// A city with its own value range, see `--pin-city`
+#[derive(Debug, Clone)]
+struct PinnedCity {
+    name: String,
+    min: f32,
+    max: f32,
+}
+
+impl FromStr for PinnedCity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (rest, max) = value.rsplit_once(':')?;
+            let (name, min) = rest.rsplit_once(':')?;
+            Some((name, min.parse::<f32>().ok()?, max.parse::<f32>().ok()?))
+        };
+        match parse() {
+            Some((name, min, max)) if !name.is_empty() && min <= max => Ok(PinnedCity {
+                name: name.to_string(),
+                min,
+                max,
+            }),
+            _ => Err("expected `NAME:MIN:MAX` with MIN <= MAX".to_string()),
+        }
+    }
+}
+
+/// The characters of the generated city names, see `--name-alphabet`
+#[derive(Debug, Clone, PartialEq)]
+struct NameAlphabet(Vec<char>);
+
+impl FromStr for NameAlphabet {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut chars = Vec::new();
+        for c in value.chars() {
+            if c == ';' {
+                return Err("the separator `;` cannot be part of city names".to_string());
+            }
+            if c.is_control() {
+                return Err(format!(
+                    "the control char {c:?} cannot be part of city names"
+                ));
+            }
+            if chars.contains(&c) {
+                return Err(format!("{c:?} is listed more than once"));
+            }
+            chars.push(c);
+        }
+        match chars.is_empty() {
+            true => Err("expected at least one char".to_string()),
+            false => Ok(NameAlphabet(chars)),
+        }
+    }
+}
+
+impl Display for NameAlphabet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|c| write!(f, "{c}"))
+    }
 }
 
 #[derive(Debug, Parser)]
@@ -25,6 +215,13 @@ struct Args {
     #[arg(short, long, default_value_t = 1_000_000_000)]
     line_count: usize,
 
+    /// Generate rows until the output reaches about this many bytes instead of a fixed
+    /// number of lines.
+    ///
+    /// The output always ends on a complete row, so it can be up to one row larger.
+    #[arg(long, conflicts_with = "line_count")]
+    target_size: Option<u64>,
+
     /// The number of cities to generate data for
     ///
     /// It is not guaranteed that all cities are used.
@@ -35,10 +232,30 @@ struct Args {
 
     /// The median length of the generated city names.
     ///
-    /// The length follows a binomial distribution with a std-deviation of 0.3.
-    #[arg(short, long, default_value_t = 5)]
+    /// The length follows a binomial distribution with a probability of `--city-len-p`.
+    #[arg(long, default_value_t = 5)]
     city_len: usize,
 
+    /// The probability of the binomial distribution of the city name length.
+    ///
+    /// Must be in (0, 1). Values closer to 0 or 1 cluster the lengths more tightly.
+    #[arg(long, default_value_t = 0.3)]
+    city_len_p: f64,
+
+    /// The structure of the generated city names.
+    ///
+    /// `prefixed` names share a few region prefixes, which distributes the names
+    /// differently in a hash map. The prefix counts towards the 100 byte limit.
+    #[arg(long, value_enum, default_value_t)]
+    name_structure: NameStructure,
+
+    /// The chars city names are made of, instead of ascii letters and digits.
+    ///
+    /// Every char is equally likely. Must not contain the separator `;` or control
+    /// chars. Names of multibyte chars end early to stay within the 100 byte limit.
+    #[arg(long, value_name = "CHARS")]
+    name_alphabet: Option<NameAlphabet>,
+
     /// The highest integer value that is generated (exclusive).
     /// This ignores the fractional digits. So a max_value of 99 with 1 fractional
     /// digit can generate a true max value of 99.9
@@ -51,14 +268,73 @@ struct Args {
     #[arg(long = "min", default_value_t = -99)]
     min_value: i32,
 
+    /// The exact lowest value that is generated (inclusive).
+    ///
+    /// Unlike `--min` this includes the fractional digits, so `--true-min -50`
+    /// will never generate a value below -50.0. Overrides `--min` and `--max`
+    /// and requires `--true-max`.
+    #[arg(long, requires = "true_max", allow_negative_numbers = true)]
+    true_min: Option<f32>,
+
+    /// The exact highest value that is generated (inclusive).
+    ///
+    /// Unlike `--max` this includes the fractional digits, so `--true-max 50`
+    /// will never generate a value above 50.0. Overrides `--min` and `--max`
+    /// and requires `--true-min`.
+    #[arg(long, requires = "true_min", allow_negative_numbers = true)]
+    true_max: Option<f32>,
+
     /// Number of fractional digits in the generated values
     #[arg(short, long, default_value_t = 1)]
     fractional_digit: u8,
 
+    /// Weights the last fractional digit like rounded sensor data.
+    ///
+    /// Only applies to values in `[--min, --max]`, not to `--true-min`,
+    /// `--true-max` or pinned cities.
+    #[arg(long, value_enum, default_value_t)]
+    fraction_bias: FractionBias,
+
+    /// Shift the values from the low end of the range at the start of the output
+    /// to the high end at its end, like seasonal drift.
+    ///
+    /// Must be between 0 and 1. The mean moves linearly over the rows by up to a
+    /// sixth of the range in either direction, but every value stays in range.
+    /// Does not apply to pinned cities.
+    #[arg(long, value_name = "AMOUNT", default_value_t = 0.0)]
+    drift: f64,
+
     /// The output filename. Default is data.txt
+    ///
+    /// A name ending in `.zst` writes zstd compressed output, which needs the
+    /// `compression` feature. The printed size and hash are of the uncompressed rows.
     #[arg(short, long)]
     output: Option<PathBuf>,
 
+    /// Write the rows round-robin to these files instead of a single output, e.g. one
+    /// file on every disk, so they are written in parallel.
+    ///
+    /// The rows are written in blocks of whole lines of at least `--output-block-size`
+    /// bytes, so every file can be processed on its own. The expected result covers
+    /// the rows of all files and the printed hash is the hash of all rows in the order
+    /// they were generated. Only supported for text output.
+    #[arg(
+        long,
+        value_name = "PATHS",
+        value_delimiter = ',',
+        conflicts_with_all = ["output", "checkpoint", "repeat", "index_output", "from_result"]
+    )]
+    output_paths: Vec<PathBuf>,
+
+    /// The size of the blocks of `--output-paths` in bytes
+    #[arg(
+        long,
+        default_value_t = 1024 * 1024,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "output_paths"
+    )]
+    output_block_size: u64,
+
     /// The output filename for the expected result of the 1 Billion Row challenge
     /// given the data created.
     ///
@@ -66,12 +342,201 @@ struct Args {
     #[arg(short, long)]
     result_output: Option<PathBuf>,
 
+    /// The format of the expected result
+    #[arg(long, value_enum, default_value_t)]
+    result_format: ResultFormat,
+
+    /// Only count the rows of every city for the expected result.
+    ///
+    /// The result is written as `{city=count, ...}`, or a json object mapping each
+    /// city to its count, which shows the distribution of the rows over the cities
+    /// without tracking the min, mean and max.
+    #[arg(long, requires = "result_output")]
+    result_count_only: bool,
+
     /// A number of predefined arguments for easy data generation
     ///
     /// This will override all arguments except for the output files.
     /// It will however change the default for the output files.
     #[arg(short, long, value_enum)]
     preset: Option<ArgPreset>,
+
+    /// A file used to cache the generated city names between runs.
+    ///
+    /// If the file exists and was created with the same city count and length
+    /// the cities are loaded from it, otherwise they are generated and written
+    /// to the file.
+    #[arg(long)]
+    cities_cache: Option<PathBuf>,
+
+    /// The line ending used for the generated rows
+    #[arg(long, value_enum, default_value_t)]
+    line_ending: LineEnding,
+
+    /// Leave out the line ending after the last row, like many hand edited files.
+    ///
+    /// The last row is never malformed. Only used for the text format.
+    #[arg(long, conflicts_with_all = ["target_size", "repeat"])]
+    no_final_newline: bool,
+
+    /// The order of the city and the value in the generated rows.
+    ///
+    /// Only used for the text format.
+    #[arg(long, value_enum, default_value_t)]
+    column_order: ColumnOrder,
+
+    /// The format of the generated rows.
+    ///
+    /// The binary format supports at most 1 fractional digit.
+    #[arg(long, value_enum, default_value_t)]
+    output_format: OutputFormat,
+
+    /// A file to periodically store the progress of the generator in.
+    ///
+    /// The file is removed once the generator finishes.
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// The number of rows between writing checkpoints
+    #[arg(long, default_value = "10000000")]
+    checkpoint_interval: NonZeroUsize,
+
+    /// Write the byte offset of every `--index-interval`th row of the output to this file.
+    ///
+    /// The file starts with a line `interval N`, followed by one offset per line,
+    /// the first being 0. The output can be split at these offsets into ranges of
+    /// whole rows, e.g. for the `--offset` and `--length` of the solver, without
+    /// searching for line starts.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["checkpoint", "repeat", "from_result"]
+    )]
+    index_output: Option<PathBuf>,
+
+    /// The number of rows between the offsets of `--index-output`
+    #[arg(
+        long,
+        default_value_t = 1_000_000,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "index_output"
+    )]
+    index_interval: u64,
+
+    /// Write the generated rows this many times.
+    ///
+    /// The rows are only generated once and then copied, which is a lot faster for
+    /// huge outputs. The expected result is computed from the first copy.
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["target_size", "checkpoint"]
+    )]
+    repeat: u64,
+
+    /// A city that is always part of the generated data, with values in `[MIN, MAX]`.
+    ///
+    /// Can be used multiple times. Pinned cities are picked instead of a random
+    /// city with a probability of `--pin-rate`.
+    #[arg(
+        long = "pin-city",
+        value_name = "NAME:MIN:MAX",
+        allow_hyphen_values = true
+    )]
+    pinned_cities: Vec<PinnedCity>,
+
+    /// The probability of generating a row for one of the pinned cities
+    #[arg(long, default_value_t = 0.01)]
+    pin_rate: f64,
+
+    /// Generate rows that reproduce the statistics of every city in a result file
+    /// in the challenge format, instead of random rows.
+    ///
+    /// Every city gets its exact min and max and a mean as close to the target
+    /// as `--fractional-digit` allows.
+    #[arg(
+        long,
+        value_name = "RESULT",
+        conflicts_with_all = ["preset", "target_size", "checkpoint", "repeat", "pinned_cities", "drift"]
+    )]
+    from_result: Option<PathBuf>,
+
+    /// The number of rows generated for every city with `--from-result`
+    #[arg(
+        long,
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u64).range(2..),
+        requires = "from_result"
+    )]
+    rows_per_city: u64,
+
+    /// Remove duplicate city names with a warning instead of failing.
+    ///
+    /// Duplicates can come from a hand edited `--cities-cache`, repeated
+    /// `--pin-city` names or `--from-result`. Only the first city of a name is kept.
+    #[arg(long)]
+    warn_on_duplicate_city: bool,
+
+    /// The seed of the random generator, a random one is used and printed if not set.
+    ///
+    /// The same seed and arguments always generate the same output.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Generate only shard INDEX of a data set that is split into multiple files.
+    ///
+    /// All shards of a `--seed` share the same cities, but their rows come from
+    /// independent streams of the random generator. So every shard can be
+    /// regenerated on its own, with or without a `--cities-cache`, and the
+    /// concatenation of all shards of a seed is deterministic as well. Shard 0 is
+    /// the output without `--shard`.
+    #[arg(
+        long,
+        value_name = "INDEX",
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u64).range(..u64::MAX),
+        requires = "seed"
+    )]
+    shard: u64,
+
+    /// The fraction of rows that are replaced by a malformed line.
+    ///
+    /// Malformed lines are not part of the expected result, as the solver skips them
+    /// with `--lenient`, but they count towards `--line-count`. Only supported for
+    /// text output.
+    #[arg(long, value_name = "FRACTION", default_value_t = 0.0)]
+    malform_rate: f64,
+
+    /// The kinds of malformed lines, separated by `,`. Every malformed line is one
+    /// of them at random.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = MalformKind::value_variants().to_vec(),
+        requires = "malform_rate"
+    )]
+    malform_kinds: Vec<MalformKind>,
+
+    /// Write all rows of a city before the rows of the next city, with the cities
+    /// sorted by name.
+    ///
+    /// The number of rows of every city is drawn up front, so the cities have as
+    /// many rows as in a shuffled output, but the rows come in long runs of the
+    /// same city. The values within a city are still random.
+    #[arg(
+        long,
+        conflicts_with_all = ["target_size", "checkpoint", "repeat", "pinned_cities"]
+    )]
+    sort_by_city: bool,
+
+    /// Continue an interrupted run from the checkpoint file.
+    ///
+    /// This must be called with the same arguments as the interrupted run and
+    /// produces exactly the same output as an uninterrupted run.
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
 }
 
 impl ArgPreset {
@@ -80,6 +545,7 @@ impl ArgPreset {
             ArgPreset::Full => "data/all_cities.txt",
             ArgPreset::Cities400 => "data/citeis_400.txt",
             ArgPreset::Test => "data/test.txt",
+            ArgPreset::Official => "data/official.txt",
         })
     }
 
@@ -88,6 +554,7 @@ impl ArgPreset {
             ArgPreset::Full => "data/all_cities_res.txt",
             ArgPreset::Cities400 => "data/citeis_400_res.txt",
             ArgPreset::Test => "data/test_res.txt",
+            ArgPreset::Official => "data/official_res.txt",
         })
     }
 
@@ -96,6 +563,7 @@ impl ArgPreset {
             ArgPreset::Full => 10_000,
             ArgPreset::Cities400 => 400,
             ArgPreset::Test => 10,
+            ArgPreset::Official => 413,
         }
     }
 
@@ -103,11 +571,21 @@ impl ArgPreset {
         5
     }
 
+    fn city_len_p(&self) -> f64 {
+        0.3
+    }
+
+    /// Whether the cities are the weather stations of the challenge
+    fn stations(&self) -> bool {
+        matches!(self, ArgPreset::Official)
+    }
+
     fn line_count(&self) -> usize {
         match self {
             ArgPreset::Full => 1_000_000_000,
             ArgPreset::Cities400 => 1_000_000_000,
             ArgPreset::Test => 1_000,
+            ArgPreset::Official => 1_000_000_000,
         }
     }
 
@@ -127,154 +605,907 @@ impl ArgPreset {
 fn main() -> Result<()> {
     let mut args = Args::parse();
 
-    match args.preset {
-        Some(preset) => {
-            args.output
-                .get_or_insert(preset.output())
-                .parent()
-                .map(|parent| create_dir_all(parent).context("Could not create output parent dir"));
+    if let Some(preset) = args.preset {
+        args.output
+            .get_or_insert(preset.output())
+            .parent()
+            .map(|parent| create_dir_all(parent).context("Could not create output parent dir"));
 
-            args.result_output
-                .get_or_insert(preset.result_output())
-                .parent()
-                .map(|parent| create_dir_all(parent).context("Could not create output parent dir"));
+        args.result_output
+            .get_or_insert(preset.result_output())
+            .parent()
+            .map(|parent| create_dir_all(parent).context("Could not create output parent dir"));
 
-            args.city_count = preset.city_count();
-            args.city_len = preset.city_len();
-            args.line_count = preset.line_count();
-            args.min_value = preset.min_value();
-            args.max_value = preset.max_value();
-            args.fractional_digit = preset.fractional_digit();
+        args.city_count = preset.city_count();
+        args.city_len = preset.city_len();
+        args.city_len_p = preset.city_len_p();
+        args.line_count = preset.line_count();
+        args.min_value = preset.min_value();
+        args.max_value = preset.max_value();
+        args.fractional_digit = preset.fractional_digit();
+    }
+
+    ensure!(
+        args.city_len_p > 0.0 && args.city_len_p < 1.0,
+        "--city-len-p must be between 0 and 1 (exclusive)"
+    );
+    ensure!(
+        (0.0..=1.0).contains(&args.pin_rate),
+        "--pin-rate must be between 0 and 1"
+    );
+    ensure!(
+        (0.0..=1.0).contains(&args.drift),
+        "--drift must be between 0 and 1"
+    );
+    ensure!(
+        args.output_format != OutputFormat::Binary || args.fractional_digit <= 1,
+        "the binary format supports at most 1 fractional digit"
+    );
+    ensure!(
+        (0.0..=1.0).contains(&args.malform_rate),
+        "--malform-rate must be between 0 and 1"
+    );
+    ensure!(
+        args.malform_rate == 0.0 || args.output_format == OutputFormat::Text,
+        "--malform-rate is only supported for text output"
+    );
+    ensure!(
+        args.output_paths.is_empty() || args.output_format == OutputFormat::Text,
+        "--output-paths is only supported for text output"
+    );
+    ensure!(
+        !args.no_final_newline || args.output_format == OutputFormat::Text,
+        "--no-final-newline is only supported for text output"
+    );
+
+    if args.output.as_deref().is_some_and(is_zstd) {
+        ensure!(
+            cfg!(feature = "compression"),
+            "writing .zst output needs the generator to be built with `--features compression`"
+        );
+        // both read back or cut the output, which only works uncompressed
+        ensure!(
+            args.checkpoint.is_none() && args.repeat == 1,
+            "--checkpoint and --repeat are not supported for .zst output"
+        );
+        ensure!(
+            args.index_output.is_none(),
+            "--index-output is not supported for .zst output, the offsets would be of the uncompressed rows"
+        );
+    }
+
+    let warn = args.warn_on_duplicate_city;
+    dedup_cities(
+        &mut args.pinned_cities,
+        |city| &city.name,
+        "--pin-city",
+        warn,
+    )?;
+
+    if let Some(result_path) = args.from_result.as_deref() {
+        return generate_from_result(&args, result_path);
+    }
+
+    let (checkpoint, checkpoint_results) = match args.checkpoint.as_deref() {
+        Some(path) if args.resume => {
+            let (checkpoint, results) = Checkpoint::read(path)?;
+            (Some(checkpoint), results)
+        }
+        _ => (None, None),
+    };
+
+    let seed = match checkpoint.as_ref() {
+        Some(checkpoint) => checkpoint.seed,
+        None => new_seed(&args),
+    };
+    // the cities come from stream 0 of their own generator, so the rows do not
+    // depend on how many draws the cities took, or whether they were cached
+    let mut city_rng = ChaCha12Rng::from_seed(seed);
+    let cities = match args.cities_cache.as_deref() {
+        _ if args.preset.is_some_and(|preset| preset.stations()) => stations::STATIONS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect(),
+        Some(cache) => load_or_generate_cities(
+            cache,
+            args.city_count,
+            args.city_len,
+            args.city_len_p,
+            args.name_structure,
+            args.name_alphabet.as_ref(),
+            &mut city_rng,
+        )?,
+        None => {
+            println!("generating cities ...");
+            generate_cities(
+                args.city_count,
+                args.city_len,
+                args.city_len_p,
+                args.name_structure,
+                args.name_alphabet.as_ref(),
+                &mut city_rng,
+            )?
         }
-        None => {}
+    };
+    let mut cities = cities.into_vec();
+    dedup_cities(&mut cities, |city| city, "the cities cache", warn)?;
+    // pinned cities only get values from their own range
+    let cities: Box<[String]> = cities
+        .into_iter()
+        .filter(|city| !args.pinned_cities.iter().any(|pinned| &pinned.name == city))
+        .collect();
+    // the rows of every shard start on their own stream after the one of the cities
+    let mut rng = ChaCha12Rng::from_seed(seed);
+    rng.set_stream(args.shard + 1);
+    if let Some(checkpoint) = checkpoint.as_ref() {
+        rng.set_word_pos(checkpoint.word_pos);
     }
 
-    let mut rng = rand::rngs::StdRng::from_entropy();
-    println!("generating cities ...");
-    let cities = generate_cities(args.city_count, args.city_len, &mut rng);
+    let line_count = match args.target_size {
+        Some(target_size) => {
+            let estimate = estimate_line_count(&args, &cities, target_size)?;
+            println!("generating about {estimate} rows for {target_size} bytes");
+            estimate
+        }
+        None => args.line_count,
+    };
 
-    let generator = Generator::new(
-        &cities,
-        args.min_value,
-        args.max_value,
-        args.fractional_digit,
-        rng,
-    );
+    let mut generator = new_generator(&args, &cities, rng)?.with_drift(args.drift, line_count);
+    if args.sort_by_city {
+        generator = generator.with_sorted_cities(line_count);
+    }
+    if let Some(checkpoint) = checkpoint.as_ref() {
+        generator.index = checkpoint.rows;
+    }
 
-    let file = File::create(args.output.unwrap_or_else(|| PathBuf::from("data.txt")))
-        .context("failed to create output file")?;
-    let mut writer = BufWriter::new(file);
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("data.txt"));
+    let file = match checkpoint.as_ref() {
+        _ if !args.output_paths.is_empty() => {
+            let block_size = args.output_block_size as usize;
+            OutputFile::RoundRobin(RoundRobinWriter::create(&args.output_paths, block_size)?)
+        }
+        Some(checkpoint) => {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .open(&output)
+                .context("failed to open output file to resume")?;
+            // drop everything written after the checkpoint
+            file.set_len(checkpoint.bytes)
+                .context("failed to truncate output file")?;
+            file.seek(SeekFrom::End(0))
+                .context("failed to seek to end of output file")?;
+            OutputFile::Single(BufWriter::new(file))
+        }
+        None => OutputFile::create(&output)?,
+    };
+    let (start_bytes, start_hash) = checkpoint.as_ref().map_or((0, FNV_OFFSET), |checkpoint| {
+        (checkpoint.bytes, checkpoint.hash)
+    });
+    let mut writer = CountingWriter::new(file, start_bytes, start_hash);
+    let mut index = args
+        .index_output
+        .as_deref()
+        .map(|path| IndexWriter::create(path, args.index_interval))
+        .transpose()?;
 
-    let mut results = if args.result_output.is_some() {
-        Some(HashMap::<String, CityResult>::with_capacity(
+    let mut results = match (args.result_output.is_some(), checkpoint_results) {
+        (true, Some(results)) => Some(results),
+        (true, None) if checkpoint.is_some() => {
+            bail!("the checkpoint does not contain the partial expected result")
+        }
+        (true, None) => Some(HashMap::<String, CityResult>::with_capacity(
             args.city_count,
-        ))
-    } else {
-        None
+        )),
+        (false, _) => None,
     };
     println!("generating rows...");
 
+    let start = Instant::now();
+    let start_rows = checkpoint.as_ref().map_or(0, |checkpoint| checkpoint.rows);
+    let mut i = start_rows;
     let term = Term::stdout();
-    for (i, row) in generator.take(args.line_count).enumerate() {
-        if i % 10_000 == 0 && args.line_count > 10_000_000 {
+    loop {
+        let done = match args.target_size {
+            Some(target_size) => writer.bytes >= target_size,
+            None => i >= args.line_count,
+        };
+        if done {
+            break;
+        }
+
+        let row = generator.next().context("no cities to generate rows for")?;
+        if i % 10_000 == 0 && line_count > 10_000_000 {
             let _ = term.clear_last_lines(1);
-            println!("generating rows {}/{}", i, args.line_count);
+            println!("generating rows {}/{}", i, line_count);
         }
+        // only draw from the rng with a malform rate, so the output for a seed does
+        // not change without it
+        let malformed = (args.malform_rate > 0.0 && generator.rng.gen_bool(args.malform_rate))
+            .then(|| args.malform_kinds.choose(&mut generator.rng))
+            .flatten();
+        let last = args.no_final_newline && i + 1 == args.line_count;
+        let line_ending = match last {
+            true => "",
+            false => args.line_ending.as_str(),
+        };
 
-        if let Some(results) = results.as_mut() {
-            if let Some(old) = results.get_mut(row.city) {
-                let value = row.value();
+        if let Some(index) = index.as_mut() {
+            index.line_start(i as u64, writer.bytes)?;
+        }
+        match malformed.filter(|_| !last) {
+            Some(&kind) => write_malformed(&mut writer, &row, kind, &args, &mut generator.rng)?,
+            None => {
+                if let Some(results) = results.as_mut() {
+                    add_to_results(results, &row, args.result_count_only);
+                }
+                write_row(&mut writer, &row, &args, line_ending)?;
+            }
+        }
+        i += 1;
 
-                old.count += 1;
-                old.total += value;
+        if let Some(checkpoint_path) = args.checkpoint.as_deref() {
+            if i % args.checkpoint_interval.get() == 0 {
+                writer.flush().context("failed to write data")?;
+                let checkpoint = Checkpoint {
+                    seed,
+                    word_pos: generator.rng.get_word_pos(),
+                    rows: i,
+                    bytes: writer.bytes,
+                    hash: writer.hash,
+                };
+                checkpoint.write(checkpoint_path, results.as_ref())?;
+            }
+        }
+    }
+    writer.flush().context("failed to write data")?;
+    if let Some(index) = index {
+        index.finish()?;
+    }
 
-                if old.min > value {
-                    old.min = value;
-                }
-                if old.max < value {
-                    old.max = value
-                }
-            } else {
-                results.entry(row.city.to_owned()).or_insert_with(|| {
-                    let value = row.value();
-                    CityResult {
-                        name: row.city.to_owned(),
-                        count: 1,
-                        total: value,
-                        min: value,
-                        max: value,
-                    }
-                });
+    if args.repeat > 1 {
+        println!("repeating rows {} times", args.repeat);
+        let block_bytes = writer.bytes;
+        for _ in 1..args.repeat {
+            let mut block = File::open(&output)
+                .context("failed to open output file to repeat it")?
+                .take(block_bytes);
+            copy(&mut block, &mut writer).context("failed to repeat data")?;
+        }
+        writer.flush().context("failed to write data")?;
+
+        if let Some(results) = results.as_mut() {
+            for result in results.values_mut() {
+                result.count *= args.repeat as usize;
+                result.total *= args.repeat as i128;
             }
         }
+        i *= args.repeat as usize;
+    }
 
-        writeln!(&mut writer, "{row}").context("failed to write data")?;
+    println!("generated {i} rows, {} bytes", writer.bytes);
+    println!("fnv-1a hash of the output: {:016x}", writer.hash);
+    // a resumed run only wrote the rows after the checkpoint
+    eprintln!(
+        "{}",
+        throughput_summary(i - start_rows, writer.bytes - start_bytes, start.elapsed())
+    );
+    writer.inner.finish()?;
+
+    if let Some(checkpoint_path) = args.checkpoint.as_deref() {
+        if checkpoint_path.exists() {
+            remove_file(checkpoint_path).context("failed to remove checkpoint file")?;
+        }
     }
-    drop(writer);
 
-    if let Some(result_file) = args.result_output {
+    if let Some(result_file) = args.result_output.as_deref() {
         println!("calculating result data");
-        let file = File::create(result_file).context("failed to create result output fiel")?;
-        let mut result_file = BufWriter::new(file);
-        write!(result_file, "{{").context("failed to write result file")?;
-
         let results = results.unwrap();
 
         let mut sorted = cities.into_vec();
+        sorted.extend(args.pinned_cities.iter().map(|pinned| pinned.name.clone()));
         sorted.sort_unstable();
-        let mut first = true;
-        for city in sorted.iter().filter_map(|name| results.get(name.as_str())) {
-            if !first {
-                write!(result_file, ", ").context("failed to write result file")?;
-            } else {
-                first = false;
-            }
-            write!(
-                result_file,
-                "{}={:.4$}/{:.4$}/{:.4$}",
-                city.name,
-                city.min,
-                city.total / city.count as f32,
-                city.max,
-                args.fractional_digit as usize
-            )
-            .context("failed to write result file")?;
+        let sorted: Vec<_> = sorted
+            .iter()
+            .filter_map(|name| results.get(name.as_str()))
+            .collect();
+        write_result_file(result_file, &sorted, &args)?;
+    }
+
+    println!("done");
+    Ok(())
+}
+
+/// The seed of the random generator from `--seed`, or a random one that is printed.
+fn new_seed(args: &Args) -> <ChaCha12Rng as SeedableRng>::Seed {
+    let seed = args.seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        println!("using seed {seed}");
+        seed
+    });
+    ChaCha12Rng::seed_from_u64(seed).get_seed()
+}
+
+/// Generates the rows for `--from-result`.
+///
+/// Each city gets its min, its max and values in between that bring the mean as
+/// close to the target as possible. The rows of all cities are shuffled.
+fn generate_from_result(args: &Args, result_path: &Path) -> Result<()> {
+    let text = read_to_string(result_path).context("failed to read result file")?;
+    let mut targets = from_result::parse_result(&text)
+        .with_context(|| format!("invalid result file {}", result_path.display()))?;
+    let warn = args.warn_on_duplicate_city;
+    dedup_cities(&mut targets, |target| &target.name, "the result file", warn)?;
+
+    let scale = 10f64.powi(args.fractional_digit.into());
+    let rows_per_city = args.rows_per_city as usize;
+    let mut rows = Vec::with_capacity(targets.len() * rows_per_city);
+    for target in &targets {
+        let min = (target.min as f64 * scale).round() as i64;
+        let max = (target.max as f64 * scale).round() as i64;
+        let mean = target.mean as f64 * scale;
+        for value in from_result::scaled_values(min, mean, max, rows_per_city) {
+            rows.push(Row::scaled(&target.name, value, args.fractional_digit));
         }
-        write!(result_file, "}}").context("failed to write result file")?;
+    }
+    rows.shuffle(&mut ChaCha12Rng::from_seed(new_seed(args)));
+
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("data.txt"));
+    let file = OutputFile::create(&output)?;
+    let start = Instant::now();
+    let mut writer = CountingWriter::new(file, 0, FNV_OFFSET);
+    let mut results = HashMap::with_capacity(targets.len());
+    for (i, row) in rows.iter().enumerate() {
+        add_to_results(&mut results, row, args.result_count_only);
+        let line_ending = match args.no_final_newline && i + 1 == rows.len() {
+            true => "",
+            false => args.line_ending.as_str(),
+        };
+        write_row(&mut writer, row, args, line_ending)?;
+    }
+    writer.flush().context("failed to write data")?;
+    println!("generated {} rows, {} bytes", rows.len(), writer.bytes);
+    println!("fnv-1a hash of the output: {:016x}", writer.hash);
+    eprintln!(
+        "{}",
+        throughput_summary(rows.len(), writer.bytes, start.elapsed())
+    );
+    writer.inner.finish()?;
+
+    if let Some(result_file) = args.result_output.as_deref() {
+        let mut sorted: Vec<_> = results.values().collect();
+        sorted.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        write_result_file(result_file, &sorted, args)?;
     }
 
     println!("done");
     Ok(())
 }
 
+/// Removes all but the first city of every name from `cities`.
+///
+/// Two cities with the same name would be merged in the expected result, so
+/// duplicates are an error unless `warn` is set.
+fn dedup_cities<T>(
+    cities: &mut Vec<T>,
+    name: impl Fn(&T) -> &String,
+    source: &str,
+    warn: bool,
+) -> Result<()> {
+    let mut seen = HashSet::with_capacity(cities.len());
+    let mut duplicates = Vec::new();
+    for city in cities.iter() {
+        if !seen.insert(name(city)) {
+            duplicates.push(name(city).clone());
+        }
+    }
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+
+    ensure!(
+        warn,
+        "{source} contains duplicate cities: {duplicates:?}, use --warn-on-duplicate-city to skip them"
+    );
+    eprintln!("warning: skipping duplicate cities in {source}: {duplicates:?}");
+    let mut seen = HashSet::with_capacity(cities.len());
+    cities.retain(|city| seen.insert(name(city).clone()));
+    Ok(())
+}
+
+/// Adds `row` to the expected result. With `count_only` only the count of the city
+/// is updated and its values stay 0, see `--result-count-only`.
+fn add_to_results(results: &mut HashMap<String, CityResult>, row: &Row, count_only: bool) {
+    let value = if count_only { 0 } else { row.scaled_value() };
+    if let Some(old) = results.get_mut(row.city) {
+        old.count += 1;
+        if count_only {
+            return;
+        }
+        old.total += value as i128;
+
+        if old.min > value {
+            old.min = value;
+        }
+        if old.max < value {
+            old.max = value
+        }
+    } else {
+        results.insert(
+            row.city.to_owned(),
+            CityResult {
+                name: row.city.to_owned(),
+                count: 1,
+                total: value as i128,
+                min: value,
+                max: value,
+            },
+        );
+    }
+}
+
+fn write_result_file(path: &Path, sorted: &[&CityResult], args: &Args) -> Result<()> {
+    let file = File::create(path).context("failed to create result output fiel")?;
+    let mut result_file = BufWriter::new(file);
+
+    let fractional_digit = args.fractional_digit as usize;
+    match (args.result_format, args.result_count_only) {
+        (ResultFormat::Text, false) => {
+            write_text_result(&mut result_file, sorted, fractional_digit)
+        }
+        (ResultFormat::Json, false) => {
+            write_json_result(&mut result_file, sorted, fractional_digit)
+        }
+        (format, true) => write_count_result(&mut result_file, sorted, format),
+    }
+    .and_then(|_| result_file.flush())
+    .context("failed to write result file")
+}
+
+fn new_generator<'a, R>(args: &'a Args, cities: &'a [String], rng: R) -> Result<Generator<'a, R>> {
+    let generator = Generator::new(
+        cities,
+        args.min_value,
+        args.max_value,
+        args.fractional_digit,
+        rng,
+    );
+    let generator = generator
+        .with_pinned_cities(&args.pinned_cities, args.pin_rate)
+        .with_fraction_bias(args.fraction_bias);
+    let generator = match args.preset.is_some_and(|preset| preset.stations()) {
+        true => generator.with_stations()?,
+        false => generator,
+    };
+    match (args.true_min, args.true_max) {
+        (Some(true_min), Some(true_max)) => {
+            ensure!(
+                true_min <= true_max,
+                "--true-min must not be larger than --true-max"
+            );
+            Ok(generator.with_exact_range(true_min, true_max))
+        }
+        _ => Ok(generator),
+    }
+}
+
+/// Writes `row` as a malformed line of `kind`, see `--malform-rate`.
+fn write_malformed<W: Write, R: Rng>(
+    writer: &mut W,
+    row: &Row,
+    kind: MalformKind,
+    args: &Args,
+    rng: &mut R,
+) -> Result<()> {
+    let value = match kind {
+        MalformKind::NonNumeric => "n/a".to_string(),
+        MalformKind::OutOfRange => {
+            let scale = 10i64.pow(row.fraction_digits as u32);
+            let scaled = rng.gen_range(100 * scale..1000 * scale);
+            let scaled = if row.negative { -scaled } else { scaled };
+            RowValue(&Row::scaled(row.city, scaled, row.fraction_digits as u8)).to_string()
+        }
+        _ => RowValue(row).to_string(),
+    };
+    let (first, second) = match args.column_order {
+        ColumnOrder::CityValue => (row.city, value.as_str()),
+        ColumnOrder::ValueCity => (value.as_str(), row.city),
+    };
+    let line_ending = args.line_ending.as_str();
+    match kind {
+        MalformKind::MissingSeparator => write!(writer, "{first}{second}{line_ending}"),
+        MalformKind::NonNumeric | MalformKind::OutOfRange => {
+            write!(writer, "{first};{second}{line_ending}")
+        }
+        MalformKind::ExtraField => write!(writer, "{first};{second};{value}{line_ending}"),
+        MalformKind::EmptyLine => write!(writer, "{line_ending}"),
+    }
+    .context("failed to write data")
+}
+
+/// How fast `rows` rows of `bytes` bytes were written in `elapsed`
+fn throughput_summary(rows: usize, bytes: u64, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64().max(f64::MIN_POSITIVE);
+    format!(
+        "wrote {rows} rows, {bytes} bytes in {elapsed:.2?}: {:.0} rows/s, {:.1} MB/s",
+        rows as f64 / seconds,
+        bytes as f64 / 1_000_000.0 / seconds
+    )
+}
+
+/// Writes `row` in the output format, text rows end in `line_ending`.
+fn write_row<W: Write>(writer: &mut W, row: &Row, args: &Args, line_ending: &str) -> Result<()> {
+    match args.output_format {
+        OutputFormat::Text => match args.column_order {
+            ColumnOrder::CityValue => write!(writer, "{row}{line_ending}"),
+            ColumnOrder::ValueCity => {
+                write!(writer, "{};{}{line_ending}", RowValue(row), row.city)
+            }
+        }
+        .context("failed to write data"),
+        OutputFormat::Binary => row.write_binary(writer),
+    }
+}
+
+/// Estimates the number of rows needed to fill `target_size` bytes.
+///
+/// The average row length is measured on a sample of rows from a separate rng,
+/// so the estimate does not change the generated data.
+fn estimate_line_count(args: &Args, cities: &[String], target_size: u64) -> Result<usize> {
+    const SAMPLE_SIZE: usize = 10_000;
+
+    let mut sample = CountingWriter::new(std::io::sink(), 0, FNV_OFFSET);
+    let generator = new_generator(args, cities, ChaCha12Rng::seed_from_u64(0))?;
+    for row in generator.take(SAMPLE_SIZE) {
+        write_row(&mut sample, &row, args, args.line_ending.as_str())?;
+    }
+    let average_len = sample.bytes as f64 / SAMPLE_SIZE as f64;
+
+    Ok((target_size as f64 / average_len).ceil() as usize)
+}
+
+/// The offset basis of the 64 bit FNV-1a hash
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// A writer that counts and hashes the bytes written through it.
+///
+/// The hash is the 64 bit FNV-1a hash of all bytes, the same the solver prints
+/// with `--hash-input`.
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+    hash: u64,
+}
+
+impl<W> CountingWriter<W> {
+    /// A writer that continues after `bytes` bytes that were hashed to `hash`.
+    fn new(inner: W, bytes: u64, hash: u64) -> Self {
+        Self { inner, bytes, hash }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        for &byte in &buf[..written] {
+            self.hash = (self.hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The file or files the rows are written to
+enum OutputFile {
+    Single(BufWriter<File>),
+    RoundRobin(RoundRobinWriter),
+    #[cfg(feature = "compression")]
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl OutputFile {
+    /// Creates the file at `path`, which is zstd compressed if the name ends in `.zst`.
+    fn create(path: &Path) -> Result<Self> {
+        let file = BufWriter::new(File::create(path).context("failed to create output file")?);
+        if !is_zstd(path) {
+            return Ok(OutputFile::Single(file));
+        }
+        #[cfg(feature = "compression")]
+        return Ok(OutputFile::Zstd(
+            zstd::Encoder::new(file, 0).context("failed to start zstd compression")?,
+        ));
+        #[cfg(not(feature = "compression"))]
+        bail!("writing .zst output needs the generator to be built with `--features compression`")
+    }
+
+    /// Writes the rest of the output and, for `--output-paths`, waits until every
+    /// file is written.
+    fn finish(self) -> Result<()> {
+        match self {
+            OutputFile::Single(mut writer) => writer.flush().context("failed to write data"),
+            OutputFile::RoundRobin(writer) => writer.finish(),
+            #[cfg(feature = "compression")]
+            OutputFile::Zstd(encoder) => encoder
+                .finish()
+                .and_then(|mut writer| writer.flush())
+                .context("failed to write data"),
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            OutputFile::Single(writer) => writer.write(buf),
+            OutputFile::RoundRobin(writer) => writer.write(buf),
+            #[cfg(feature = "compression")]
+            OutputFile::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            OutputFile::Single(writer) => writer.flush(),
+            OutputFile::RoundRobin(writer) => writer.flush(),
+            #[cfg(feature = "compression")]
+            OutputFile::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Whether the output at `path` is zstd compressed
+fn is_zstd(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "zst")
+}
+
+fn write_text_result<W: Write>(
+    writer: &mut W,
+    sorted: &[&CityResult],
+    fractional_digit: usize,
+) -> std::io::Result<()> {
+    write!(writer, "{{")?;
+    let mut first = true;
+    for city in sorted {
+        if !first {
+            write!(writer, ", ")?;
+        } else {
+            first = false;
+        }
+        let (min, mean, max) = city.stats(fractional_digit);
+        write!(
+            writer,
+            "{}={:.4$}/{:.4$}/{:.4$}",
+            city.name, min, mean, max, fractional_digit
+        )?;
+    }
+    write!(writer, "}}")
+}
+
+fn write_json_result<W: Write>(
+    writer: &mut W,
+    sorted: &[&CityResult],
+    fractional_digit: usize,
+) -> std::io::Result<()> {
+    write!(writer, "{{")?;
+    let mut first = true;
+    for city in sorted {
+        if !first {
+            write!(writer, ",")?;
+        } else {
+            first = false;
+        }
+        write_json_string(writer, &city.name)?;
+        let (min, mean, max) = city.stats(fractional_digit);
+        write!(
+            writer,
+            ":{{\"min\":{:.3$},\"mean\":{:.3$},\"max\":{:.3$}}}",
+            min, mean, max, fractional_digit
+        )?;
+    }
+    write!(writer, "}}")
+}
+
+/// Writes only the number of rows of every city, see `--result-count-only`.
+fn write_count_result<W: Write>(
+    writer: &mut W,
+    sorted: &[&CityResult],
+    format: ResultFormat,
+) -> std::io::Result<()> {
+    write!(writer, "{{")?;
+    for (i, city) in sorted.iter().enumerate() {
+        match format {
+            ResultFormat::Text => {
+                if i > 0 {
+                    write!(writer, ", ")?;
+                }
+                write!(writer, "{}={}", city.name, city.count)?;
+            }
+            ResultFormat::Json => {
+                if i > 0 {
+                    write!(writer, ",")?;
+                }
+                write_json_string(writer, &city.name)?;
+                write!(writer, ":{}", city.count)?;
+            }
+        }
+    }
+    write!(writer, "}}")
+}
+
+fn write_json_string<W: Write>(writer: &mut W, value: &str) -> std::io::Result<()> {
+    write!(writer, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(writer, "\\\"")?,
+            '\\' => write!(writer, "\\\\")?,
+            c if c.is_control() => write!(writer, "\\u{:04x}", c as u32)?,
+            c => write!(writer, "{c}")?,
+        }
+    }
+    write!(writer, "\"")
+}
+
+/// The expected result of a city, with all values scaled by `10^fractional_digit`
+/// so that they are exact.
 #[derive(Debug)]
 struct CityResult {
     name: String,
     count: usize,
-    total: f32,
-    min: f32,
-    max: f32,
+    total: i128,
+    min: i64,
+    max: i64,
 }
 
-fn generate_city<R: Rng>(distribution: Binomial, rng: &mut R) -> String {
-    let len = distribution.sample(rng).min(100).max(1);
-    let result: String = rng
-        .sample_iter(Alphanumeric)
-        .map(char::from)
-        .take(len as usize)
-        .collect();
+impl CityResult {
+    /// The min, mean and max of the city, unscaled by `10^fractional_digit`
+    fn stats(&self, fractional_digit: usize) -> (f64, f64, f64) {
+        let scale = 10f64.powi(fractional_digit as i32);
+        (
+            self.min as f64 / scale,
+            self.total as f64 / self.count as f64 / scale,
+            self.max as f64 / scale,
+        )
+    }
+}
+
+/// The longest city name in bytes allowed by the challenge
+const MAX_CITY_LEN: usize = 100;
+
+/// The number of region prefixes of [NameStructure::Prefixed]
+const REGION_COUNT: usize = 16;
+
+/// The length of a region prefix including the `-` after it
+const REGION_PREFIX_LEN: usize = 3;
+
+/// Generates a random name, behind `region` followed by a `-` if it is set.
+///
+/// The name is made of ascii letters and digits, or of the chars of `alphabet`.
+fn generate_city<R: Rng>(
+    distribution: Binomial,
+    region: Option<&str>,
+    alphabet: Option<&NameAlphabet>,
+    rng: &mut R,
+) -> String {
+    let max_len = match region {
+        Some(_) => MAX_CITY_LEN - REGION_PREFIX_LEN,
+        None => MAX_CITY_LEN,
+    };
+    let len = distribution.sample(rng).clamp(1, max_len as u64);
+    let mut result = String::with_capacity(MAX_CITY_LEN);
+    if let Some(region) = region {
+        result.push_str(region);
+        result.push('-');
+    }
+    match alphabet {
+        None => result.extend(
+            rng.sample_iter(Alphanumeric)
+                .map(char::from)
+                .take(len as usize),
+        ),
+        Some(NameAlphabet(chars)) => {
+            for _ in 0..len {
+                let c = chars[rng.gen_range(0..chars.len())];
+                // multibyte chars can reach the limit before `len` chars
+                if result.len() + c.len_utf8() > MAX_CITY_LEN {
+                    break;
+                }
+                result.push(c);
+            }
+        }
+    }
 
-    assert!(result.bytes().len() <= 100);
+    assert!(result.len() <= MAX_CITY_LEN);
     result
 }
 
-fn generate_cities<R: Rng>(count: usize, city_len: usize, rng: &mut R) -> Box<[String]> {
+/// The sorted region prefixes of [NameStructure::Prefixed], two uppercase letters each.
+fn generate_regions<R: Rng>(rng: &mut R) -> Vec<String> {
+    let mut regions = Vec::with_capacity(REGION_COUNT);
+    while regions.len() < REGION_COUNT {
+        let region: String = (0..REGION_PREFIX_LEN - 1)
+            .map(|_| rng.gen_range(b'A'..=b'Z') as char)
+            .collect();
+        if !regions.contains(&region) {
+            regions.push(region);
+        }
+    }
+    regions.sort_unstable();
+    regions
+}
+
+/// The number of distinct names [generate_city] can produce for `city_len` from an
+/// alphabet of `alphabet_len` chars.
+///
+/// Names of multibyte chars can be shorter than `city_len`, so this is only an upper
+/// bound for them.
+fn max_city_names(city_len: usize, alphabet_len: u64) -> u64 {
+    (1..=city_len.clamp(1, MAX_CITY_LEN) as u32)
+        .map(|len| alphabet_len.saturating_pow(len))
+        .fold(0, u64::saturating_add)
+}
+
+/// The number of names in a row that may already exist before generating cities is aborted.
+///
+/// Long names are rare if `city_len_p` is small, so a count below [max_city_names] can
+/// still be unreachable in practice.
+const MAX_CITY_RETRIES: usize = 1_000_000;
+
+fn generate_cities<R: Rng>(
+    count: usize,
+    city_len: usize,
+    city_len_p: f64,
+    structure: NameStructure,
+    alphabet: Option<&NameAlphabet>,
+    rng: &mut R,
+) -> Result<Box<[String]>> {
+    let alphabet_len = alphabet.map_or(62, |alphabet| alphabet.0.len() as u64);
+    let max_names = match structure {
+        NameStructure::Flat => max_city_names(city_len, alphabet_len),
+        NameStructure::Prefixed => {
+            max_city_names(city_len.min(MAX_CITY_LEN - REGION_PREFIX_LEN), alphabet_len)
+                .saturating_mul(REGION_COUNT as u64)
+        }
+    };
+    ensure!(
+        count as u64 <= max_names,
+        "cannot generate {count} distinct cities with a city length of {city_len}, \
+         there are only {max_names} possible names"
+    );
+
     let mut cities = HashSet::with_capacity(count);
 
-    let name_len_dist = Binomial::new(city_len as u64, 0.3).unwrap();
+    let name_len_dist = Binomial::new(city_len as u64, city_len_p).unwrap();
+    let regions = match structure {
+        NameStructure::Flat => Vec::new(),
+        NameStructure::Prefixed => generate_regions(rng),
+    };
 
+    let mut retries = 0;
     while cities.len() != count {
-        cities.insert(generate_city(name_len_dist, rng));
+        let region = match regions.is_empty() {
+            true => None,
+            false => Some(regions[rng.gen_range(0..regions.len())].as_str()),
+        };
+        if cities.insert(generate_city(name_len_dist, region, alphabet, rng)) {
+            retries = 0;
+        } else {
+            retries += 1;
+            ensure!(
+                retries < MAX_CITY_RETRIES,
+                "only found {} of {count} distinct cities, increase --city-len or --city-len-p",
+                cities.len()
+            );
+        }
     }
 
     let mut result = Vec::with_capacity(count);
@@ -282,15 +1513,103 @@ fn generate_cities<R: Rng>(count: usize, city_len: usize, rng: &mut R) -> Box<[S
     for city in cities.into_iter() {
         result.push(city);
     }
+    // the iteration order of the set is random, so sort to get the same cities for the same seed
+    result.sort_unstable();
+
+    Ok(result.into())
+}
+
+fn cities_cache_header(
+    count: usize,
+    city_len: usize,
+    city_len_p: f64,
+    structure: NameStructure,
+    alphabet: Option<&NameAlphabet>,
+) -> String {
+    let header = format!(
+        "# city_count={count} city_len={city_len} city_len_p={city_len_p} name_structure={}",
+        structure.as_str()
+    );
+    // caches without an alphabet stay valid
+    match alphabet {
+        Some(alphabet) => format!("{header} name_alphabet={alphabet}"),
+        None => header,
+    }
+}
+
+fn load_or_generate_cities<R: Rng>(
+    cache: &Path,
+    count: usize,
+    city_len: usize,
+    city_len_p: f64,
+    structure: NameStructure,
+    alphabet: Option<&NameAlphabet>,
+    rng: &mut R,
+) -> Result<Box<[String]>> {
+    let header = cities_cache_header(count, city_len, city_len_p, structure, alphabet);
+
+    if cache.exists() {
+        let file = File::open(cache).context("failed to open cities cache")?;
+        let mut lines = BufReader::new(file).lines();
+        let cached_header = lines
+            .next()
+            .transpose()
+            .context("failed to read cities cache")?;
+
+        if cached_header.as_deref() == Some(header.as_str()) {
+            println!("loading cities from cache ...");
+            let cities = lines
+                .collect::<std::io::Result<Vec<String>>>()
+                .context("failed to read cities cache")?;
+            ensure!(
+                cities.len() == count,
+                "cities cache contains {} cities, expected {}",
+                cities.len(),
+                count
+            );
+            return Ok(cities.into());
+        }
+        println!("cities cache parameters do not match, regenerating");
+    }
+
+    println!("generating cities ...");
+    let cities = generate_cities(count, city_len, city_len_p, structure, alphabet, rng)?;
 
-    result.into()
+    let file = File::create(cache).context("failed to create cities cache")?;
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "{header}").context("failed to write cities cache")?;
+    for city in cities.iter() {
+        writeln!(writer, "{city}").context("failed to write cities cache")?;
+    }
+    writer.flush().context("failed to write cities cache")?;
+
+    Ok(cities)
 }
 
 struct Generator<'a, R> {
     cities: &'a [String],
     min: i32,
     max: i32,
+    fraction_digits: u8,
     fraction_max: usize,
+    /// The inclusive range of values scaled by `10^fraction_digits`.
+    ///
+    /// If this is set `min` and `max` are ignored.
+    exact_range: Option<(i64, i64)>,
+    /// Cities with their own inclusive range of values scaled by `10^fraction_digits`
+    pinned: Vec<(&'a str, i64, i64)>,
+    /// The probability of generating a row for a pinned city
+    pin_rate: f64,
+    /// The distribution of the last fractional digit, if it is not uniform
+    last_digit: Option<WeightedIndex<u32>>,
+    /// The `--drift` and the number of rows it is spread over
+    drift: Option<(f64, usize)>,
+    /// The cities with the distribution of their values, if they are weather stations
+    stations: Option<Vec<(&'a str, Normal<f64>)>>,
+    /// The planned rows of every city for `--sort-by-city`
+    sorted: Option<CityPlan>,
+    /// The number of rows generated so far
+    index: usize,
     rng: R,
 }
 
@@ -302,61 +1621,666 @@ impl<'a, R> Generator<'a, R> {
             cities,
             min,
             max,
+            fraction_digits,
             fraction_max,
+            exact_range: None,
+            pinned: Vec::new(),
+            pin_rate: 0.0,
+            last_digit: None,
+            drift: None,
+            stations: None,
+            sorted: None,
+            index: 0,
             rng,
         }
     }
+
+    /// Generate values in exactly `[min, max]`, including the fractional digits.
+    fn with_exact_range(mut self, min: f32, max: f32) -> Self {
+        self.exact_range = Some(self.scaled_range(min, max));
+        self
+    }
+
+    /// Generate a row for one of `pinned` with a probability of `rate`.
+    fn with_pinned_cities(mut self, pinned: &'a [PinnedCity], rate: f64) -> Self {
+        self.pinned = pinned
+            .iter()
+            .map(|city| {
+                let (min, max) = self.scaled_range(city.min, city.max);
+                (city.name.as_str(), min, max)
+            })
+            .collect();
+        self.pin_rate = rate;
+        self
+    }
+
+    /// Weights the last fractional digit by `bias`, if there are fractional digits.
+    fn with_fraction_bias(mut self, bias: FractionBias) -> Self {
+        self.last_digit = bias
+            .weights()
+            .filter(|_| self.fraction_max > 0)
+            .map(|weights| WeightedIndex::new(weights).expect("the weights are valid"));
+        self
+    }
+
+    /// Biases the values towards the low end of their range for the first rows and
+    /// towards the high end for the last of `line_count` rows by `amount`.
+    fn with_drift(mut self, amount: f64, line_count: usize) -> Self {
+        self.drift = (amount > 0.0).then_some((amount, line_count));
+        self
+    }
+
+    /// Draws the values of every city from a normal distribution around the mean
+    /// of its weather station, see [stations::STATIONS].
+    fn with_stations(mut self) -> Result<Self> {
+        let stations = self
+            .cities
+            .iter()
+            .map(|city| {
+                let mean = stations::mean(city)
+                    .with_context(|| format!("{city} is not a weather station"))?;
+                let normal =
+                    Normal::new(mean, stations::STD_DEV).expect("the standard deviation is valid");
+                Ok((city.as_str(), normal))
+            })
+            .collect::<Result<_>>()?;
+        self.stations = Some(stations);
+        Ok(self)
+    }
+
+    /// The smallest and the largest scaled value in `[min, max]` with all fractions
+    fn scaled_bounds(&self) -> (i64, i64) {
+        let scale = (self.fraction_max + 1) as i64;
+        let fraction = self.fraction_max as i64;
+        let min = self.min as i64 * scale - if self.min < 0 { fraction } else { 0 };
+        let max = self.max as i64 * scale + if self.max >= 0 { fraction } else { 0 };
+        (min, max)
+    }
+
+    fn scaled_range(&self, min: f32, max: f32) -> (i64, i64) {
+        let scale = (self.fraction_max + 1) as f64;
+        let min = (min as f64 * scale).round() as i64;
+        let max = (max as f64 * scale).round() as i64;
+        (min, max)
+    }
+}
+
+impl<'a, R: Rng> Generator<'a, R> {
+    /// Generates all rows of a city before the next city, in the order of their
+    /// names, for `line_count` rows.
+    ///
+    /// The number of rows of every city is drawn from a multinomial distribution,
+    /// like the counts of uniformly chosen cities.
+    fn with_sorted_cities(mut self, line_count: usize) -> Self {
+        let mut order: Vec<_> = (0..self.cities.len()).collect();
+        order.sort_unstable_by_key(|&city| &self.cities[city]);
+
+        let mut remaining = line_count as u64;
+        let mut counts = Vec::with_capacity(order.len());
+        for i in 0..order.len() {
+            // the share of each of the cities that are left
+            let p = 1.0 / (order.len() - i) as f64;
+            let count = Binomial::new(remaining, p)
+                .expect("the probability is valid")
+                .sample(&mut self.rng);
+            remaining -= count;
+            counts.push(count);
+        }
+        self.sorted = Some(CityPlan {
+            order,
+            counts,
+            current: 0,
+        });
+        self
+    }
+
+    /// Generates a row with a value in the scaled range `[min, max]`.
+    fn scaled_row(&mut self, city: &'a str, min: i64, max: i64) -> Row<'a> {
+        let scaled = self.rng.gen_range(min..=max);
+        Row::scaled(city, scaled, self.fraction_digits)
+    }
+
+    /// Draws a value from `[min, max]`, biased by the drift at the current row.
+    ///
+    /// A drifted row draws a second value and keeps the lower or the higher one.
+    /// The share of drifted rows changes linearly from `amount` at the start to 0
+    /// in the middle and back to `amount` at the end.
+    fn drifted<T: SampleUniform + PartialOrd + Copy>(&mut self, min: T, max: T) -> T {
+        let value = self.rng.gen_range(min..=max);
+        // only draw again with drift, so the output for a seed does not change without it
+        let Some((amount, line_count)) = self.drift else {
+            return value;
+        };
+        let progress = self.index as f64 / line_count.max(1) as f64;
+        let bias = (amount * (2.0 * progress - 1.0)).clamp(-1.0, 1.0);
+        if !self.rng.gen_bool(bias.abs()) {
+            return value;
+        }
+        let other = self.rng.gen_range(min..=max);
+        if (bias < 0.0) == (other < value) {
+            other
+        } else {
+            value
+        }
+    }
+}
+
+/// The number of rows left for every city with `--sort-by-city`
+struct CityPlan {
+    /// The indices of the cities in output order
+    order: Vec<usize>,
+    /// The rows left for every city of `order`
+    counts: Vec<u64>,
+    /// The position in `order` of the city that is generated
+    current: usize,
+}
+
+impl CityPlan {
+    /// The index of the city of the next row, or `None` once all rows are generated
+    fn next_city(&mut self) -> Option<usize> {
+        while *self.counts.get(self.current)? == 0 {
+            self.current += 1;
+        }
+        self.counts[self.current] -= 1;
+        Some(self.order[self.current])
+    }
 }
 
 #[derive(Debug)]
 struct Row<'a> {
     city: &'a str,
-    int_value: i32,
+    negative: bool,
+    int_value: u32,
     fraction: Option<u32>,
+    fraction_digits: usize,
 }
 
 impl Display for Row<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if let Some(fract_value) = self.fraction {
-            write!(f, "{};{}.{}", self.city, self.int_value, fract_value)
+        write!(f, "{};{}", self.city, RowValue(self))
+    }
+}
+
+/// Displays only the value of a row
+struct RowValue<'r, 'a>(&'r Row<'a>);
+
+impl Display for RowValue<'_, '_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let row = self.0;
+        let sign = if row.negative { "-" } else { "" };
+        if let Some(fract_value) = row.fraction {
+            write!(
+                f,
+                "{}{}.{:03$}",
+                sign, row.int_value, fract_value, row.fraction_digits
+            )
         } else {
-            write!(f, "{};{}", self.city, self.int_value)
+            write!(f, "{}{}", sign, row.int_value)
         }
     }
 }
 
-impl Row<'_> {
-    fn value(&self) -> f32 {
-        if let Some(fraction) = self.fraction {
-            // TODO is this the best I can come up with
-            format!("{}.{}", self.int_value, fraction).parse().unwrap()
+impl<'a> Row<'a> {
+    /// A row with the value `scaled / 10^fraction_digits`.
+    fn scaled(city: &'a str, scaled: i64, fraction_digits: u8) -> Self {
+        let scale = 10u64.pow(fraction_digits.into());
+        let int_value = (scaled.unsigned_abs() / scale) as u32;
+        let fraction = (scaled.unsigned_abs() % scale) as u32;
+        Row {
+            city,
+            negative: scaled < 0,
+            int_value,
+            fraction: (fraction_digits > 0).then_some(fraction),
+            fraction_digits: fraction_digits.into(),
+        }
+    }
+
+    /// Writes the row as a binary record. See [OutputFormat::Binary].
+    fn write_binary<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let tenths = match self.fraction_digits {
+            0 => self.scaled_value() * 10,
+            1 => self.scaled_value(),
+            _ => bail!("the binary format supports at most 1 fractional digit"),
+        };
+        let tenths = i16::try_from(tenths).with_context(|| {
+            format!(
+                "value {} is out of range for the binary format",
+                self.value()
+            )
+        })?;
+
+        writer
+            .write_all(&[self.city.len() as u8])
+            .and_then(|_| writer.write_all(self.city.as_bytes()))
+            .and_then(|_| writer.write_all(&tenths.to_le_bytes()))
+            .context("failed to write data")
+    }
+
+    /// The value scaled by `10^fraction_digits`, the inverse of [Row::scaled].
+    fn scaled_value(&self) -> i64 {
+        let scale = 10i64.pow(self.fraction_digits as u32);
+        let scaled = self.int_value as i64 * scale + self.fraction.unwrap_or(0) as i64;
+        if self.negative {
+            -scaled
         } else {
-            self.int_value as f32
+            scaled
         }
     }
+
+    fn value(&self) -> f64 {
+        self.scaled_value() as f64 / 10f64.powi(self.fraction_digits as i32)
+    }
 }
 
 impl<'a, R: Rng> Iterator for Generator<'a, R> {
     type Item = Row<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let city = self.cities.choose(&mut self.rng)?;
+        self.index += 1;
+        // only draw from the rng for pinned cities if there are any, so the output
+        // for a seed does not change without them
+        if !self.pinned.is_empty() && self.rng.gen_bool(self.pin_rate) {
+            let (city, min, max) = self.pinned[self.rng.gen_range(0..self.pinned.len())];
+            return Some(self.scaled_row(city, min, max));
+        }
+
+        // the stations are in the same order as the cities, so the cities of the
+        // rows are the same with and without their value distributions
+        if let Some(stations) = self.stations.as_ref() {
+            let (city, normal) = match self.sorted.as_mut() {
+                Some(plan) => stations[plan.next_city()?],
+                None => *stations.choose(&mut self.rng)?,
+            };
+            let scale = (self.fraction_max + 1) as f64;
+            let scaled = (normal.sample(&mut self.rng) * scale).round() as i64;
+            let (min, max) = self.scaled_bounds();
+            return Some(Row::scaled(
+                city,
+                scaled.clamp(min, max),
+                self.fraction_digits,
+            ));
+        }
 
-        let int_value = self.rng.gen_range(self.min..=self.max);
+        let city = match self.sorted.as_mut() {
+            Some(plan) => &self.cities[plan.next_city()?],
+            None => self.cities.choose(&mut self.rng)?,
+        };
+
+        if let Some((min, max)) = self.exact_range {
+            let scaled = self.drifted(min, max);
+            return Some(Row::scaled(city, scaled, self.fraction_digits));
+        }
+
+        let int_value = self.drifted(self.min, self.max);
 
         if self.fraction_max > 0 {
-            let fract_value = self.rng.gen_range(0..=self.fraction_max) as u32;
+            let fract_value = match &self.last_digit {
+                Some(last_digit) => {
+                    let leading = self.rng.gen_range(0..=self.fraction_max / 10);
+                    (leading * 10 + last_digit.sample(&mut self.rng)) as u32
+                }
+                None => self.rng.gen_range(0..=self.fraction_max) as u32,
+            };
             Some(Row {
                 city,
-                int_value,
+                negative: int_value < 0,
+                int_value: int_value.unsigned_abs(),
                 fraction: Some(fract_value),
+                fraction_digits: self.fraction_digits.into(),
             })
         } else {
             Some(Row {
                 city,
-                int_value,
+                negative: int_value < 0,
+                int_value: int_value.unsigned_abs(),
                 fraction: None,
+                fraction_digits: 0,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    };
+
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha12Rng;
+
+    use crate::{
+        add_to_results, dedup_cities,
+        from_result::{parse_result, scaled_values},
+        generate_cities, max_city_names,
+        stations::STATIONS,
+        throughput_summary, write_count_result, FractionBias, Generator, NameAlphabet,
+        NameStructure, PinnedCity, ResultFormat, Row, MAX_CITY_LEN, REGION_COUNT,
+    };
+
+    #[test]
+    fn check_scaled_value() {
+        for (scaled, fraction_digits, text) in [
+            (-1005, 2, "-10.05"),
+            (-3, 1, "-0.3"),
+            (0, 0, "0"),
+            (999, 1, "99.9"),
+            (123_456, 3, "123.456"),
+        ] {
+            let row = Row::scaled("a", scaled, fraction_digits);
+            assert_eq!(row.to_string(), format!("a;{text}"));
+            assert_eq!(row.scaled_value(), scaled);
+            assert_eq!(row.value(), text.parse::<f64>().unwrap());
+        }
+    }
+
+    #[test]
+    fn check_dedup_cities() {
+        let mut cities: Vec<String> = ["a", "b", "a", "c", "b"].map(String::from).to_vec();
+        assert!(dedup_cities(&mut cities, |city| city, "test", false).is_err());
+        assert_eq!(cities.len(), 5);
+
+        dedup_cities(&mut cities, |city| city, "test", true).unwrap();
+        assert_eq!(cities, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn check_from_result() {
+        let targets = parse_result("{a=b=-1.5/0.3/2.0, c=1.0/1.0/1.0}\n").unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].name, "a=b");
+        assert_eq!(targets[0].mean, 0.3);
+        assert!(parse_result("{}").unwrap().is_empty());
+        assert!(parse_result("a=1.0/2.0/3.0").is_err());
+        assert!(parse_result("{a=1.0/2.0}").is_err());
+        assert!(parse_result("{a=1.0/5.0/3.0}").is_err());
+
+        let values = scaled_values(-15, 3.0, 20, 10);
+        assert_eq!(values.len(), 10);
+        assert_eq!(values.iter().min(), Some(&-15));
+        assert_eq!(values.iter().max(), Some(&20));
+        assert_eq!(values.iter().sum::<i64>(), 30);
+
+        // the mean can not be reached, so it is as close as possible
+        assert_eq!(scaled_values(0, 9.0, 10, 3), [0, 10, 10]);
+        assert_eq!(scaled_values(0, 5.0, 10, 2), [0, 10]);
+        assert_eq!(scaled_values(7, 7.0, 7, 3), [7, 7, 7]);
+    }
+
+    #[test]
+    fn check_max_city_names() {
+        assert_eq!(max_city_names(0, 62), 62);
+        assert_eq!(max_city_names(1, 62), 62);
+        assert_eq!(max_city_names(2, 62), 62 + 62 * 62);
+        assert_eq!(max_city_names(100, 62), u64::MAX);
+        assert_eq!(max_city_names(3, 2), 2 + 4 + 8);
+    }
+
+    #[test]
+    fn check_impossible_city_count_fails() {
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+        assert!(generate_cities(63, 1, 0.3, NameStructure::Flat, None, &mut rng).is_err());
+        assert_eq!(
+            generate_cities(62, 1, 0.3, NameStructure::Flat, None, &mut rng)
+                .unwrap()
+                .len(),
+            62
+        );
+    }
+
+    #[test]
+    fn check_prefixed_names() {
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+        let cities =
+            generate_cities(500, 100, 0.99, NameStructure::Prefixed, None, &mut rng).unwrap();
+        let mut regions: Vec<_> = cities
+            .iter()
+            .map(|city| {
+                assert!(city.len() <= MAX_CITY_LEN, "{city}");
+                let (region, name) = city.split_once('-').unwrap();
+                assert!(region.len() == 2 && region.bytes().all(|c| c.is_ascii_uppercase()));
+                assert!(!name.is_empty());
+                region
             })
+            .collect();
+        regions.dedup();
+        assert_eq!(regions.len(), REGION_COUNT);
+
+        // 16 regions with 62 single character names each
+        assert!(generate_cities(993, 1, 0.3, NameStructure::Prefixed, None, &mut rng).is_err());
+    }
+
+    #[test]
+    fn check_name_alphabet() {
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+        let binary: NameAlphabet = "01".parse().unwrap();
+        let cities =
+            generate_cities(6, 2, 0.5, NameStructure::Flat, Some(&binary), &mut rng).unwrap();
+        assert_eq!(&*cities, ["0", "00", "01", "1", "10", "11"]);
+        assert!(generate_cities(7, 2, 0.5, NameStructure::Flat, Some(&binary), &mut rng).is_err());
+
+        // 50 bytes of 2 byte chars fill the limit
+        let accented: NameAlphabet = "éü".parse().unwrap();
+        let cities = generate_cities(
+            20,
+            100,
+            0.99,
+            NameStructure::Prefixed,
+            Some(&accented),
+            &mut rng,
+        )
+        .unwrap();
+        for city in cities.iter() {
+            assert!(city.len() <= MAX_CITY_LEN, "{city}");
+            let (_, name) = city.split_once('-').unwrap();
+            assert!(name.chars().all(|c| c == 'é' || c == 'ü'), "{city}");
+        }
+
+        assert_eq!(accented.to_string(), "éü");
+        assert!("a;b".parse::<NameAlphabet>().is_err());
+        assert!("a\tb".parse::<NameAlphabet>().is_err());
+        assert!("aba".parse::<NameAlphabet>().is_err());
+        assert!("".parse::<NameAlphabet>().is_err());
+    }
+
+    #[test]
+    fn check_unreachable_city_count_fails() {
+        // names longer than 1 are possible, but too unlikely to ever find 200 cities
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+        assert!(generate_cities(200, 3, 0.0001, NameStructure::Flat, None, &mut rng).is_err());
+    }
+
+    #[test]
+    fn check_shards() {
+        let shard_rows = |shard: u64| {
+            let mut city_rng = ChaCha12Rng::seed_from_u64(7);
+            let cities =
+                generate_cities(20, 3, 0.3, NameStructure::Flat, None, &mut city_rng).unwrap();
+            let mut rng = ChaCha12Rng::seed_from_u64(7);
+            rng.set_stream(shard + 1);
+            let rows: Vec<_> = Generator::new(&cities, -99, 99, 1, rng)
+                .take(100)
+                .map(|row| row.to_string())
+                .collect();
+            (cities, rows)
+        };
+
+        let (cities, rows) = shard_rows(3);
+        // a shard regenerated on its own is the same
+        assert_eq!(shard_rows(3), (cities.clone(), rows.clone()));
+        // other shards share the cities, but not the rows
+        let (other_cities, other_rows) = shard_rows(4);
+        assert_eq!(cities, other_cities);
+        assert_ne!(rows, other_rows);
+    }
+
+    #[test]
+    fn check_fraction_bias() {
+        let cities = ["a".to_string()];
+        let last_digits = |fraction_digits: u8, bias: FractionBias| {
+            let mut counts = [0; 10];
+            let generator = Generator::new(
+                &cities,
+                -9,
+                9,
+                fraction_digits,
+                ChaCha12Rng::seed_from_u64(0),
+            )
+            .with_fraction_bias(bias);
+            for row in generator.take(10_000) {
+                counts[row.fraction.unwrap() as usize % 10] += 1;
+            }
+            counts
+        };
+
+        let uniform = last_digits(1, FractionBias::None);
+        assert!(
+            uniform.iter().all(|&count| (800..1200).contains(&count)),
+            "{uniform:?}"
+        );
+        // 3 of 14 for .0 and .5
+        let half = last_digits(1, FractionBias::Half);
+        assert!((1900..2400).contains(&half[0]), "{half:?}");
+        assert!((1900..2400).contains(&half[5]), "{half:?}");
+        assert!((500..900).contains(&half[1]), "{half:?}");
+        // 5 of 14 for the last of 2 digits
+        let zero = last_digits(2, FractionBias::Zero);
+        assert!((3300..3900).contains(&zero[0]), "{zero:?}");
+        assert!((500..900).contains(&zero[5]), "{zero:?}");
+    }
+
+    #[test]
+    fn check_throughput_summary() {
+        assert_eq!(
+            throughput_summary(3_000_000, 42_000_000, Duration::from_millis(1500)),
+            "wrote 3000000 rows, 42000000 bytes in 1.50s: 2000000 rows/s, 28.0 MB/s"
+        );
+        // nothing was written when resuming a finished run
+        assert!(throughput_summary(0, 0, Duration::ZERO).ends_with(": 0 rows/s, 0.0 MB/s"));
+    }
+
+    #[test]
+    fn check_count_only_result() {
+        let mut results = HashMap::new();
+        for (city, value) in [("b", 12), ("a", -3), ("b", 40)] {
+            add_to_results(&mut results, &Row::scaled(city, value, 1), true);
+        }
+        let b = &results["b"];
+        assert_eq!((b.count, b.total, b.min, b.max), (2, 0, 0, 0));
+
+        let sorted = [&results["a"], &results["b"]];
+        let written = |format| {
+            let mut output = Vec::new();
+            write_count_result(&mut output, &sorted, format).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(written(ResultFormat::Text), "{a=1, b=2}");
+        assert_eq!(written(ResultFormat::Json), r#"{"a":1,"b":2}"#);
+    }
+
+    #[test]
+    fn check_sorted_cities() {
+        let cities: Vec<_> = ["d", "b", "a", "c"].map(String::from).into();
+        let rng = ChaCha12Rng::seed_from_u64(0);
+        let rows: Vec<_> = Generator::new(&cities, -99, 99, 1, rng)
+            .with_sorted_cities(10_000)
+            .map(|row| row.city)
+            .collect();
+        assert_eq!(rows.len(), 10_000);
+        assert!(rows.is_sorted());
+
+        let mut counts = rows.chunk_by(|a, b| a == b).map(|run| (run[0], run.len()));
+        for city in ["a", "b", "c", "d"] {
+            let (run_city, count) = counts.next().unwrap();
+            assert_eq!(run_city, city);
+            // a quarter of the rows give or take a few standard deviations
+            assert!((2300..2700).contains(&count), "{city}: {count}");
+        }
+    }
+
+    #[test]
+    fn check_stations() {
+        let names: HashSet<_> = STATIONS.iter().map(|(name, _)| name).collect();
+        assert_eq!(names.len(), 413);
+
+        let cities: Vec<_> = ["Abha", "Dikson", "Assab"].map(String::from).into();
+        let rng = ChaCha12Rng::seed_from_u64(0);
+        let generator = Generator::new(&cities, -99, 99, 1, rng).with_stations();
+        let mut values: HashMap<&str, Vec<f64>> = HashMap::new();
+        for row in generator.unwrap().take(30_000) {
+            values.entry(row.city).or_default().push(row.value());
+        }
+        for (city, mean) in [("Abha", 18.0), ("Dikson", -11.1), ("Assab", 30.5)] {
+            let values = &values[city];
+            let count = values.len() as f64;
+            let sample_mean = values.iter().sum::<f64>() / count;
+            let variance = values
+                .iter()
+                .map(|v| (v - sample_mean).powi(2))
+                .sum::<f64>()
+                / count;
+            assert!((sample_mean - mean).abs() < 0.5, "{city}: {sample_mean}");
+            assert!((variance.sqrt() - 10.0).abs() < 0.5, "{city}: {variance}");
+        }
+
+        let cities = ["Atlantis".to_string()];
+        let rng = ChaCha12Rng::seed_from_u64(0);
+        let generator = Generator::new(&cities, -99, 99, 1, rng).with_stations();
+        assert!(generator.is_err());
+    }
+
+    #[test]
+    fn check_drift() {
+        let cities = ["a".to_string()];
+        let rows = |drift: f64| -> Vec<f64> {
+            let rng = ChaCha12Rng::seed_from_u64(0);
+            Generator::new(&cities, -99, 99, 1, rng)
+                .with_exact_range(-10.0, 10.0)
+                .with_drift(drift, 10_000)
+                .take(10_000)
+                .map(|row| row.value())
+                .collect()
+        };
+        let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+
+        let drifted = rows(1.0);
+        assert!(drifted.iter().all(|value| (-10.0..=10.0).contains(value)));
+        // a sixth of the range of 20 below and above the middle
+        let (start, end) = (mean(&drifted[..1000]), mean(&drifted[9000..]));
+        assert!((-3.8..-2.2).contains(&start), "{start}");
+        assert!((2.2..3.8).contains(&end), "{end}");
+        // without drift the output does not change
+        let plain: Vec<_> = Generator::new(&cities, -99, 99, 1, ChaCha12Rng::seed_from_u64(0))
+            .with_exact_range(-10.0, 10.0)
+            .take(10_000)
+            .map(|row| row.value())
+            .collect();
+        assert_eq!(rows(0.0), plain);
+    }
+
+    #[test]
+    fn check_pinned_cities() {
+        let pinned: Vec<PinnedCity> = ["Hamburg:-5.5:-5.0", "a:b:7:7"]
+            .iter()
+            .map(|city| city.parse().unwrap())
+            .collect();
+        assert_eq!(pinned[1].name, "a:b");
+        assert!("Hamburg:1.0".parse::<PinnedCity>().is_err());
+        assert!("Hamburg:2.0:1.0".parse::<PinnedCity>().is_err());
+
+        let cities = ["random".to_string()];
+        let generator = Generator::new(&cities, -99, 99, 1, ChaCha12Rng::seed_from_u64(0))
+            .with_pinned_cities(&pinned, 0.5);
+        let mut pinned_rows = 0;
+        for row in generator.take(1000) {
+            match row.city {
+                "Hamburg" => assert!((-5.5..=-5.0).contains(&row.value()), "{row}"),
+                "a:b" => assert_eq!(row.value(), 7.0),
+                _ => continue,
+            }
+            pinned_rows += 1;
         }
+        assert!((400..600).contains(&pinned_rows), "{pinned_rows}");
     }
 }
@@ -0,0 +1,157 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{sync_channel, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, Context, Result};
+
+/// The number of blocks that can wait for each file before writing blocks
+const QUEUED_BLOCKS: usize = 2;
+
+/// Writes the output in blocks of whole lines to several files in turn, see
+/// `--output-paths`.
+///
+/// Every file is written by its own thread, so files on different disks are
+/// written at the same time. A block is sent once it holds at least `block_size`
+/// bytes, cut after its last complete line. The rest starts the next block.
+pub struct RoundRobinWriter {
+    block: Vec<u8>,
+    block_size: usize,
+    files: Vec<FileWriter>,
+    next: usize,
+}
+
+struct FileWriter {
+    path: PathBuf,
+    sender: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl FileWriter {
+    fn create(path: &Path) -> Result<Self> {
+        let mut file = File::create(path)
+            .with_context(|| format!("failed to create output file {}", path.display()))?;
+        let (sender, receiver) = sync_channel::<Vec<u8>>(QUEUED_BLOCKS);
+        let thread = thread::spawn(move || {
+            for block in receiver {
+                file.write_all(&block)?;
+            }
+            file.flush()
+        });
+        Ok(FileWriter {
+            path: path.to_path_buf(),
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    /// Waits until all blocks are written and returns the error of the thread, if any.
+    fn join(&mut self) -> Result<()> {
+        self.sender = None;
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        let written = thread
+            .join()
+            .map_err(|_| anyhow!("the writer of {} panicked", self.path.display()))?;
+        written.with_context(|| format!("failed to write {}", self.path.display()))
+    }
+}
+
+impl RoundRobinWriter {
+    pub fn create(paths: &[PathBuf], block_size: usize) -> Result<Self> {
+        let files = paths
+            .iter()
+            .map(|path| FileWriter::create(path))
+            .collect::<Result<_>>()?;
+        Ok(RoundRobinWriter {
+            block: Vec::with_capacity(block_size),
+            block_size,
+            files,
+            next: 0,
+        })
+    }
+
+    /// Sends the first `len` bytes of the block to the next file.
+    fn send(&mut self, len: usize) -> io::Result<()> {
+        let rest = self.block.split_off(len);
+        let block = std::mem::replace(&mut self.block, rest);
+        let next = (self.next + 1) % self.files.len();
+        let file = &mut self.files[std::mem::replace(&mut self.next, next)];
+
+        let sent = file.sender.as_ref().map(|sender| sender.send(block));
+        match sent {
+            Some(Ok(())) => Ok(()),
+            // the thread only stops early after an error, which `join` reports
+            _ => Err(match file.join() {
+                Ok(()) => io::Error::other(format!("{} is closed", file.path.display())),
+                Err(e) => io::Error::other(format!("{e:#}")),
+            }),
+        }
+    }
+
+    /// Writes the rest of the output and waits until every file is written.
+    pub fn finish(mut self) -> Result<()> {
+        self.flush().context("failed to write data")?;
+        for file in &mut self.files {
+            file.join()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RoundRobinWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.block.extend_from_slice(buf);
+        if self.block.len() >= self.block_size {
+            if let Some(last) = self.block.iter().rposition(|&byte| byte == b'\n') {
+                self.send(last + 1)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Sends the buffered bytes as a block of their own, which should only be done
+    /// once all rows are written, as the block might end within a line.
+    fn flush(&mut self) -> io::Result<()> {
+        match self.block.is_empty() {
+            true => Ok(()),
+            false => self.send(self.block.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs, io::Write};
+
+    use super::RoundRobinWriter;
+
+    #[test]
+    fn check_round_robin() {
+        let paths: Vec<_> = (0..3)
+            .map(|i| temp_dir().join(format!("one-billion-rows-round-robin-{i}.txt")))
+            .collect();
+        let mut writer = RoundRobinWriter::create(&paths, 8).unwrap();
+        for row in ["a;1.0\n", "b;2.0\n", "c;3.0\n", "dd;4.0\n", "e;5.0\n"] {
+            // write the rows in pieces, like `write!` does
+            let (city, value) = row.split_at(row.find(';').unwrap());
+            writer.write_all(city.as_bytes()).unwrap();
+            writer.write_all(value.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let files: Vec<_> = paths
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+        // the block of `dd` is already full before its line ends, so it is cut after `c`
+        assert_eq!(files, ["a;1.0\nb;2.0\ne;5.0\n", "c;3.0\n", "dd;4.0\n"]);
+    }
+}
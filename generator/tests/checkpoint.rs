@@ -0,0 +1,68 @@
+//! Checks that `--resume` after an interrupted run writes the same output as an
+//! uninterrupted run.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+fn generator(data: &Path, expected: &Path, checkpoint: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_generator"));
+    command
+        .args(["--seed", "7", "--line-count", "300000"])
+        .args(["--city-count", "40", "--checkpoint-interval", "10000"])
+        .arg("--output")
+        .arg(data)
+        .arg("--result-output")
+        .arg(expected)
+        .arg("--checkpoint")
+        .arg(checkpoint)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command
+}
+
+#[test]
+fn check_resume() {
+    let dir = std::env::temp_dir().join("one-billion-rows-checkpoint");
+    fs::create_dir_all(&dir).unwrap();
+    let data = dir.join("data.txt");
+    let expected = dir.join("expected.txt");
+    let checkpoint = dir.join("checkpoint.txt");
+
+    let status = generator(&data, &expected, &checkpoint).status().unwrap();
+    assert!(status.success());
+    let uninterrupted = (fs::read(&data).unwrap(), fs::read(&expected).unwrap());
+
+    // interrupt the run once it wrote its first checkpoint
+    fs::remove_file(&data).unwrap();
+    fs::remove_file(&expected).unwrap();
+    let mut child = generator(&data, &expected, &checkpoint).spawn().unwrap();
+    while !checkpoint.exists() {
+        assert!(child.try_wait().unwrap().is_none(), "finished too early");
+        thread::sleep(Duration::from_millis(1));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let interrupted = fs::read(&data).unwrap().len();
+
+    let status = generator(&data, &expected, &checkpoint)
+        .arg("--resume")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let resumed = (fs::read(&data).unwrap(), fs::read(&expected).unwrap());
+    let checkpoint_removed = !checkpoint.exists();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(interrupted < uninterrupted.0.len(), "{interrupted}");
+    assert!(resumed.0 == uninterrupted.0, "the resumed data differs");
+    assert_eq!(
+        String::from_utf8(resumed.1).unwrap(),
+        String::from_utf8(uninterrupted.1).unwrap()
+    );
+    assert!(checkpoint_removed);
+}
@@ -0,0 +1,38 @@
+//! Checks that `--from-result` writes the same rows for the same seed.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+fn generate(seed: &str, result: &Path, output: &Path) -> Vec<u8> {
+    let status = Command::new(env!("CARGO_BIN_EXE_generator"))
+        .args(["--seed", seed, "--rows-per-city", "50", "--from-result"])
+        .arg(result)
+        .arg("--output")
+        .arg(output)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::read(output).unwrap()
+}
+
+#[test]
+fn check_from_result_seed() {
+    let dir = std::env::temp_dir().join("one-billion-rows-from-result");
+    fs::create_dir_all(&dir).unwrap();
+    let result = dir.join("result.txt");
+    let output = dir.join("data.txt");
+    fs::write(&result, "{Hamburg=-5.0/10.0/30.0, Oslo=-10.0/5.7/20.0}\n").unwrap();
+
+    let first = generate("7", &result, &output);
+    let second = generate("7", &result, &output);
+    let other = generate("8", &result, &output);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(first, second);
+    assert_ne!(first, other);
+}
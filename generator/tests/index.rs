@@ -0,0 +1,36 @@
+//! Checks that the index of the generator points at the start of the indexed rows.
+
+use std::process::{Command, Stdio};
+
+#[test]
+fn check_index_offsets() {
+    let dir = std::env::temp_dir().join("one-billion-rows-index");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = dir.join("data.txt");
+    let index = dir.join("data.idx");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_generator"))
+        .args(["--seed", "7", "--line-count", "1050", "--city-count", "20"])
+        .args(["--index-interval", "100", "--line-ending", "crlf"])
+        .arg("--output")
+        .arg(&data)
+        .arg("--index-output")
+        .arg(&index)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let data = std::fs::read(&data).unwrap();
+    let index = std::fs::read_to_string(&index).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let mut lines = index.lines();
+    assert_eq!(lines.next(), Some("interval 100"));
+    let offsets: Vec<usize> = lines.map(|line| line.parse().unwrap()).collect();
+    assert_eq!(offsets.len(), 11);
+    for (k, offset) in offsets.into_iter().enumerate() {
+        let rows_before = data[..offset].iter().filter(|&&c| c == b'\n').count();
+        assert_eq!(rows_before, k * 100);
+        assert!(offset == 0 || data[offset - 1] == b'\n');
+    }
+}
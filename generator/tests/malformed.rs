@@ -0,0 +1,84 @@
+//! Checks that the solver skips the malformed lines of `--malform-rate`.
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use clap::Parser;
+use one_billion_rows::{calculate, Args, Error};
+
+fn solve(args: &[&str], input: &Path) -> Result<String, Error> {
+    let args = Args::parse_from(
+        ["one-billion-rows"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.as_ref())
+            .chain([input.as_os_str()]),
+    );
+    let mut output = Vec::new();
+    calculate(args, &mut output)?;
+    Ok(String::from_utf8(output).unwrap())
+}
+
+fn generate(data: &Path, expected: &Path, kinds: &str) {
+    let status = Command::new(env!("CARGO_BIN_EXE_generator"))
+        .args(["--seed", "7", "--line-count", "5000", "--city-count", "40"])
+        .args(["--malform-rate", "0.2", "--malform-kinds", kinds])
+        .arg("--output")
+        .arg(data)
+        .arg("--result-output")
+        .arg(expected)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn check_malformed_lines_are_skipped() {
+    let dir = std::env::temp_dir().join("one-billion-rows-malformed");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = dir.join("data.txt");
+    let expected = dir.join("expected.txt");
+    let actual = dir.join("actual.txt");
+
+    generate(
+        &data,
+        &expected,
+        "missing-separator,non-numeric,extra-field,empty-line",
+    );
+    // every way of parsing rows has to skip the same lines
+    let mut strict = Vec::new();
+    let mut compared = Vec::new();
+    for engine_args in [&[][..], &["--assume-ascii"], &["--engine=mmap-zerocopy"]] {
+        strict.push(solve(engine_args, &data));
+        let lenient_args = [engine_args, &["--lenient", "--expect-lines=5000"]].concat();
+        let lenient = solve(&lenient_args, &data).unwrap();
+        std::fs::write(&actual, lenient).unwrap();
+        // the solver sums with f32, so means on a tie like x.x5 can round either way
+        let compare_args = ["--decimals=1", "--compare", actual.to_str().unwrap()];
+        compared.push(solve(&compare_args, &expected));
+    }
+
+    generate(&data, &expected, "out-of-range");
+    let bounded = solve(
+        &["--lenient", "--expect-min=-99.9", "--expect-max=99.9"],
+        &data,
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    for strict in strict {
+        assert!(matches!(strict, Err(Error::MalformedData(_))), "{strict:?}");
+    }
+    for compared in compared {
+        let compared = compared.unwrap();
+        let summary = compared.lines().last().unwrap();
+        assert!(summary.starts_with("# 0 of 40 cities differ"), "{compared}");
+    }
+    match bounded {
+        Err(Error::MalformedData(message)) => assert!(message.starts_with("value out of bounds")),
+        other => panic!("expected a value out of bounds, got {other:?}"),
+    }
+}
@@ -0,0 +1,68 @@
+//! Checks that the solver reads the last row of `--no-final-newline` outputs.
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use clap::Parser;
+use one_billion_rows::{calculate, Args};
+
+fn solve(args: &[&str], input: &Path) -> String {
+    let args = Args::parse_from(
+        ["one-billion-rows"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.as_ref())
+            .chain([input.as_os_str()]),
+    );
+    let mut output = Vec::new();
+    calculate(args, &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn check_no_final_newline() {
+    let dir = std::env::temp_dir().join("one-billion-rows-no-final-newline");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = dir.join("data.txt");
+    let expected = dir.join("expected.txt");
+    let actual = dir.join("actual.txt");
+
+    let mut compared = Vec::new();
+    let mut endings = Vec::new();
+    for line_ending in ["lf", "crlf"] {
+        let status = Command::new(env!("CARGO_BIN_EXE_generator"))
+            .args(["--seed", "7", "--line-count", "5000", "--city-count", "40"])
+            .args(["--line-ending", line_ending, "--no-final-newline"])
+            .arg("--output")
+            .arg(&data)
+            .arg("--result-output")
+            .arg(&expected)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let content = std::fs::read(&data).unwrap();
+        endings.push(content[content.len() - 1]);
+        for engine in ["chunked", "mmap-zerocopy"] {
+            let result = solve(&["--expect-lines=5000", "--engine", engine], &data);
+            std::fs::write(&actual, result).unwrap();
+            // the solver sums with f32, so means on a tie like x.x5 can round either way
+            let compare_args = ["--decimals=1", "--compare", actual.to_str().unwrap()];
+            compared.push(solve(&compare_args, &expected));
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert!(
+        endings.iter().all(|&byte| byte.is_ascii_digit()),
+        "{endings:?}"
+    );
+    for compared in compared {
+        let summary = compared.lines().last().unwrap();
+        assert!(summary.starts_with("# 0 of 40 cities differ"), "{compared}");
+    }
+}
@@ -0,0 +1,67 @@
+//! Checks that the files of `--output-paths` together have the expected result.
+
+use std::{
+    ffi::OsStr,
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use clap::Parser;
+use one_billion_rows::{calculate, Args};
+
+fn solve(args: &[&str], inputs: &[&Path]) -> String {
+    let args = Args::parse_from(
+        ["one-billion-rows"]
+            .iter()
+            .chain(args)
+            .map(OsStr::new)
+            .chain(inputs.iter().map(|input| input.as_os_str())),
+    );
+    let mut output = Vec::new();
+    calculate(args, &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn check_output_paths() {
+    let dir = std::env::temp_dir().join("one-billion-rows-output-paths");
+    fs::create_dir_all(&dir).unwrap();
+    let paths: Vec<_> = (0..3).map(|i| dir.join(format!("data-{i}.txt"))).collect();
+    let expected = dir.join("expected.txt");
+    let actual = dir.join("actual.txt");
+
+    let joined = paths
+        .iter()
+        .map(|path| path.to_str().unwrap())
+        .collect::<Vec<_>>()
+        .join(",");
+    let status = Command::new(env!("CARGO_BIN_EXE_generator"))
+        .args(["--seed", "7", "--line-count", "5000", "--city-count", "40"])
+        .args(["--output-paths", &joined, "--output-block-size", "4096"])
+        .arg("--result-output")
+        .arg(&expected)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let files: Vec<_> = paths
+        .iter()
+        .map(|path| fs::read_to_string(path).unwrap())
+        .collect();
+    let inputs: Vec<_> = paths.iter().map(|path| path.as_path()).collect();
+    let result = solve(&["--expect-lines=5000"], &inputs);
+    fs::write(&actual, result).unwrap();
+    // the solver sums with f32, so means on a tie like x.x5 can round either way
+    let compare_args = ["--decimals=1", "--compare", actual.to_str().unwrap()];
+    let compared = solve(&compare_args, &[&expected]);
+    fs::remove_dir_all(&dir).unwrap();
+
+    for file in &files {
+        assert!(file.len() >= 4096 && file.ends_with('\n'), "{}", file.len());
+    }
+    let summary = compared.lines().last().unwrap();
+    assert!(summary.starts_with("# 0 of 40 cities differ"), "{compared}");
+}
@@ -0,0 +1,55 @@
+//! Checks that the result file of the generator is understood by the solver.
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use clap::Parser;
+use one_billion_rows::{calculate, Args};
+
+fn solve(args: &[&Path]) -> Result<String, one_billion_rows::Error> {
+    let args = Args::parse_from(
+        [Path::new("one-billion-rows")]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.as_os_str()),
+    );
+    let mut output = Vec::new();
+    calculate(args, &mut output)?;
+    Ok(String::from_utf8(output).unwrap())
+}
+
+#[test]
+fn check_result_file_matches_solver() {
+    let dir = std::env::temp_dir().join("one-billion-rows-result-format");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = dir.join("data.txt");
+    let expected = dir.join("expected.txt");
+    let actual = dir.join("actual.txt");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_generator"))
+        .args(["--seed", "42", "--line-count", "5000", "--city-count", "50"])
+        .arg("--output")
+        .arg(&data)
+        .arg("--result-output")
+        .arg(&expected)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    std::fs::write(&actual, solve(&[&data]).unwrap()).unwrap();
+    // the solver sums with f32, so means on a tie like x.x5 can round either way
+    let compared = solve(&[
+        Path::new("--compare"),
+        &actual,
+        &expected,
+        Path::new("--decimals=1"),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let compared = compared.unwrap();
+    let summary = compared.lines().last().unwrap();
+    assert!(summary.starts_with("# 0 of 50 cities differ"), "{compared}");
+}
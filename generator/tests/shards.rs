@@ -0,0 +1,44 @@
+//! Checks that a `--shard` is regenerated the same with and without a `--cities-cache`.
+
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+fn generate(output: &Path, cache: Option<&Path>) -> Vec<u8> {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_generator"));
+    command
+        .args(["--seed", "7", "--shard", "3", "--line-count", "1000"])
+        .args(["--city-count", "40"])
+        .arg("--output")
+        .arg(output);
+    if let Some(cache) = cache {
+        command.arg("--cities-cache").arg(cache);
+    }
+    let status = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::read(output).unwrap()
+}
+
+#[test]
+fn check_shard_with_cities_cache() {
+    let dir = std::env::temp_dir().join("one-billion-rows-shards");
+    fs::create_dir_all(&dir).unwrap();
+    let data = dir.join("data.txt");
+    let cache = dir.join("cities.txt");
+    let _ = fs::remove_file(&cache);
+
+    let uncached = generate(&data, None);
+    let cold = generate(&data, Some(&cache));
+    let warm = generate(&data, Some(&cache));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(!uncached.is_empty());
+    assert!(cold == uncached, "writing the cache changed the rows");
+    assert!(warm == uncached, "reading the cache changed the rows");
+}
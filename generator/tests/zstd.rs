@@ -0,0 +1,54 @@
+//! Checks that the solver reads the `.zst` output of the generator.
+#![cfg(feature = "compression")]
+
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use clap::Parser;
+use one_billion_rows::{calculate, Args};
+
+fn solve(args: &[&str], input: &Path) -> String {
+    let args = Args::parse_from(
+        ["one-billion-rows"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.as_ref())
+            .chain([input.as_os_str()]),
+    );
+    let mut output = Vec::new();
+    calculate(args, &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn check_zstd_output() {
+    let dir = std::env::temp_dir().join("one-billion-rows-zstd-output");
+    std::fs::create_dir_all(&dir).unwrap();
+    let data = dir.join("data.txt.zst");
+    let expected = dir.join("expected.txt");
+    let actual = dir.join("actual.txt");
+
+    let status = Command::new(env!("CARGO_BIN_EXE_generator"))
+        .args(["--seed", "7", "--line-count", "5000", "--city-count", "40"])
+        .arg("--output")
+        .arg(&data)
+        .arg("--result-output")
+        .arg(&expected)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let result = solve(&[], &data);
+    std::fs::write(&actual, result).unwrap();
+    // the solver sums with f32, so means on a tie like x.x5 can round either way
+    let compare_args = ["--decimals=1", "--compare", actual.to_str().unwrap()];
+    let compared = solve(&compare_args, &expected);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let summary = compared.lines().last().unwrap();
+    assert!(summary.starts_with("# 0 of 40 cities differ"), "{compared}");
+}
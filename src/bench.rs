@@ -0,0 +1,176 @@
+use std::{hint::black_box, io::Write, ops::Range, path::PathBuf, thread, time::Instant};
+
+use crate::{calculate_chunked, Args, BenchPhase, CityMap, Error, RowSink};
+
+/// Counts the rows without aggregating them, so only reading and parsing is measured
+#[derive(Debug, Default, Clone)]
+struct Discard {
+    rows: usize,
+}
+
+impl RowSink for Discard {
+    fn accept(&mut self, city: &[u8], value: f32) {
+        // keep the parsed row alive, so parsing is not optimized out
+        black_box((city, value));
+        self.rows += 1;
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.rows += other.rows;
+    }
+}
+
+/// The parsed rows of the input in memory, with the city names back to back
+#[derive(Debug, Default, Clone)]
+struct ParsedRows {
+    names: Vec<u8>,
+    rows: Vec<(Range<usize>, f32)>,
+}
+
+impl RowSink for ParsedRows {
+    fn accept(&mut self, city: &[u8], value: f32) {
+        let start = self.names.len();
+        self.names.extend_from_slice(city);
+        self.rows.push((start..self.names.len(), value));
+    }
+
+    fn merge(&mut self, other: Self) {
+        let offset = self.names.len();
+        self.names.extend_from_slice(&other.names);
+        self.rows.extend(
+            other
+                .rows
+                .into_iter()
+                .map(|(name, value)| (name.start + offset..name.end + offset, value)),
+        );
+    }
+}
+
+/// Measures a single phase of processing the input of `args`, see `--bench`.
+///
+/// [BenchPhase::Parse] runs the chunked engine with a sink that discards every row.
+/// [BenchPhase::Aggregate] parses the whole input into memory first and only
+/// measures replaying the rows into a city map per worker and merging those.
+pub(crate) fn run<OWrite: Write>(
+    args: &Args,
+    phase: BenchPhase,
+    input_files: Vec<PathBuf>,
+    threads: usize,
+    mut output: OWrite,
+) -> Result<(), Error> {
+    let mut bytes = 0;
+    for path in &input_files {
+        bytes += path.metadata().map_err(|e| Error::from_io(path, e))?.len();
+    }
+    let megabytes = bytes as f64 / 1e6;
+
+    let start = Instant::now();
+    match phase {
+        BenchPhase::Parse => {
+            let (discarded, _) =
+                calculate_chunked(args, input_files, threads, Discard::default(), None)?;
+            let elapsed = start.elapsed();
+            writeln!(
+                output,
+                "# bench parse: {} rows, {megabytes:.1} MB in {elapsed:.2?}: {:.0} rows/s, {:.1} MB/s",
+                discarded.rows,
+                discarded.rows as f64 / elapsed.as_secs_f64(),
+                megabytes / elapsed.as_secs_f64()
+            )?;
+        }
+        BenchPhase::Aggregate => {
+            let (parsed, _) =
+                calculate_chunked(args, input_files, threads, ParsedRows::default(), None)?;
+            let parse_time = start.elapsed();
+
+            let start = Instant::now();
+            let cities = replay(&parsed, threads);
+            let elapsed = start.elapsed();
+            writeln!(
+                output,
+                "# bench aggregate: {} rows into {} cities in {elapsed:.2?}: {:.0} rows/s, \
+                 after parsing into memory in {parse_time:.2?}",
+                parsed.rows.len(),
+                black_box(cities).into_result()?.len(),
+                parsed.rows.len() as f64 / elapsed.as_secs_f64()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds the rows to a [CityMap] per worker, each taking a contiguous part of them,
+/// and merges the maps like the chunked engine does.
+fn replay(parsed: &ParsedRows, threads: usize) -> CityMap {
+    let part = parsed.rows.len().div_ceil(threads).max(1);
+    let maps: Vec<CityMap> = thread::scope(|scope| {
+        let handles: Vec<_> = parsed
+            .rows
+            .chunks(part)
+            .map(|rows| {
+                scope.spawn(move || {
+                    let mut cities = CityMap::default();
+                    for (name, value) in rows {
+                        cities.accept(&parsed.names[name.clone()], *value);
+                    }
+                    cities
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("replaying rows failed"))
+            .collect()
+    });
+
+    let mut maps = maps.into_iter();
+    let mut merged = maps.next().unwrap_or_default();
+    maps.for_each(|cities| merged.merge(cities));
+    merged
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::{replay, ParsedRows};
+    use crate::{calculate, Args, RowSink};
+
+    #[test]
+    fn check_replay() {
+        let mut first = ParsedRows::default();
+        first.accept(b"a", 1.0);
+        first.accept(b"bb", 2.0);
+        let mut second = ParsedRows::default();
+        second.accept(b"a", 3.0);
+        first.merge(second);
+        assert_eq!(first.names, b"abba");
+
+        for threads in [1, 2, 8] {
+            let result = replay(&first, threads).into_result().unwrap();
+            let a = &result["a"];
+            assert_eq!((a.min(), a.mean(), a.max()), (1.0, 2.0, 3.0), "{threads}");
+            assert_eq!(result["bb"].count(), 1);
+        }
+    }
+
+    #[test]
+    fn check_bench() {
+        for (phase, expected) in [
+            ("parse", "# bench parse: 1000 rows, 0.0 MB in "),
+            (
+                "aggregate",
+                "# bench aggregate: 1000 rows into 10 cities in ",
+            ),
+        ] {
+            let args = Args::parse_from(["one-billion-rows", "--bench", phase, "data/test.txt"]);
+            let mut output = Vec::new();
+            calculate(args, &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            assert!(output.starts_with(expected), "{output}");
+            assert_eq!(output.lines().count(), 1);
+        }
+        let args = Args::parse_from(["one-billion-rows", "--bench=parse", "-"]);
+        assert!(calculate(args, Vec::new()).is_err());
+    }
+}
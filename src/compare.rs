@@ -0,0 +1,405 @@
+use std::{
+    fs::read_to_string,
+    io::{self, Write},
+    path::Path,
+};
+
+use hashbrown::HashMap;
+
+use crate::Error;
+
+/// The min, mean and max of a city as printed in a result file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub min: f32,
+    pub mean: f32,
+    pub max: f32,
+}
+
+/// How much two results may differ in `--compare`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Tolerance {
+    /// The largest accepted absolute difference
+    Absolute(f32),
+    /// Accept a difference of one unit in the last of this many decimals,
+    /// e.g. `0.1` for 1 decimal.
+    Decimals(u8),
+}
+
+/// How the values of a city in two results relate, see [Stats::classify]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Difference {
+    /// Equal within the tolerance
+    Match,
+    /// One unit in the last decimal apart, which a value on a rounding boundary
+    /// like `x.x5` can cause when the results were summed differently, e.g. as
+    /// integer tenths and as floats
+    Rounding,
+    /// Further apart than rounding can explain
+    Divergence,
+}
+
+impl Tolerance {
+    /// The number of decimals used to print differing values
+    fn precision(&self) -> usize {
+        match self {
+            Tolerance::Absolute(_) => 1,
+            Tolerance::Decimals(decimals) => *decimals as usize,
+        }
+    }
+}
+
+impl Stats {
+    /// The largest absolute difference of min, mean and max.
+    fn difference(&self, other: &Stats) -> f32 {
+        (self.min - other.min)
+            .abs()
+            .max((self.mean - other.mean).abs())
+            .max((self.max - other.max).abs())
+    }
+
+    /// The largest difference of min, mean and max in units of the last of `decimals`.
+    ///
+    /// The values are rounded to `decimals` first, so float noise never adds a unit.
+    fn unit_difference(&self, other: &Stats, decimals: u8) -> i64 {
+        let units = |value: f32| decimal_units(value, decimals);
+        [
+            (self.min, other.min),
+            (self.mean, other.mean),
+            (self.max, other.max),
+        ]
+        .into_iter()
+        .map(|(left, right)| (units(left) - units(right)).abs())
+        .max()
+        .unwrap_or(0)
+    }
+
+    /// Classifies the difference to `other`.
+    ///
+    /// Only [Tolerance::Decimals] knows the precision the values were rounded to,
+    /// so an absolute tolerance never reports [Difference::Rounding].
+    fn classify(&self, other: &Stats, tolerance: Tolerance) -> Difference {
+        match tolerance {
+            // the values only have one fractional digit, so ignore float noise
+            Tolerance::Absolute(tolerance) if self.difference(other) <= tolerance + 1e-4 => {
+                Difference::Match
+            }
+            Tolerance::Absolute(_) => Difference::Divergence,
+            Tolerance::Decimals(decimals) => match self.unit_difference(other, decimals) {
+                0 => Difference::Match,
+                1 => Difference::Rounding,
+                _ => Difference::Divergence,
+            },
+        }
+    }
+}
+
+/// `value` in units of the last of `decimals`, rounded half away from zero.
+///
+/// Rounds the shortest decimal representation of `value`, which is the text it was
+/// parsed from, as e.g. `1.05_f32` is slightly below 1.05 and rounds down as a float.
+fn decimal_units(value: f32, decimals: u8) -> i64 {
+    let text = value.abs().to_string();
+    let (integer, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let mut digits = fraction.bytes().map(|digit| i64::from(digit - b'0'));
+    let mut units = integer.parse::<i64>().unwrap_or(i64::MAX);
+    for _ in 0..decimals {
+        units = units
+            .saturating_mul(10)
+            .saturating_add(digits.next().unwrap_or(0));
+    }
+    if digits.next().is_some_and(|digit| digit >= 5) {
+        units = units.saturating_add(1);
+    }
+    match value.is_sign_negative() {
+        true => -units,
+        false => units,
+    }
+}
+
+/// Parses a result in the challenge format `{city=min/mean/max, ...}`.
+///
+/// Returns the cities in the order they appear in the result.
+pub fn parse_result(text: &str) -> Result<Vec<(String, Stats)>, String> {
+    result_entries(text)?
+        .into_iter()
+        .map(|(city, values)| {
+            let mut parsed = values.split('/').map(str::parse::<f32>);
+            match (parsed.next(), parsed.next(), parsed.next(), parsed.next()) {
+                (Some(Ok(min)), Some(Ok(mean)), Some(Ok(max)), None) => {
+                    Ok((city.to_string(), Stats { min, mean, max }))
+                }
+                _ => Err(format!(
+                    "expected `min/mean/max` in {:?}",
+                    format!("{city}={values}")
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Splits a result in the challenge format `{city=values, ...}` into the cities
+/// and their unparsed values.
+pub(crate) fn result_entries(text: &str) -> Result<Vec<(&str, &str)>, String> {
+    let text = text.trim_end();
+    let inner = text
+        .strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+        .ok_or_else(|| "result is not enclosed in `{}`".to_string())?;
+    if inner.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    inner
+        .split(", ")
+        .map(|entry| {
+            entry
+                .rsplit_once('=')
+                .ok_or_else(|| format!("missing `=` in {entry:?}"))
+        })
+        .collect()
+}
+
+/// Reads the result file at `path` and parses it with `parse`.
+pub(crate) fn read_result_file<T>(
+    path: &Path,
+    parse: impl FnOnce(&str) -> Result<T, String>,
+) -> Result<T, Error> {
+    let text = read_to_string(path).map_err(|e| Error::from_io(path, e))?;
+    parse(&text).map_err(|message| Error::MalformedData(format!("{}: {message}", path.display())))
+}
+
+/// Compares two result files and writes every city that differs by more than `tolerance`.
+///
+/// Fails with [Error::VerificationMismatch] if any city differs or is missing
+/// from one of the files. Cities that only differ by rounding are reported as
+/// warnings and only fail with `fail_on_rounding`.
+pub(crate) fn compare_files<OWrite: Write>(
+    left: &Path,
+    right: &Path,
+    tolerance: Tolerance,
+    fail_on_rounding: bool,
+    output: OWrite,
+) -> Result<(), Error> {
+    let left = read_result_file(left, parse_result)?;
+    let right = read_result_file(right, parse_result)?;
+    let mismatches = compare_results(&left, &right, tolerance, output)?;
+
+    let differences = match fail_on_rounding {
+        true => mismatches.divergent + mismatches.rounding,
+        false => mismatches.divergent,
+    };
+    if differences == 0 {
+        Ok(())
+    } else {
+        Err(Error::VerificationMismatch(format!(
+            "{differences} cities differ"
+        )))
+    }
+}
+
+/// The number of cities that differ between two results
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Mismatches {
+    /// Cities that differ by more than rounding can explain or are missing in a result
+    divergent: usize,
+    /// Cities that only differ by rounding, see [Difference::Rounding]
+    rounding: usize,
+}
+
+/// Writes the differences between two results and returns how many cities differ.
+fn compare_results<OWrite: Write>(
+    left: &[(String, Stats)],
+    right: &[(String, Stats)],
+    tolerance: Tolerance,
+    mut output: OWrite,
+) -> io::Result<Mismatches> {
+    let precision = tolerance.precision();
+    let right_by_city: HashMap<_, _> = right.iter().map(|(city, stats)| (city, stats)).collect();
+    let left_by_city: HashMap<_, _> = left.iter().map(|(city, stats)| (city, stats)).collect();
+
+    let mut differences = 0;
+    let mut rounding = 0;
+    for (city, left_stats) in left {
+        match right_by_city.get(city) {
+            Some(right_stats) => {
+                let (relation, note) = match left_stats.classify(right_stats, tolerance) {
+                    Difference::Match => continue,
+                    Difference::Rounding => {
+                        rounding += 1;
+                        ("~", " (rounding)")
+                    }
+                    Difference::Divergence => {
+                        differences += 1;
+                        ("!=", "")
+                    }
+                };
+                writeln!(
+                    output,
+                    "{city}: {:.6$}/{:.6$}/{:.6$} {relation} {:.6$}/{:.6$}/{:.6$}{note}",
+                    left_stats.min,
+                    left_stats.mean,
+                    left_stats.max,
+                    right_stats.min,
+                    right_stats.mean,
+                    right_stats.max,
+                    precision
+                )?;
+            }
+            None => {
+                differences += 1;
+                writeln!(output, "{city}: missing in second result")?;
+            }
+        }
+    }
+    let mut total = left.len();
+    for (city, _) in right {
+        if !left_by_city.contains_key(city) {
+            differences += 1;
+            total += 1;
+            writeln!(output, "{city}: missing in first result")?;
+        }
+    }
+
+    write!(output, "# {differences} of {total} cities differ")?;
+    if rounding > 0 {
+        write!(output, ", {rounding} more only differ by rounding")?;
+    }
+    writeln!(output)?;
+    Ok(Mismatches {
+        divergent: differences,
+        rounding,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs};
+
+    use super::{compare_files, compare_results, parse_result, Mismatches, Stats, Tolerance};
+    use crate::Error;
+
+    #[test]
+    fn check_parse_result() {
+        let result = parse_result("{Abha=-23.0/18.0/59.2, Zürich=1.5/2.0/3.5}\n").unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].0, "Abha");
+        assert_eq!(
+            result[1].1,
+            Stats {
+                min: 1.5,
+                mean: 2.0,
+                max: 3.5
+            }
+        );
+        assert_eq!(parse_result("{}").unwrap().len(), 0);
+        assert!(parse_result("Abha=1.0/2.0/3.0").is_err());
+        assert!(parse_result("{Abha=1.0/2.0}").is_err());
+    }
+
+    #[test]
+    fn check_compare_results() {
+        let left = parse_result("{a=1.0/2.0/3.0, b=1.0/2.0/3.0, c=0.0/0.0/0.0}").unwrap();
+        let right = parse_result("{a=1.0/2.1/3.0, b=1.0/2.5/3.0, d=0.0/0.0/0.0}").unwrap();
+
+        let mut output = Vec::new();
+        let mismatches =
+            compare_results(&left, &right, Tolerance::Absolute(0.1), &mut output).unwrap();
+
+        assert_eq!(mismatches.divergent, 3);
+        assert_eq!(mismatches.rounding, 0);
+        let output = String::from_utf8(output).unwrap();
+        assert!(!output.contains("a:"));
+        assert!(output.contains("b: 1.0/2.0/3.0 != 1.0/2.5/3.0"));
+        assert!(output.contains("c: missing in second result"));
+        assert!(output.contains("d: missing in first result"));
+        assert!(output.ends_with("# 3 of 4 cities differ\n"));
+    }
+
+    #[test]
+    fn check_compare_decimals() {
+        let left = parse_result("{a=0.1/2.0/3.0, b=-0.1/2.0/3.0, c=1.5/2.0/3.0}").unwrap();
+        // one unit off in every value, the most a rounding difference can cause
+        let right = parse_result("{a=0.2/2.1/2.9, b=0.0/1.9/3.1, c=1.6/2.1/3.1}").unwrap();
+        let mut output = Vec::new();
+        let mismatches =
+            compare_results(&left, &right, Tolerance::Decimals(1), &mut output).unwrap();
+        assert_eq!(mismatches, rounding(3));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("a: 0.1/2.0/3.0 ~ 0.2/2.1/2.9 (rounding)\n"));
+        assert!(output.ends_with("# 0 of 3 cities differ, 3 more only differ by rounding\n"));
+
+        // two units off in a single value
+        let right = parse_result("{a=0.1/2.2/3.0, b=0.1/2.0/3.0, c=1.5/2.0/3.2}").unwrap();
+        let mut output = Vec::new();
+        let mismatches =
+            compare_results(&left, &right, Tolerance::Decimals(1), &mut output).unwrap();
+        assert_eq!(mismatches.divergent, 3);
+
+        // with more decimals the same results are far apart
+        let left = parse_result("{a=0.10/2.00/3.00}").unwrap();
+        let right = parse_result("{a=0.11/2.01/2.99}").unwrap();
+        let mismatches =
+            compare_results(&left, &right, Tolerance::Decimals(2), Vec::new()).unwrap();
+        assert_eq!(mismatches, rounding(1));
+        let mismatches =
+            compare_results(&left, &right, Tolerance::Decimals(3), Vec::new()).unwrap();
+        assert_eq!(mismatches.divergent, 1);
+    }
+
+    fn rounding(cities: usize) -> Mismatches {
+        Mismatches {
+            divergent: 0,
+            rounding: cities,
+        }
+    }
+
+    #[test]
+    fn check_rounding_boundary() {
+        let compare = |left: &str, right: &str| {
+            let left = parse_result(left).unwrap();
+            let right = parse_result(right).unwrap();
+            compare_results(&left, &right, Tolerance::Decimals(1), Vec::new()).unwrap()
+        };
+        // a mean of 1.05 summed as integer tenths and as floats
+        assert_eq!(compare("{a=0.0/1.1/2.0}", "{a=0.0/1.0/2.0}"), rounding(1));
+        assert_eq!(compare("{a=0.0/-1.1/2.0}", "{a=0.0/-1.0/2.0}"), rounding(1));
+        // more digits than the compared decimals round to the same value
+        assert_eq!(compare("{a=0.0/1.05/2.0}", "{a=0.0/1.1/2.0}"), rounding(0));
+        assert_eq!(compare("{a=0.0/1.15/2.0}", "{a=0.0/1.2/2.0}"), rounding(0));
+        assert_eq!(
+            compare("{a=0.0/-1.05/2.0}", "{a=0.0/-1.1/2.0}"),
+            rounding(0)
+        );
+        assert_eq!(compare("{a=0.0/1.04/2.0}", "{a=0.0/1.1/2.0}"), rounding(1));
+        // beyond the boundary
+        assert_eq!(compare("{a=0.0/1.2/2.0}", "{a=0.0/1.0/2.0}").divergent, 1);
+        assert_eq!(compare("{a=0.0/0.1/2.0}", "{a=0.0/-0.1/2.0}").divergent, 1);
+    }
+
+    #[test]
+    fn check_fail_on_rounding() {
+        let left = temp_dir().join("one-billion-rows-compare-rounding-left.txt");
+        let right = temp_dir().join("one-billion-rows-compare-rounding-right.txt");
+        fs::write(&left, "{a=0.0/1.1/2.0, b=1.0/1.0/1.0}").unwrap();
+        fs::write(&right, "{a=0.0/1.0/2.0, b=1.0/1.0/1.0}").unwrap();
+        let compare = |fail_on_rounding: bool| {
+            compare_files(
+                &left,
+                &right,
+                Tolerance::Decimals(1),
+                fail_on_rounding,
+                Vec::new(),
+            )
+        };
+
+        compare(false).unwrap();
+        match compare(true) {
+            Err(Error::VerificationMismatch(message)) => assert_eq!(message, "1 cities differ"),
+            other => panic!("expected a verification mismatch, got {other:?}"),
+        }
+        fs::remove_file(left).unwrap();
+        fs::remove_file(right).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use std::{cmp::Reverse, io::Write};
+
+use hashbrown::HashMap;
+
+use crate::{Error, RowSink};
+
+/// Cities with fewer rows are not reported, as any of their values is frequent
+const MIN_ROWS: u64 = 10;
+
+/// Counts how often every value of every city appears, see `--dedup-values`.
+///
+/// Values are counted exactly in tenths between `min` and `max`. Values outside of
+/// that range only count as rows of their city.
+#[derive(Debug, Clone)]
+pub(crate) struct RepeatedValues {
+    /// The lowest counted value in tenths
+    min: i32,
+    /// The number of counted values
+    values: usize,
+    cities: HashMap<Vec<u8>, ValueCounts>,
+}
+
+#[derive(Debug, Clone)]
+struct ValueCounts {
+    counts: Vec<u32>,
+    rows: u64,
+}
+
+impl RepeatedValues {
+    pub(crate) fn new(min: f32, max: f32) -> Self {
+        let min = (min * 10.0).round() as i32;
+        let max = (max * 10.0).round() as i32;
+        assert!(min <= max, "the range of values is empty");
+        RepeatedValues {
+            min,
+            values: (max - min + 1) as usize,
+            cities: HashMap::new(),
+        }
+    }
+
+    fn index(&self, value: f32) -> Option<usize> {
+        let tenths = (value * 10.0).round() as i32;
+        // NaN saturates to 0 and is not counted either way
+        usize::try_from(tenths.checked_sub(self.min)?)
+            .ok()
+            .filter(|&index| index < self.values)
+    }
+
+    /// Writes every city with at least [MIN_ROWS] rows where a single value makes
+    /// up more than `threshold` of its rows, sorted by city name.
+    pub(crate) fn write<OWrite: Write>(
+        &self,
+        threshold: f64,
+        mut output: OWrite,
+    ) -> Result<(), Error> {
+        let mut repeated = Vec::new();
+        for (city, counts) in &self.cities {
+            let (index, &count) = counts
+                .counts
+                .iter()
+                .enumerate()
+                .max_by_key(|&(index, count)| (count, Reverse(index)))
+                .expect("there is at least one value");
+            let share = count as f64 / counts.rows as f64;
+            if counts.rows >= MIN_ROWS && share > threshold {
+                let city = std::str::from_utf8(city).map_err(|e| {
+                    Error::MalformedData(format!("city name is not valid utf8: {e}"))
+                })?;
+                let value = (self.min + index as i32) as f32 / 10.0;
+                repeated.push((city, value, count, counts.rows, share));
+            }
+        }
+        repeated.sort_unstable_by(|a, b| a.0.cmp(b.0));
+
+        writeln!(
+            output,
+            "# {} of {} cities repeat a value in more than {:.1}% of their rows",
+            repeated.len(),
+            self.cities.len(),
+            threshold * 100.0
+        )?;
+        for (city, value, count, rows, share) in repeated {
+            writeln!(
+                output,
+                "{city}: {value:.1} in {count} of {rows} rows ({:.1}%)",
+                share * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl RowSink for RepeatedValues {
+    fn accept(&mut self, city: &[u8], value: f32) {
+        let index = self.index(value);
+        if !self.cities.contains_key(city) {
+            let counts = ValueCounts {
+                counts: vec![0; self.values],
+                rows: 0,
+            };
+            self.cities.insert(city.to_vec(), counts);
+        }
+        let counts = self
+            .cities
+            .get_mut(city)
+            .expect("the city was just inserted");
+        if let Some(index) = index {
+            counts.counts[index] += 1;
+        }
+        counts.rows += 1;
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (city, other_counts) in other.cities {
+            match self.cities.get_mut(&city) {
+                Some(counts) => {
+                    for (count, other_count) in counts.counts.iter_mut().zip(other_counts.counts) {
+                        *count += other_count;
+                    }
+                    counts.rows += other_counts.rows;
+                }
+                None => {
+                    self.cities.insert(city, other_counts);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RepeatedValues;
+    use crate::RowSink;
+
+    #[test]
+    fn check_repeated_values() {
+        let mut first = RepeatedValues::new(-5.0, 5.0);
+        for _ in 0..6 {
+            first.accept(b"stuck", 1.2);
+        }
+        for value in [-5.0, 0.0, 2.5, 5.0, 7.5] {
+            first.accept(b"fine", value);
+            first.accept(b"few", 3.0);
+        }
+        let mut second = RepeatedValues::new(-5.0, 5.0);
+        for value in [-1.0, 1.2, 4.9, 1.2] {
+            second.accept(b"stuck", value);
+        }
+        for value in [-4.0, 0.1, 0.2, 0.3, 0.4] {
+            second.accept(b"fine", value);
+        }
+        first.merge(second);
+
+        let mut output = Vec::new();
+        first.write(0.5, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "# 1 of 3 cities repeat a value in more than 50.0% of their rows\n\
+             stuck: 1.2 in 8 of 10 rows (80.0%)\n"
+        );
+
+        // the value outside of the range is not counted
+        let mut output = Vec::new();
+        first.write(0.05, &mut output).unwrap();
+        assert!(String::from_utf8(output).unwrap().ends_with(
+            "fine: -5.0 in 1 of 10 rows (10.0%)\n\
+             stuck: 1.2 in 8 of 10 rows (80.0%)\n"
+        ));
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fmt::Display,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// The ways the solver can fail.
+///
+/// Each variant maps to its own process exit code, see [Error::exit_code].
+#[derive(Debug)]
+pub enum Error {
+    /// An input file does not exist
+    FileNotFound(PathBuf),
+    /// The input does not follow the expected format
+    MalformedData(String),
+    /// The result does not match the expected result
+    VerificationMismatch(String),
+    /// The arguments cannot be used together, like the usage errors of clap
+    InvalidArguments(String),
+    /// Any other io error while reading the input or writing the output
+    Io(io::Error),
+}
+
+impl Error {
+    /// The exit code of the process if it fails with this error.
+    ///
+    /// Code 1 is unused, [Error::InvalidArguments] shares 2 with the usage errors of
+    /// clap and 101 is left for panics.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::FileNotFound(_) => 3,
+            Error::MalformedData(_) => 4,
+            Error::VerificationMismatch(_) => 5,
+            Error::Io(_) => 6,
+            Error::InvalidArguments(_) => 2,
+        }
+    }
+
+    /// Classifies an io error that happened while reading `path`.
+    pub(crate) fn from_io(path: &Path, error: io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::NotFound => Error::FileNotFound(path.to_owned()),
+            ErrorKind::InvalidData | ErrorKind::UnexpectedEof => {
+                Error::MalformedData(format!("{}: {error}", path.display()))
+            }
+            _ => Error::Io(error),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::FileNotFound(path) => write!(f, "file not found: {}", path.display()),
+            Error::MalformedData(message) => write!(f, "malformed data: {message}"),
+            Error::VerificationMismatch(message) => write!(f, "verification failed: {message}"),
+            Error::Io(error) => write!(f, "io error: {error}"),
+            Error::InvalidArguments(message) => write!(f, "invalid arguments: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
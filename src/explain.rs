@@ -0,0 +1,217 @@
+use std::{fs::metadata, io::Write, path::PathBuf};
+
+use clap::ValueEnum;
+
+use crate::{
+    mmap::SEGMENT_SIZE, Args, Engine, Error, Format, Separator, ValueType, BLOCK_SIZE,
+    CHANNEL_CAPACITY, MAX_BLOCK_SIZE,
+};
+
+/// Writes how the input of `args` is going to be processed, see `--explain`.
+///
+/// Covers the options as they are resolved for this run, like the number of
+/// workers that is limited by the input size and the detected separator.
+pub(crate) fn write_plan<OWrite: Write>(
+    args: &Args,
+    input_files: &[PathBuf],
+    threads: usize,
+    separator: u8,
+    mut output: OWrite,
+) -> Result<(), Error> {
+    let mut bytes = 0;
+    for path in input_files {
+        bytes += metadata(path).map_err(|e| Error::from_io(path, e))?.len();
+    }
+    writeln!(output, "# execution plan")?;
+    writeln!(
+        output,
+        "#   input: {bytes} bytes in {} file{}, not compressed{}",
+        input_files.len(),
+        if input_files.len() == 1 { "" } else { "s" },
+        byte_range(args)
+    )?;
+
+    let workers = match threads == args.threads {
+        true => format!("{threads} workers"),
+        false => format!(
+            "{threads} workers, {} requested but limited by the input size",
+            args.threads
+        ),
+    };
+    match args.engine {
+        Engine::Chunked => writeln!(
+            output,
+            "#   engine: chunked, {workers}, {}, a channel of {CHANNEL_CAPACITY} blocks{}",
+            match args.adaptive_block_size {
+                true => format!(
+                    "blocks of {BLOCK_SIZE} to {MAX_BLOCK_SIZE} bytes, adapted to the workers"
+                ),
+                false => format!("blocks of {BLOCK_SIZE} bytes"),
+            },
+            if args.reverse {
+                ", read in reverse"
+            } else {
+                ""
+            }
+        )?,
+        Engine::MmapZerocopy => writeln!(
+            output,
+            "#   engine: mmap-zerocopy, {workers}, segments of about {} MiB",
+            SEGMENT_SIZE / (1024 * 1024)
+        )?,
+    }
+
+    match args.format {
+        Format::Text => {
+            let detected = match args.separator {
+                Separator::Auto => " (detected)",
+                Separator::Char(_) => "",
+            };
+            let mut columns = match args.city_column.zip(args.value_column) {
+                Some((city, value)) => format!("city column {city}, value column {value}"),
+                None => format!("columns {}", name(args.column_order)),
+            };
+            if let Some((timestamp, window)) = args.timestamp_column.zip(args.window) {
+                columns += &format!(", the last {window} seconds of timestamp column {timestamp}");
+            }
+            writeln!(
+                output,
+                "#   format: text, separator {:?}{detected}, decimal separator {:?}, {columns}",
+                separator as char,
+                args.decimal_separator.as_byte() as char
+            )?;
+        }
+        Format::Binary => writeln!(output, "#   format: binary records")?,
+    }
+    writeln!(
+        output,
+        "#   values: {}, numeric format {}{}",
+        name(args.value_type),
+        name(args.numeric_format),
+        if args.lenient {
+            ", malformed lines are skipped"
+        } else {
+            ""
+        }
+    )?;
+    writeln!(output, "#   utf8: {}", utf8_validation(args))?;
+    writeln!(
+        output,
+        "#   aggregation: {}, hashbrown with its default hasher",
+        aggregation(args)
+    )?;
+    Ok(())
+}
+
+/// The name of `value` on the command line
+fn name<T: ValueEnum>(value: T) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |value| value.get_name().to_string())
+}
+
+fn byte_range(args: &Args) -> String {
+    match (args.offset, args.length) {
+        (None, None) => String::new(),
+        (offset, Some(length)) => format!(", {length} bytes from byte {}", offset.unwrap_or(0)),
+        (Some(offset), None) => format!(", from byte {offset}"),
+    }
+}
+
+fn utf8_validation(args: &Args) -> &'static str {
+    match (args.format, args.engine) {
+        (Format::Binary, _) => "only the city names are validated, after merging",
+        (_, Engine::MmapZerocopy) => "only city names are validated",
+        _ if args.assume_ascii => "not validated, the input is assumed to be ascii",
+        _ if args.validate_utf8_once => "validated once before processing",
+        _ => "validated for every block",
+    }
+}
+
+/// What the workers collect, in the order `calculate` checks the options
+fn aggregation(args: &Args) -> &'static str {
+    if args.histogram.is_some() {
+        "a histogram of every city"
+    } else if args.dedup_values {
+        "the count of every value of every city"
+    } else if args.keys_only {
+        "only the city names"
+    } else if args.external_merge.is_some() {
+        "a city map that spills sorted runs to disk"
+    } else if args.sorted_merge {
+        "a city map sorted by name"
+    } else if args.spec_strict || args.value_type == ValueType::Int {
+        "exact integer sums of every city"
+    } else if args.engine == Engine::MmapZerocopy {
+        "a city map with names borrowed from the mapping"
+    } else {
+        "a city map"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::write_plan;
+    use crate::Args;
+
+    fn plan(extra: &[&str], threads: usize, separator: u8) -> String {
+        let args = Args::parse_from([&["one-billion-rows"], extra, &["data/test.txt"]].concat());
+        let input_files = [PathBuf::from("data/test.txt")];
+        let mut output = Vec::new();
+        write_plan(&args, &input_files, threads, separator, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn check_plan() {
+        let chunked = plan(&["--threads=4", "--separator=auto"], 2, b',');
+        let lines: Vec<_> = chunked.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "# execution plan",
+                "#   input: 8173 bytes in 1 file, not compressed",
+                "#   engine: chunked, 2 workers, 4 requested but limited by the input size, \
+                 blocks of 4096 bytes, a channel of 10 blocks",
+                "#   format: text, separator ',' (detected), decimal separator '.', \
+                 columns city-value",
+                "#   values: float, numeric format lenient",
+                "#   utf8: validated for every block",
+                "#   aggregation: a city map, hashbrown with its default hasher",
+            ]
+        );
+
+        let mmap = plan(&["--engine=mmap-zerocopy", "--value-type=int"], 8, b';');
+        assert!(mmap.contains("engine: mmap-zerocopy, 8 workers, segments of about 4 MiB\n"));
+        assert!(mmap.contains("utf8: only city names are validated\n"));
+        assert!(mmap.contains("values: int,"));
+
+        let adaptive = plan(&["--adaptive-block-size"], 8, b';');
+        assert!(adaptive.contains(
+            "8 workers, blocks of 4096 to 1048576 bytes, adapted to the workers, a channel"
+        ));
+
+        let window = plan(
+            &[
+                "--city-column=1",
+                "--value-column=2",
+                "--timestamp-column=0",
+                "--window=60",
+            ],
+            1,
+            b';',
+        );
+        assert!(window.contains(
+            "city column 1, value column 2, the last 60 seconds of timestamp column 0\n"
+        ));
+
+        let histogram = plan(&["--histogram=5", "--offset=10", "--assume-ascii"], 8, b';');
+        assert!(histogram.contains("not compressed, from byte 10\n"));
+        assert!(histogram.contains("utf8: not validated, the input is assumed to be ascii\n"));
+        assert!(histogram.contains("aggregation: a histogram of every city,"));
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::{remove_file, File},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    str::from_utf8,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use hashbrown::HashMap;
+
+use crate::{
+    results::{CityLine, CityStats},
+    CityEntry, Error, RowSink,
+};
+
+/// The most runs that are merged at once, to stay below the open file limit
+const MAX_FAN_IN: usize = 64;
+
+/// Numbers the run files of all sinks of this process
+static NEXT_RUN: AtomicUsize = AtomicUsize::new(0);
+
+/// A [RowSink] that keeps at most `max_cities` cities in memory and spills them
+/// to sorted run files in a directory, see `--external-merge`.
+///
+/// The runs are merged into the sorted challenge output by [SpillingMap::write],
+/// which only holds one city per run in memory.
+#[derive(Debug, Clone)]
+pub(crate) struct SpillingMap {
+    cities: HashMap<Vec<u8>, CityEntry>,
+    dir: Arc<Path>,
+    max_cities: usize,
+    fan_in: usize,
+    runs: Vec<PathBuf>,
+    /// The first error while spilling, as [RowSink::accept] cannot fail
+    error: Option<String>,
+}
+
+impl SpillingMap {
+    pub(crate) fn new(dir: &Path, max_cities: usize) -> Self {
+        assert!(max_cities > 0, "a run needs at least one city");
+        SpillingMap {
+            cities: HashMap::new(),
+            dir: dir.into(),
+            max_cities,
+            fan_in: MAX_FAN_IN,
+            runs: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn spill_if_full(&mut self) {
+        if self.cities.len() >= self.max_cities && self.error.is_none() {
+            if let Err(e) = self.spill() {
+                self.error = Some(format!("failed to spill cities to {:?}: {e}", self.dir));
+            }
+        }
+    }
+
+    /// Writes all cities in memory to a new sorted run
+    fn spill(&mut self) -> io::Result<()> {
+        let mut cities: Vec<_> = self.cities.drain().collect();
+        cities.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let path = new_run_path(&self.dir);
+        // remember the run first, so it is removed even if writing fails
+        self.runs.push(path.clone());
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for (name, entry) in &cities {
+            write_entry(&mut writer, name, entry)?;
+        }
+        writer.flush()
+    }
+
+    /// Merges all runs and writes the cities sorted by name in the challenge format.
+    ///
+    /// The run files are removed afterwards, even if merging fails.
+    pub(crate) fn write<OWrite: Write>(
+        mut self,
+        show_stddev: bool,
+        mut output: OWrite,
+    ) -> Result<(), Error> {
+        let result = self.merge_into(show_stddev, &mut output);
+        for run in &self.runs {
+            // runs that were already merged are gone
+            let _ = remove_file(run);
+        }
+        result
+    }
+
+    fn merge_into<OWrite: Write>(
+        &mut self,
+        show_stddev: bool,
+        output: &mut OWrite,
+    ) -> Result<(), Error> {
+        if let Some(error) = self.error.take() {
+            return Err(Error::Io(io::Error::other(error)));
+        }
+        if !self.cities.is_empty() {
+            self.spill()?;
+        }
+
+        // merge groups of runs into larger runs until they can be merged at once
+        while self.runs.len() > self.fan_in {
+            let runs = std::mem::take(&mut self.runs);
+            for group in runs.chunks(self.fan_in) {
+                let path = new_run_path(&self.dir);
+                self.runs.push(path.clone());
+                let mut writer = BufWriter::new(File::create(&path)?);
+                merge_runs(group, |name, entry| write_entry(&mut writer, name, entry))?;
+                writer.flush()?;
+                for run in group {
+                    remove_file(run)?;
+                }
+            }
+        }
+
+        write!(output, "{{")?;
+        let mut first = true;
+        // the error of the output, as `emit` can only return io errors
+        let mut error = None;
+        merge_runs(&self.runs, |name, city| {
+            let Ok(name) = from_utf8(name) else {
+                let name = String::from_utf8_lossy(name);
+                error = Some(Error::MalformedData(format!(
+                    "city name is not valid utf8: {name:?}"
+                )));
+                return Err(ErrorKind::InvalidData.into());
+            };
+            if let Err(e) = city.check_count(name) {
+                error = Some(e);
+                return Err(ErrorKind::InvalidData.into());
+            }
+            if !first {
+                write!(output, ", ")?;
+            }
+            first = false;
+            let line = CityLine {
+                name,
+                stats: CityStats::from(city),
+                show_stddev,
+                show_count: false,
+            };
+            write!(output, "{line}")
+        })
+        .map_err(|e| error.take().unwrap_or(Error::Io(e)))?;
+        write!(output, "}}")?;
+        Ok(())
+    }
+}
+
+impl RowSink for SpillingMap {
+    fn accept(&mut self, city: &[u8], value: f32) {
+        match self.cities.get_mut(city) {
+            Some(entry) => entry.add(value),
+            None => {
+                let mut entry = CityEntry::default();
+                entry.add(value);
+                self.cities.insert(city.to_vec(), entry);
+                self.spill_if_full();
+            }
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.runs.extend(other.runs);
+        if self.error.is_none() {
+            self.error = other.error;
+        }
+        for (city, entry) in other.cities {
+            match self.cities.get_mut(&city) {
+                Some(merged) => merged.merge(&entry),
+                None => {
+                    self.cities.insert(city, entry);
+                    self.spill_if_full();
+                }
+            }
+        }
+    }
+}
+
+fn new_run_path(dir: &Path) -> PathBuf {
+    let run = NEXT_RUN.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!("one-billion-rows-{}-{run}.run", process::id()))
+}
+
+/// Writes a run entry: the `u32` name length, the name and the values, all
+/// little endian. A count of `u64::MAX` marks a count that overflowed.
+fn write_entry<W: Write>(output: &mut W, name: &[u8], entry: &CityEntry) -> io::Result<()> {
+    let length = u32::try_from(name.len()).map_err(|_| ErrorKind::InvalidInput)?;
+    output.write_all(&length.to_le_bytes())?;
+    output.write_all(name)?;
+    output.write_all(&entry.min.to_le_bytes())?;
+    output.write_all(&entry.max.to_le_bytes())?;
+    output.write_all(&entry.sum.to_le_bytes())?;
+    output.write_all(&entry.sum_squares.to_le_bytes())?;
+    let count = match entry.overflowed {
+        true => u64::MAX,
+        false => entry.count as u64,
+    };
+    output.write_all(&count.to_le_bytes())
+}
+
+/// Reads the next entry written by [write_entry], or `None` at the end of the run.
+fn read_entry<R: Read>(input: &mut R) -> io::Result<Option<(Vec<u8>, CityEntry)>> {
+    let mut length = [0; 4];
+    match input.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut name = vec![0; u32::from_le_bytes(length) as usize];
+    input.read_exact(&mut name)?;
+
+    let mut values = [0; 4 + 4 + 4 + 8 + 8];
+    input.read_exact(&mut values)?;
+    let f32_at = |at: usize| f32::from_le_bytes(values[at..at + 4].try_into().unwrap());
+    let count = u64::from_le_bytes(values[20..28].try_into().unwrap());
+    let entry = CityEntry {
+        min: f32_at(0),
+        max: f32_at(4),
+        sum: f32_at(8),
+        sum_squares: f64::from_le_bytes(values[12..20].try_into().unwrap()),
+        count: count.try_into().unwrap_or(usize::MAX),
+        overflowed: count == u64::MAX,
+        ..CityEntry::default()
+    };
+    Ok(Some((name, entry)))
+}
+
+/// Calls `emit` for every city of the sorted `runs` in order, with the entries
+/// of a city in all runs merged.
+fn merge_runs(
+    runs: &[PathBuf],
+    mut emit: impl FnMut(&[u8], &CityEntry) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut readers = runs
+        .iter()
+        .map(|run| Ok(BufReader::new(File::open(run)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // the smallest name of every run, with its entry kept next to the reader
+    let mut heads = Vec::with_capacity(readers.len());
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (run, reader) in readers.iter_mut().enumerate() {
+        let head = read_entry(reader)?.map(|(name, entry)| {
+            heap.push(Reverse((name, run)));
+            entry
+        });
+        heads.push(head);
+    }
+
+    while let Some(Reverse((name, run))) = heap.pop() {
+        let mut merged = heads[run].take().expect("every run in the heap has a head");
+        advance(&mut readers[run], &mut heads[run], &mut heap, run)?;
+
+        while let Some(Reverse((next, other))) = heap.peek() {
+            if *next != name {
+                break;
+            }
+            let other = *other;
+            heap.pop();
+            merged.merge(
+                &heads[other]
+                    .take()
+                    .expect("every run in the heap has a head"),
+            );
+            advance(&mut readers[other], &mut heads[other], &mut heap, other)?;
+        }
+        emit(&name, &merged)?;
+    }
+    Ok(())
+}
+
+/// Reads the next entry of `run` into `head` and the heap
+fn advance(
+    reader: &mut BufReader<File>,
+    head: &mut Option<CityEntry>,
+    heap: &mut BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    run: usize,
+) -> io::Result<()> {
+    if let Some((name, entry)) = read_entry(reader)? {
+        heap.push(Reverse((name, run)));
+        *head = Some(entry);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs::create_dir_all, str::from_utf8};
+
+    use super::SpillingMap;
+    use crate::RowSink;
+
+    #[test]
+    fn check_spilling_map() {
+        let dir = std::env::temp_dir().join("one-billion-rows-spilling-map");
+        create_dir_all(&dir).unwrap();
+
+        let mut first = SpillingMap::new(&dir, 2);
+        for (city, value) in [("d", 1.0), ("b", 2.0), ("a", -1.0), ("b", 4.0), ("c", 0.5)] {
+            first.accept(city.as_bytes(), value);
+        }
+        let mut second = SpillingMap::new(&dir, 2);
+        for (city, value) in [("b", -6.0), ("e", 3.0), ("a", 1.0)] {
+            second.accept(city.as_bytes(), value);
+        }
+        first.merge(second);
+        assert!(first.runs.len() >= 2, "{:?}", first.runs);
+        // merge some runs into larger runs before the final merge
+        first.fan_in = 2;
+
+        let mut output = Vec::new();
+        first.write(true, &mut output).unwrap();
+        assert_eq!(
+            from_utf8(&output).unwrap(),
+            "{a=-1.0/0.0/1.0/1.0, b=-6.0/0.0/4.0/4.3, c=0.5/0.5/0.5/0.0, \
+             d=1.0/1.0/1.0/0.0, e=3.0/3.0/3.0/0.0}"
+        );
+        assert_eq!(dir.read_dir().unwrap().count(), 0, "all runs are removed");
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+    thread,
+};
+
+use crossbeam::channel::{bounded, Receiver};
+use flate2::read::GzDecoder;
+
+use crate::{is_bgzf_member, BGZF_HEADER};
+
+/// The number of decompressed members every worker can be ahead of the reader
+const MEMBERS_AHEAD: usize = 2;
+
+/// Reads a bgzip file, whose members are decompressed in parallel by `threads` workers.
+///
+/// Worker `w` decompresses the members `w`, `w + threads`, ..., each with its own
+/// decoder, and the reader takes them from the workers in turn, so the
+/// decompressed data comes out in order. Lines can span members like any other
+/// read boundary.
+pub(crate) struct MemberReader {
+    decompressed: Vec<Receiver<io::Result<Vec<u8>>>>,
+    members: usize,
+    next: usize,
+    current: Vec<u8>,
+    position: usize,
+}
+
+impl MemberReader {
+    pub(crate) fn open(path: &Path, threads: usize) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let members = member_ranges(&mut file)?;
+        let threads = threads.clamp(1, members.len().max(1));
+
+        let mut decompressed = Vec::with_capacity(threads);
+        for worker in 0..threads {
+            let (sender, receiver) = bounded(MEMBERS_AHEAD);
+            // every worker seeks on its own, so they cannot share the file offset
+            let mut file = File::open(path)?;
+            let ranges: Vec<_> = members
+                .iter()
+                .skip(worker)
+                .step_by(threads)
+                .cloned()
+                .collect();
+            thread::spawn(move || {
+                for range in ranges {
+                    // the reader stops at the first error, or was dropped early
+                    if sender.send(decompress(&mut file, range)).is_err() {
+                        return;
+                    }
+                }
+            });
+            decompressed.push(receiver);
+        }
+        Ok(Self {
+            decompressed,
+            members: members.len(),
+            next: 0,
+            current: Vec::new(),
+            position: 0,
+        })
+    }
+}
+
+impl Read for MemberReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.current.len() {
+            if self.next == self.members {
+                return Ok(0);
+            }
+            let worker = &self.decompressed[self.next % self.decompressed.len()];
+            self.current = worker
+                .recv()
+                .map_err(|_| io::Error::other("decompressing a gzip member failed"))??;
+            self.position = 0;
+            self.next += 1;
+        }
+        let size = buf.len().min(self.current.len() - self.position);
+        buf[..size].copy_from_slice(&self.current[self.position..][..size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+/// The byte ranges of the members of the bgzip `file`.
+///
+/// Every member stores its own size in the `BC` subfield of its header, so the
+/// members are found without decompressing any of them.
+fn member_ranges(file: &mut File) -> io::Result<Vec<Range<u64>>> {
+    let len = file.metadata()?.len();
+    let mut members = Vec::new();
+    let mut start = 0;
+    while start < len {
+        let mut header = [0; BGZF_HEADER.len() + 2];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut header)?;
+        if !is_bgzf_member(&header) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("the gzip member at byte {start} is not a bgzip member"),
+            ));
+        }
+        let size = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+        members.push(start..start + size);
+        start += size;
+    }
+    Ok(members)
+}
+
+fn decompress(file: &mut File, range: Range<u64>) -> io::Result<Vec<u8>> {
+    let mut compressed = vec![0; (range.end - range.start) as usize];
+    file.seek(SeekFrom::Start(range.start))?;
+    file.read_exact(&mut compressed)?;
+    let mut decompressed = Vec::new();
+    GzDecoder::new(&compressed[..]).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
@@ -0,0 +1,69 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    path::PathBuf,
+};
+
+use crate::Error;
+
+/// The 64 bit FNV-1a hash, the same the generator prints for its output.
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    pub(crate) fn new() -> Self {
+        Fnv1a(Self::OFFSET)
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hashes the content of all `files` as if they were a single file.
+pub(crate) fn hash_files(files: &[PathBuf]) -> Result<u64, Error> {
+    let mut hash = Fnv1a::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    for path in files {
+        let mut file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+        loop {
+            match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(size) => hash.update(&buffer[..size]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::from_io(path, e)),
+            }
+        }
+    }
+    Ok(hash.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Fnv1a;
+
+    #[test]
+    fn check_fnv1a() {
+        // reference values of the FNV-1a specification
+        assert_eq!(Fnv1a::new().finish(), 0xcbf2_9ce4_8422_2325);
+        let mut hash = Fnv1a::new();
+        hash.update(b"a");
+        assert_eq!(hash.finish(), 0xaf63_dc4c_8601_ec8c);
+
+        let mut split = Fnv1a::new();
+        split.update(b"foo");
+        split.update(b"bar");
+        let mut whole = Fnv1a::new();
+        whole.update(b"foobar");
+        assert_eq!(split.finish(), whole.finish());
+        assert_eq!(whole.finish(), 0x85944171f73967e8);
+    }
+}
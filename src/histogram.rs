@@ -0,0 +1,121 @@
+use std::io::Write;
+
+use hashbrown::HashMap;
+
+use crate::{Error, RowSink};
+
+/// Counts the values of every city in equal-width bins.
+///
+/// All cities share the same bins, which span `min` to `max`. Values outside of
+/// that range are counted in the first or last bin.
+#[derive(Debug, Clone)]
+pub(crate) struct Histograms {
+    min: f32,
+    max: f32,
+    bins: usize,
+    cities: HashMap<Vec<u8>, Vec<u64>>,
+}
+
+impl Histograms {
+    pub(crate) fn new(bins: usize, min: f32, max: f32) -> Self {
+        assert!(bins > 0, "a histogram needs at least one bin");
+        Histograms {
+            min,
+            max,
+            bins,
+            cities: HashMap::new(),
+        }
+    }
+
+    fn bin(&self, value: f32) -> usize {
+        let position = (value - self.min) / (self.max - self.min);
+        // negative positions and NaN saturate to 0
+        ((position * self.bins as f32) as usize).min(self.bins - 1)
+    }
+
+    /// Writes the bin counts of every city, sorted by city name.
+    pub(crate) fn write<OWrite: Write>(&self, mut output: OWrite) -> Result<(), Error> {
+        let mut cities = self
+            .cities
+            .iter()
+            .map(|(city, counts)| {
+                let city = std::str::from_utf8(city).map_err(|e| {
+                    Error::MalformedData(format!("city name is not valid utf8: {e}"))
+                })?;
+                Ok((city, counts))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        cities.sort_unstable_by_key(|(city, _)| *city);
+
+        writeln!(
+            output,
+            "# {} bins of width {} from {:.1} to {:.1}",
+            self.bins,
+            (self.max - self.min) / self.bins as f32,
+            self.min,
+            self.max
+        )?;
+        for (city, counts) in cities {
+            write!(output, "{city}:")?;
+            for count in counts {
+                write!(output, " {count}")?;
+            }
+            writeln!(output)?;
+        }
+        Ok(())
+    }
+}
+
+impl RowSink for Histograms {
+    fn accept(&mut self, city: &[u8], value: f32) {
+        let bin = self.bin(value);
+        match self.cities.get_mut(city) {
+            Some(counts) => counts[bin] += 1,
+            None => {
+                let mut counts = vec![0; self.bins];
+                counts[bin] = 1;
+                self.cities.insert(city.to_vec(), counts);
+            }
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (city, other_counts) in other.cities {
+            match self.cities.get_mut(&city) {
+                Some(counts) => {
+                    for (count, other_count) in counts.iter_mut().zip(other_counts) {
+                        *count += other_count;
+                    }
+                }
+                None => {
+                    self.cities.insert(city, other_counts);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Histograms;
+    use crate::RowSink;
+
+    #[test]
+    fn check_histogram_bins() {
+        let mut first = Histograms::new(4, -10.0, 10.0);
+        for value in [-10.0, -5.1, -5.0, 0.0, 4.9, 10.0, 25.0, -25.0] {
+            first.accept(b"a", value);
+        }
+        let mut second = Histograms::new(4, -10.0, 10.0);
+        second.accept(b"a", 7.5);
+        second.accept(b"b", 0.0);
+        first.merge(second);
+
+        let mut output = Vec::new();
+        first.write(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "# 4 bins of width 5 from -10.0 to 10.0\na: 3 1 2 3\nb: 0 0 1 0\n"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+use crate::{compression, decompressed, Error};
+
+/// Whether `input` is read over http instead of from a file
+pub(crate) fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Requests `url` and returns its body, which is read and decompressed as it arrives.
+///
+/// Only plain `http://` is supported, as there is no tls implementation. The request
+/// is sent as HTTP/1.0, so the server sends the body as is until it closes the
+/// connection. Redirects are not followed.
+pub(crate) fn open(url: &str) -> Result<Box<dyn Read + Send>, Error> {
+    let Some(rest) = url.strip_prefix("http://") else {
+        return Err(Error::Io(io::Error::new(
+            ErrorKind::Unsupported,
+            format!("{url}: only http urls are supported, download the file first"),
+        )));
+    };
+    let (host, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    let address = match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{host}:80"),
+    };
+
+    let io_error = |e: io::Error| Error::Io(io::Error::new(e.kind(), format!("{url}: {e}")));
+    let mut stream = TcpStream::connect(address).map_err(io_error)?;
+    let request = format!("GET {path} HTTP/1.0\r\nHost: {host}\r\nConnection: close\r\n\r\n");
+    stream.write_all(request.as_bytes()).map_err(io_error)?;
+
+    let mut response = BufReader::new(stream);
+    let mut status = String::new();
+    response.read_line(&mut status).map_err(io_error)?;
+    let status = status.trim_end();
+    match status.split(' ').nth(1) {
+        Some("200") => {}
+        Some(_) => {
+            return Err(Error::Io(io::Error::other(format!(
+                "{url}: the server responded with {status:?}"
+            ))))
+        }
+        None => {
+            return Err(Error::MalformedData(format!(
+                "{url}: invalid http response {status:?}"
+            )))
+        }
+    }
+
+    // the headers end at the first empty line
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if response.read_line(&mut header).map_err(io_error)? == 0 {
+            return Err(Error::MalformedData(format!(
+                "{url}: the response ends in its headers"
+            )));
+        }
+        if header.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    let compression = compression(response.fill_buf().map_err(io_error)?);
+    decompressed(response, compression, url)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::open;
+    use crate::Error;
+
+    /// Answers a single request with `response` and returns the url to request
+    fn serve(response: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // answering before the whole request arrived can fail the client's write
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buf).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream.write_all(response).unwrap();
+        });
+        format!("http://{address}/data.txt")
+    }
+
+    #[test]
+    fn check_http_input() {
+        let url = serve(b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\na;1.0\nb;2.0\n");
+        let mut body = String::new();
+        open(&url).unwrap().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "a;1.0\nb;2.0\n");
+
+        let url = serve(b"HTTP/1.1 404 Not Found\r\n\r\n");
+        let error = open(&url).err().unwrap().to_string();
+        assert!(
+            error.ends_with("responded with \"HTTP/1.1 404 Not Found\""),
+            "{error}"
+        );
+
+        #[cfg(not(feature = "compression"))]
+        {
+            let url = serve(b"HTTP/1.1 200 OK\r\n\r\n\x1f\x8b\x08");
+            let error = open(&url).err().unwrap().to_string();
+            assert!(
+                error.ends_with(
+                    "is gzip compressed, decompress it first \
+                     or build with `--features compression` to read it"
+                ),
+                "{error}"
+            );
+        }
+        #[cfg(feature = "compression")]
+        {
+            use flate2::{write::GzEncoder, Compression};
+
+            let mut response =
+                GzEncoder::new(b"HTTP/1.1 200 OK\r\n\r\n".to_vec(), Compression::default());
+            response.write_all(b"a;1.0\nb;2.0\n").unwrap();
+            let url = serve(response.finish().unwrap().leak());
+            let mut body = String::new();
+            open(&url).unwrap().read_to_string(&mut body).unwrap();
+            assert_eq!(body, "a;1.0\nb;2.0\n");
+        }
+
+        match open("https://example.com/data.txt") {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), std::io::ErrorKind::Unsupported),
+            Err(other) => panic!("expected an unsupported error, got {other:?}"),
+            Ok(_) => panic!("expected an unsupported error"),
+        }
+    }
+}
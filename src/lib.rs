@@ -0,0 +1,4020 @@
+use std::{
+    fmt::Display,
+    fs::{metadata, read_dir, File},
+    io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    mem::ManuallyDrop,
+    num::{NonZeroU64, NonZeroUsize},
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::{from_utf8, from_utf8_unchecked},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::{builder::NonEmptyStringValueParser, Parser, ValueEnum};
+use crossbeam::channel::{bounded, unbounded, Receiver, RecvTimeoutError, SendError, Sender};
+use hashbrown::{hash_map::Entry, HashMap};
+
+mod bench;
+mod compare;
+mod dedup;
+mod error;
+mod explain;
+mod external;
+#[cfg(feature = "compression")]
+mod gzip;
+mod hash;
+mod histogram;
+mod http;
+mod merge;
+mod mmap;
+mod parse;
+mod perf;
+mod profile;
+mod query;
+mod results;
+mod sink;
+mod stream;
+mod tail;
+mod utf8;
+mod window;
+
+pub use compare::{parse_result, Stats};
+pub use error::Error;
+use parse::{parse_city, parse_city_bytes};
+pub use parse::{
+    parse_int, parse_line, parse_line_bytes, parse_row, parse_row_bytes, parse_value, ColumnOrder,
+    DecimalSeparator, NumericFormat, ParseOptions, Separator, Value, ValueType,
+};
+pub use results::{CityStats, ResultSink, Results, TextSink};
+pub use sink::RowSink;
+
+use compare::Tolerance;
+use dedup::RepeatedValues;
+use external::SpillingMap;
+use histogram::Histograms;
+use profile::Profile;
+pub use sink::CityMap;
+use sink::{CityNames, IntCityMap, SortedCityMap};
+
+#[derive(Debug, Clone, Parser)]
+pub struct Args {
+    /// The input files to process.
+    ///
+    /// Directories are expanded to all `*.txt` files they contain. `-` reads the
+    /// rows from stdin and an `http://` url reads them from the response, both
+    /// like a single input with `--emit-every`. Only plain `http://` works, `https://`
+    /// urls are rejected and redirects are not followed. With the `compression` feature, zstd
+    /// and gzip compressed input is read the same way, and the members of bgzip
+    /// input are decompressed by up to `--threads` workers.
+    #[arg(required_unless_present_any = ["compare", "merge_results"])]
+    pub input: Vec<PathBuf>,
+
+    /// Also include `*.txt` files in subdirectories of input directories
+    #[arg(long)]
+    pub recursive: bool,
+
+    #[arg(short, long, default_value_t = 8)]
+    pub threads: usize,
+
+    /// Skip malformed lines instead of failing on the first one.
+    ///
+    /// Lines without a separator, with an empty city name like `;12.3` or with an
+    /// invalid value are malformed. The number of skipped lines is reported on stderr.
+    #[arg(long)]
+    pub lenient: bool,
+
+    /// The forms of values that are accepted
+    #[arg(long, value_enum, default_value_t)]
+    pub numeric_format: NumericFormat,
+
+    /// Enforce the rules of the original challenge and print its exact output.
+    ///
+    /// Rows must be `city;value` with a value in [-99.9, 99.9] with exactly one
+    /// decimal, city names must have 1 to 100 bytes and there must be at most
+    /// 10,000 cities. Values are summed exactly and rounded half up like the
+    /// reference implementation, and cities are sorted by name.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "numeric_format", "value_type", "separator", "decimal_separator", "column_order",
+            "city_column", "order",
+            "normalize_names", "expect_min", "expect_max", "top", "bottom", "keys_only",
+            "histogram", "interactive", "external_merge", "show_stddev", "show_count",
+            "limit_cities", "dedup_values",
+        ]
+    )]
+    pub spec_strict: bool,
+
+    /// Only print the distinct city names, sorted and one per line.
+    ///
+    /// Values are not parsed, so this is faster than computing the statistics.
+    /// Always uses the chunked engine.
+    #[arg(long, conflicts_with_all = ["top", "bottom"])]
+    pub keys_only: bool,
+
+    /// How the values are interpreted.
+    ///
+    /// Integer values are aggregated without rounding and the result is printed
+    /// without decimals. Only supported by the chunked engine for text input.
+    #[arg(long, value_enum, default_value_t)]
+    pub value_type: ValueType,
+
+    /// The character between the city name and the value.
+    ///
+    /// Either a single ascii character, `tab` or `auto`. `auto` uses the first of
+    /// `;`, `,` and tab that appears in the first line of the first input file.
+    #[arg(long, default_value = ";")]
+    pub separator: Separator,
+
+    /// The character between the integer and the fractional digits of a value.
+    ///
+    /// Must be different from `--separator`, `auto` never detects it as the separator.
+    #[arg(long, value_enum, default_value_t)]
+    pub decimal_separator: DecimalSeparator,
+
+    /// The order of the city and the value in a row. Ignored for binary input.
+    #[arg(long, value_enum, default_value_t)]
+    pub column_order: ColumnOrder,
+
+    /// The index of the city field in rows with more than two fields, starting at 0.
+    ///
+    /// Replaces `--column-order` and requires `--value-column`. Every row needs at
+    /// least as many fields as the first line of the input, and city names cannot
+    /// contain the separator.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "value_column",
+        conflicts_with = "column_order"
+    )]
+    pub city_column: Option<usize>,
+
+    /// The index of the value field in rows with more than two fields, starting at 0.
+    ///
+    /// Requires `--city-column`.
+    #[arg(long, value_name = "N", requires = "city_column")]
+    pub value_column: Option<usize>,
+
+    /// The index of a field with integer timestamps, like unix seconds, starting at 0.
+    ///
+    /// Only used by `--window`. Requires `--city-column` and `--value-column`.
+    #[arg(long, value_name = "N", requires = "city_column")]
+    pub timestamp_column: Option<usize>,
+
+    /// Only aggregate the rows of the last SECS seconds before the latest timestamp
+    /// of the input, including it.
+    ///
+    /// The input is read twice, first to find the latest timestamp. Rows outside
+    /// of the window are skipped without counting them as malformed, rows without
+    /// a valid timestamp are malformed. Requires `--timestamp-column` and text input.
+    #[arg(
+        long,
+        value_name = "SECS",
+        requires = "timestamp_column",
+        conflicts_with_all = ["offset", "length", "tail", "expect_lines", "spec_strict"]
+    )]
+    pub window: Option<NonZeroU64>,
+
+    /// The size in bytes of the buffer the result is written through.
+    ///
+    /// The result is written in many small pieces, one or more per city, so they
+    /// are collected in a buffer before they are written to the output.
+    #[arg(long, value_name = "BYTES", default_value = "65536")]
+    pub output_buffer_size: NonZeroUsize,
+
+    /// Print additional information, like the detected separator, on stderr
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Print how the input is going to be processed on stderr before processing it.
+    ///
+    /// Shows the options as they are resolved for this run, like the engine, the
+    /// number of workers, the block and channel sizes, the detected separator and
+    /// what the workers aggregate.
+    #[arg(long)]
+    pub explain: bool,
+
+    /// Print the FNV-1a hash of the input instead of processing it.
+    ///
+    /// This is the same hash the generator prints for its output, to check that
+    /// the input is exactly what was generated.
+    #[arg(long, conflicts_with_all = ["offset", "length"])]
+    pub hash_input: bool,
+
+    /// Print the city and value of the last N lines of the last input file instead
+    /// of processing it.
+    ///
+    /// Only reads the end of the file, to quickly check whether a large or
+    /// received file ends cleanly. Malformed lines and a missing newline at the end
+    /// of the file are reported.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["hash_input", "offset", "length", "keys_only", "histogram", "dedup_values"]
+    )]
+    pub tail: Option<usize>,
+
+    /// Only process the lines that start at or after this byte offset.
+    ///
+    /// Requires a single text input file and the chunked engine.
+    #[arg(long, value_name = "BYTES")]
+    pub offset: Option<u64>,
+
+    /// Only process the lines that start before `--offset` plus this many bytes.
+    ///
+    /// Requires a single text input file and the chunked engine.
+    #[arg(long, value_name = "BYTES")]
+    pub length: Option<u64>,
+
+    /// Feed the chunks to the workers from the end of the input to its start.
+    ///
+    /// A debugging aid to check that the result does not depend on the order in
+    /// which chunks are processed. Requires text input and the chunked engine.
+    #[arg(long)]
+    pub reverse: bool,
+
+    /// Grow the blocks that are read while the workers wait for them.
+    ///
+    /// Blocks start at the fixed block size and double up to 1 MiB whenever the
+    /// channel to the workers is empty, and halve again whenever it is full, so the
+    /// workers are kept busy without holding more memory than needed. Requires the
+    /// chunked engine.
+    #[arg(long, conflicts_with = "reverse")]
+    pub adaptive_block_size: bool,
+
+    /// Read all input files once before processing them.
+    ///
+    /// This loads the input into the page cache, so that benchmarks measure the
+    /// processing instead of the disk. With `--verbose` the time spent processing
+    /// the input is reported without the priming.
+    #[arg(long)]
+    pub prime_cache: bool,
+
+    /// Print a table of the N cities with the highest mean instead of the challenge output
+    #[arg(long, value_name = "N")]
+    pub top: Option<usize>,
+
+    /// Print a table of the N cities with the lowest mean instead of the challenge output
+    #[arg(long, value_name = "N")]
+    pub bottom: Option<usize>,
+
+    /// Print how the values of each city are distributed over N equal-width bins
+    /// instead of the challenge output.
+    ///
+    /// The bins span `--expect-min` to `--expect-max`, or the challenge range of
+    /// -99.9 to 99.9 if those are not set. Values outside of the range are counted
+    /// in the first or last bin.
+    #[arg(long, value_name = "N", conflicts_with_all = ["top", "bottom", "keys_only"])]
+    pub histogram: Option<NonZeroUsize>,
+
+    /// Print the cities where a single value makes up more than
+    /// `--dedup-threshold` of the rows instead of the challenge output.
+    ///
+    /// Finds stuck sensors or a broken generator. Values are counted exactly in
+    /// tenths from `--expect-min` to `--expect-max`, or from -99.9 to 99.9 if
+    /// those are not set. Cities with fewer than 10 rows are not reported.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "top", "bottom", "keys_only", "histogram", "external_merge", "interactive",
+            "show_stddev", "limit_cities",
+        ]
+    )]
+    pub dedup_values: bool,
+
+    /// The share of the rows of a city above which a repeated value is reported
+    /// by `--dedup-values`
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        default_value_t = 0.1,
+        requires = "dedup_values"
+    )]
+    pub dedup_threshold: f64,
+
+    /// Skip the utf8 validation of the input and parse lines as raw bytes.
+    ///
+    /// Values are expected to be ascii. City names are only validated once
+    /// per distinct city after all lines are processed.
+    #[arg(long)]
+    pub assume_ascii: bool,
+
+    /// Validate the input as utf8 in a single pass before processing it.
+    ///
+    /// Processing then skips the validation of every chunk like `--assume-ascii`,
+    /// so only city names are validated again. Requires text input.
+    #[arg(long)]
+    pub validate_utf8_once: bool,
+
+    /// Merge the cities through sorted run files in DIR, for more distinct cities
+    /// than fit into memory.
+    ///
+    /// Every worker holds at most `--spill-cities` cities and writes them to a
+    /// sorted run file once it is full. The runs are merged into the sorted
+    /// challenge output at the end and removed. Requires the chunked engine,
+    /// float values and the default `--order` and `--normalize-names`.
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["top", "bottom", "keys_only", "histogram", "interactive", "snapshot_every"]
+    )]
+    pub external_merge: Option<PathBuf>,
+
+    /// Merge the cities in maps that are sorted by name, and write the result while
+    /// walking the merged map.
+    ///
+    /// Avoids holding all city names in a second, sorted list at the end. This is
+    /// a memory tradeoff, not a speedup: inserting into sorted maps is slower than
+    /// into hash maps, so the whole run usually takes longer than sorting the names
+    /// once. Requires the chunked engine, float values and the default `--order`
+    /// and `--normalize-names`.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "top", "bottom", "keys_only", "histogram", "dedup_values", "interactive",
+            "external_merge", "spec_strict", "limit_cities", "group_by_prefix", "sort_order",
+        ]
+    )]
+    pub sorted_merge: bool,
+
+    /// The number of cities a worker holds before spilling them with `--external-merge`
+    #[arg(long, default_value = "1000000", requires = "external_merge")]
+    pub spill_cities: NonZeroUsize,
+
+    /// Print counters of the reading pipeline on stderr when done.
+    ///
+    /// Shows how many chunks each worker processed and how full the channel
+    /// between the reader and the workers was, to tell whether reading or
+    /// processing is the bottleneck. Only supported by the chunked engine.
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Measure a single phase of processing instead of printing the result.
+    ///
+    /// `parse` reads and parses every line but discards the rows. `aggregate` parses
+    /// the whole input into memory first and then only measures adding the rows to a
+    /// city map per worker. Comparing both shows whether parsing or hashing dominates.
+    /// Always uses the chunked reader, and `aggregate` needs the memory for all rows.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "PHASE",
+        conflicts_with_all = [
+            "top", "bottom", "keys_only", "histogram", "dedup_values", "external_merge",
+            "interactive", "spec_strict", "hash_input", "tail",
+        ]
+    )]
+    pub bench: Option<BenchPhase>,
+
+    /// Append the standard deviation of every city to the challenge output,
+    /// as `city=min/mean/max/stddev`
+    #[arg(long, conflicts_with_all = ["top", "bottom", "keys_only", "histogram"])]
+    pub show_stddev: bool,
+
+    /// Append the number of rows of every city to the challenge output, as
+    /// `city=min/mean/max/count`
+    ///
+    /// Results with counts can be combined with `--merge-results`.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "top", "bottom", "keys_only", "histogram", "show_stddev", "interactive",
+            "external_merge", "dedup_values",
+        ]
+    )]
+    pub show_count: bool,
+
+    /// Only show the N cities with the most rows and fold all other cities into a
+    /// single `(other)` entry at the end of the output.
+    ///
+    /// `(other)` has the lowest and highest value and the mean of all rows of the
+    /// folded cities.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["top", "bottom", "keys_only", "histogram", "external_merge"]
+    )]
+    pub limit_cities: Option<usize>,
+
+    /// Print a second line with the cities grouped by the part of their name before
+    /// SEP, e.g. `DE` for `DE-Hamburg` with `--group-by-prefix=-`.
+    ///
+    /// A group has the lowest and highest value and the mean of all rows of its
+    /// cities, in the format and order of the cities. Cities without SEP are not part
+    /// of any group, and groups include the cities folded by `--limit-cities`.
+    #[arg(
+        long,
+        value_name = "SEP",
+        value_parser = NonEmptyStringValueParser::new(),
+        conflicts_with_all = [
+            "top", "bottom", "keys_only", "histogram", "dedup_values", "external_merge",
+            "interactive", "spec_strict",
+        ]
+    )]
+    pub group_by_prefix: Option<String>,
+
+    /// Answer queries on stdin instead of printing the result.
+    ///
+    /// Each line is either a city name, which prints the values of that city, or
+    /// a filter like `mean>30` on `min`, `mean`, `max`, `stddev` or `count` with
+    /// `<`, `<=`, `>`, `>=` or `=`, which prints all matching cities. Stops at
+    /// the end of the input or at `quit`.
+    #[arg(
+        long,
+        conflicts_with_all = ["top", "bottom", "keys_only", "histogram", "hash_input"]
+    )]
+    pub interactive: bool,
+
+    /// The order of the cities in the output
+    #[arg(long, value_enum, default_value_t)]
+    pub order: Order,
+
+    /// How city names are compared when the output is sorted.
+    ///
+    /// Only affects the challenge output and `--interactive`.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = ["spec_strict", "external_merge", "keys_only"]
+    )]
+    pub sort_order: SortOrder,
+
+    /// The format of the input files
+    #[arg(long, value_enum, default_value_t)]
+    pub format: Format,
+
+    /// The strategy used to read and process the input
+    #[arg(long, value_enum, default_value_t)]
+    pub engine: Engine,
+
+    /// Fail if any value is lower than this.
+    ///
+    /// Requires `--expect-max`.
+    #[arg(long, requires = "expect_max", allow_negative_numbers = true)]
+    pub expect_min: Option<f32>,
+
+    /// Fail if any value is higher than this.
+    ///
+    /// Requires `--expect-min`.
+    #[arg(long, requires = "expect_min", allow_negative_numbers = true)]
+    pub expect_max: Option<f32>,
+
+    /// Fail if any line is shorter than this many bytes, without its line ending.
+    ///
+    /// Catches lines that are cut off, like the last line of a truncated file. Applies
+    /// even with `--lenient`.
+    #[arg(long, value_name = "BYTES")]
+    pub min_line_length: Option<usize>,
+
+    /// Fail if any line is longer than this many bytes, without its line ending.
+    ///
+    /// A line of the challenge has at most 106 bytes, a name of up to 100 bytes, the
+    /// separator and a value like `-99.9`. Applies even with `--lenient`.
+    #[arg(long, value_name = "BYTES")]
+    pub max_line_length: Option<usize>,
+
+    /// Fail if the input does not have exactly N lines.
+    ///
+    /// The rows of all cities and the skipped malformed lines are counted after
+    /// all partial results are combined, so this catches chunks or partial results
+    /// that got lost on the way. The generator prints the number of rows it wrote.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with_all = ["hash_input", "tail", "keys_only", "histogram", "dedup_values", "external_merge"]
+    )]
+    pub expect_lines: Option<u64>,
+
+    /// How city names are normalized before cities are grouped
+    #[arg(long, value_enum, default_value_t)]
+    pub normalize_names: NormalizeNames,
+
+    /// Compare two result files instead of processing any input.
+    ///
+    /// Prints every city whose min, mean or max differ by more than
+    /// `--tolerance` and fails if any city differs.
+    #[arg(long, num_args = 2, value_names = ["FIRST", "SECOND"], conflicts_with = "input")]
+    pub compare: Option<Vec<PathBuf>>,
+
+    /// The largest difference that is still accepted by `--compare`
+    #[arg(long, default_value_t = 0.0, requires = "compare")]
+    pub tolerance: f32,
+
+    /// Accept a difference of one unit in the last of this many decimals in `--compare`.
+    ///
+    /// Covers values that were rounded differently, e.g. `1.05` printed as `1.0`
+    /// or `1.1`, but nothing more. Such cities are still printed as rounding
+    /// differences, only larger differences fail the comparison.
+    #[arg(long, requires = "compare", conflicts_with = "tolerance")]
+    pub decimals: Option<u8>,
+
+    /// Also fail `--compare` for cities that only differ by rounding in `--decimals`.
+    ///
+    /// For once both results are summed the same way and must match exactly.
+    #[arg(long, requires = "decimals")]
+    pub fail_on_rounding: bool,
+
+    /// Merge result files of independently processed shards into a single result
+    /// instead of processing any input.
+    ///
+    /// The results must be written with `--show-count`. Mins and maxes are combined
+    /// exactly, the means are weighted by the number of rows of their city. As the
+    /// printed means are rounded, the merged means can be off by the rounding of a
+    /// single result.
+    #[arg(
+        long,
+        num_args = 1..,
+        value_name = "RESULT",
+        conflicts_with_all = ["input", "compare"]
+    )]
+    pub merge_results: Option<Vec<PathBuf>>,
+
+    /// Periodically write the partial result to `--snapshot-file`.
+    ///
+    /// Snapshots only contain the rows processed so far and lag behind by up to
+    /// one interval. Only supported by the chunked engine.
+    #[arg(long, value_name = "SECS", requires = "snapshot_file")]
+    pub snapshot_every: Option<NonZeroU64>,
+
+    /// The file that is replaced with every snapshot
+    #[arg(long, requires = "snapshot_every")]
+    pub snapshot_file: Option<PathBuf>,
+
+    /// Print the result of all rows read so far every SECS seconds, each on its
+    /// own line, and once more at the end of the input.
+    ///
+    /// For a single input that is fed continuously, like stdin (`-`) or a named
+    /// pipe. Rows are aggregated as soon as their line is complete, by a single
+    /// thread, so this is slower than reading a file.
+    #[arg(
+        long,
+        value_name = "SECS",
+        requires = "input",
+        conflicts_with = "snapshot_every"
+    )]
+    pub emit_every: Option<NonZeroU64>,
+
+    /// Stop reading the input after SECS seconds and print the result of the rows
+    /// read so far.
+    ///
+    /// For a fast approximate result of a huge input. The chunks that were already
+    /// read are still processed, and a note on stderr marks the result as partial
+    /// if the input was not read completely. Only supported by the chunked engine.
+    #[arg(long, value_name = "SECS", conflicts_with = "expect_lines")]
+    pub max_seconds: Option<NonZeroU64>,
+
+    /// Measure the throughput of processing the input and fail if it is more than
+    /// `--perf-tolerance` percent below the baseline in this file.
+    ///
+    /// The file contains a single throughput in MB/s, which `--update-baseline`
+    /// writes. The median of `--perf-runs` runs after `--perf-warmup` unmeasured
+    /// runs is compared, so a single slow run does not fail the check. The output
+    /// of the runs is discarded.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["interactive", "emit_every", "snapshot_every", "tail", "hash_input"]
+    )]
+    pub perf_baseline: Option<PathBuf>,
+
+    /// The number of measured runs of `--perf-baseline`
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "5",
+        requires = "perf_baseline"
+    )]
+    pub perf_runs: NonZeroUsize,
+
+    /// The number of runs before the measured runs of `--perf-baseline`
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        requires = "perf_baseline"
+    )]
+    pub perf_warmup: usize,
+
+    /// How many percent the throughput may be below the baseline of `--perf-baseline`
+    #[arg(
+        long,
+        value_name = "PERCENT",
+        default_value_t = 5.0,
+        requires = "perf_baseline"
+    )]
+    pub perf_tolerance: f64,
+
+    /// Write the measured throughput to the `--perf-baseline` file instead of
+    /// comparing it
+    #[arg(long, requires = "perf_baseline")]
+    pub update_baseline: bool,
+}
+
+/// The phase measured by `--bench`
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Eq)]
+pub enum BenchPhase {
+    /// Read and parse every line without aggregating it
+    Parse,
+    /// Add rows that were parsed into memory before to the city maps
+    Aggregate,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Read the input in blocks that are distributed to the workers
+    #[default]
+    Chunked,
+    /// Memory map the input and borrow city names from the mapping.
+    ///
+    /// Only supports text input and does not validate the input as utf8
+    /// except for the city names.
+    MmapZerocopy,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One `city;value` row per line
+    #[default]
+    Text,
+    /// Length prefixed binary records as written by the generator:
+    /// `u8` name length, the name bytes and the value in tenths as a little endian `i16`
+    Binary,
+}
+
+impl Args {
+    /// The `--offset` and `--length` of the processed range, if any is set
+    fn byte_range(&self) -> Option<(u64, u64)> {
+        if self.offset.is_none() && self.length.is_none() {
+            return None;
+        }
+        Some((self.offset.unwrap_or(0), self.length.unwrap_or(u64::MAX)))
+    }
+
+    /// The `--snapshot-file` and `--snapshot-every` interval, if snapshots are enabled
+    fn snapshot_target(&self) -> Option<(&Path, Duration)> {
+        let every = Duration::from_secs(self.snapshot_every?.get());
+        Some((self.snapshot_file.as_deref()?, every))
+    }
+
+    /// Whether chunks are read as raw bytes without validating them as utf8
+    fn skip_utf8_validation(&self) -> bool {
+        self.assume_ascii || self.validate_utf8_once
+    }
+
+    fn parse_options(&self, separator: u8) -> ParseOptions {
+        if self.spec_strict {
+            return ParseOptions {
+                lenient: self.lenient,
+                numeric_format: NumericFormat::Strict,
+                value_type: ValueType::Tenths,
+                column_order: ColumnOrder::CityValue,
+                columns: None,
+                separator: b';',
+                decimal_separator: b'.',
+                bounds: Some((-999.0, 999.0)),
+                line_length: self.line_length(),
+                window: None,
+            };
+        }
+        ParseOptions {
+            lenient: self.lenient,
+            numeric_format: self.numeric_format,
+            value_type: self.value_type,
+            column_order: self.column_order,
+            columns: self.city_column.zip(self.value_column),
+            separator,
+            decimal_separator: self.decimal_separator.as_byte(),
+            bounds: self.expect_min.zip(self.expect_max),
+            line_length: self.line_length(),
+            // the window starts at a timestamp of the input, see `window::resolve`
+            window: None,
+        }
+    }
+
+    /// The inclusive range of `--min-line-length` and `--max-line-length`
+    fn line_length(&self) -> (usize, usize) {
+        (
+            self.min_line_length.unwrap_or(0),
+            self.max_line_length.unwrap_or(usize::MAX),
+        )
+    }
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// Sort cities by name
+    #[default]
+    Sorted,
+    /// Keep the order in which cities first appear in the input
+    FirstSeen,
+}
+
+/// How city names are compared for `--order sorted`
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Compare the bytes of the names, as the challenge expects
+    #[default]
+    Bytewise,
+    /// Compare the names like a person would, e.g. "äpfel" before "Zürich".
+    ///
+    /// A simplified collation: case and the diacritics of Latin letters are
+    /// ignored, other characters are compared by code point. Names that are
+    /// equal this way are sorted by their bytes.
+    Unicode,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NormalizeNames {
+    /// Group cities by their exact name
+    #[default]
+    None,
+    /// Group cities by their lowercase name, e.g. "Hamburg" and "hamburg".
+    ///
+    /// The output uses the lowercase names.
+    Lower,
+    /// Group cities by their lowercase name, like `lower`.
+    ///
+    /// The output uses the casing that appears first in the input. This only
+    /// depends on the position in the input, not on which worker finishes first.
+    FirstSeen,
+}
+
+/// The aggregated values of a single city.
+#[derive(Debug, Clone)]
+pub struct CityEntry {
+    min: f32,
+    max: f32,
+    sum: f32,
+    /// The sum of the squared values, used for the standard deviation.
+    ///
+    /// Summed as `f64`, as the squares of a billion values exceed the precision
+    /// of `f32` by far.
+    sum_squares: f64,
+    /// The number of values of this city.
+    ///
+    /// This limits the supported input to `usize::MAX` rows per city, which is
+    /// only reachable on 32 bit targets (about 4.3 billion rows). An overflow is
+    /// recorded in `overflowed` and reported by [CityEntry::check_count].
+    count: usize,
+    /// Whether `count` overflowed while adding or merging values
+    overflowed: bool,
+    /// The chunk index and line within that chunk where the city first appeared
+    first_seen: (usize, usize),
+}
+
+impl Default for CityEntry {
+    /// An entry without any values.
+    ///
+    /// `min` and `max` start out as an empty range, so that the first value
+    /// added sets both of them.
+    fn default() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            sum: 0.0,
+            sum_squares: 0.0,
+            count: 0,
+            overflowed: false,
+            first_seen: (0, 0),
+        }
+    }
+}
+
+impl CityEntry {
+    /// The lowest value of the city
+    pub fn min(&self) -> f32 {
+        self.min
+    }
+
+    /// The highest value of the city
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    /// The mean of all values of the city, or NaN if the city has no values
+    pub fn mean(&self) -> f64 {
+        self.sum as f64 / self.count as f64
+    }
+
+    /// The population standard deviation of all values of the city
+    pub fn stddev(&self) -> f64 {
+        let mean = self.mean();
+        // rounding can make the variance of nearly constant values slightly negative
+        (self.sum_squares / self.count as f64 - mean * mean)
+            .max(0.0)
+            .sqrt()
+    }
+
+    /// The number of values of the city
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Fails if the city called `name` has more than `usize::MAX` values.
+    pub(crate) fn check_count(&self, name: &str) -> Result<(), Error> {
+        check_count(self.overflowed, name)
+    }
+
+    fn add(&mut self, value: f32) {
+        self.sum += value;
+        self.sum_squares += value as f64 * value as f64;
+        let (count, overflowed) = self.count.overflowing_add(1);
+        self.count = count;
+        self.overflowed |= overflowed;
+        if self.max < value {
+            self.max = value;
+        }
+        if self.min > value {
+            self.min = value;
+        }
+    }
+
+    fn merge(&mut self, other: &CityEntry) {
+        self.sum += other.sum;
+        self.sum_squares += other.sum_squares;
+        let (count, overflowed) = self.count.overflowing_add(other.count);
+        self.count = count;
+        self.overflowed |= overflowed || other.overflowed;
+        if self.min > other.min {
+            self.min = other.min;
+        }
+        if self.max < other.max {
+            self.max = other.max;
+        }
+        if self.first_seen > other.first_seen {
+            self.first_seen = other.first_seen;
+        }
+    }
+}
+
+/// Fails if the count of the city called `name` `overflowed`.
+pub(crate) fn check_count(overflowed: bool, name: &str) -> Result<(), Error> {
+    match overflowed {
+        true => Err(Error::MalformedData(format!(
+            "more than usize::MAX rows for city {name:?}"
+        ))),
+        false => Ok(()),
+    }
+}
+
+pub const BLOCK_SIZE: usize = 4096;
+
+/// The largest block `--adaptive-block-size` grows to
+const MAX_BLOCK_SIZE: usize = 1024 * 1024;
+
+/// The number of blocks that are read ahead of the workers
+const CHANNEL_CAPACITY: usize = 10;
+
+/// The utf8 byte order mark some tools write at the start of text files
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+type Block = Box<[u8]>;
+
+fn new_block(size: usize) -> Block {
+    vec![0u8; size].into_boxed_slice()
+}
+
+/// Blocks of dropped chunks, which are reused for the next chunks.
+///
+/// Only as many blocks are allocated as there are chunks alive at the same time,
+/// instead of one per chunk.
+struct BlockPool {
+    returned: Sender<Block>,
+    free: Receiver<Block>,
+}
+
+impl BlockPool {
+    fn new() -> Self {
+        let (returned, free) = unbounded();
+        BlockPool { returned, free }
+    }
+
+    /// A returned block or a new one if none was returned yet
+    fn take(&self) -> Block {
+        self.take_sized(BLOCK_SIZE)
+    }
+
+    /// A returned block of `size` bytes or a new one if none was returned yet.
+    ///
+    /// Returned blocks of another size are freed, they are left over from before
+    /// the block size changed.
+    fn take_sized(&self, size: usize) -> Block {
+        while let Ok(block) = self.free.try_recv() {
+            if block.len() == size {
+                return block;
+            }
+        }
+        new_block(size)
+    }
+}
+
+/// The size of the next block to read with `--adaptive-block-size`.
+///
+/// Workers that find the channel empty are waiting for the reader, so the next
+/// block is twice as large to feed them more rows per chunk. A full channel means
+/// the workers are the bottleneck, so the blocks shrink again to hold less memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AdaptiveBlockSize {
+    size: usize,
+}
+
+impl AdaptiveBlockSize {
+    fn new() -> Self {
+        AdaptiveBlockSize { size: BLOCK_SIZE }
+    }
+
+    /// Adapts the size to `queued` chunks in a channel of `capacity` chunks, where
+    /// an unbounded channel has no capacity.
+    fn adapt(&mut self, queued: usize, capacity: Option<usize>) -> usize {
+        if queued == 0 {
+            self.size = (self.size * 2).min(MAX_BLOCK_SIZE);
+        } else if capacity.is_some_and(|capacity| queued >= capacity) {
+            self.size = (self.size / 2).max(BLOCK_SIZE);
+        }
+        self.size
+    }
+}
+
+pub struct StrBuffer {
+    raw_data: ManuallyDrop<Block>,
+    size: usize,
+    utf8: bool,
+    /// The position of this chunk in the input, used to track the order of first appearance
+    index: usize,
+    /// Where `raw_data` is returned once the chunk is dropped
+    pool: Option<Sender<Block>>,
+}
+
+impl StrBuffer {
+    /// Reads the next block of complete lines from `read`.
+    ///
+    /// The reader is left directly after the last newline in the block.
+    /// The last line of the input does not need to end in a newline.
+    pub fn read_from<R: Read + Seek>(read: &mut R) -> io::Result<Option<Self>> {
+        Self::read_block(read, new_block(BLOCK_SIZE), true)
+    }
+
+    /// Same as [StrBuffer::read_from] but without validating that the data is utf8.
+    ///
+    /// The resulting buffer can only be accessed using [StrBuffer::as_bytes].
+    pub fn read_bytes_from<R: Read + Seek>(read: &mut R) -> io::Result<Option<Self>> {
+        Self::read_block(read, new_block(BLOCK_SIZE), false)
+    }
+
+    /// Reads the next block of complete binary records from `read`.
+    ///
+    /// See [Format::Binary] for the record layout. The data is not validated as utf8.
+    pub fn read_records_from<R: Read + Seek>(read: &mut R) -> io::Result<Option<Self>> {
+        Self::read_records(read, new_block(BLOCK_SIZE))
+    }
+
+    fn read_text(read: &mut FileRange, raw_data: Block) -> io::Result<Option<Self>> {
+        read.skip_bom()?;
+        Self::read_block(read, raw_data, true)
+    }
+
+    fn read_ascii(read: &mut FileRange, raw_data: Block) -> io::Result<Option<Self>> {
+        read.skip_bom()?;
+        Self::read_block(read, raw_data, false)
+    }
+
+    fn read_records<R: Read + Seek>(read: &mut R, mut raw_data: Block) -> io::Result<Option<Self>> {
+        let full_size = Self::fill(read, &mut raw_data)?;
+
+        if full_size == 0 {
+            return Ok(None);
+        }
+
+        let mut size = 0;
+        while let Some(&name_len) = raw_data[..full_size].get(size) {
+            let record_len = 1 + name_len as usize + 2;
+            if size + record_len > full_size {
+                break;
+            }
+            size += record_len;
+        }
+
+        if size == 0 {
+            // a record is at most 258 bytes, so this can only happen at the end of the input
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "input ends with a truncated record",
+            ));
+        }
+
+        if size < full_size {
+            read.seek(SeekFrom::Current(-((full_size - size) as i64)))?;
+        }
+
+        Ok(Some(StrBuffer {
+            raw_data: ManuallyDrop::new(raw_data),
+            size,
+            utf8: false,
+            index: 0,
+            pool: None,
+        }))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw_data[0..self.size]
+    }
+
+    /// Reads from `read` until `buffer` is full or the end of the input is reached.
+    fn fill<R: Read>(read: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+        let mut full_size = 0;
+        while full_size < buffer.len() {
+            match read.read(&mut buffer[full_size..]) {
+                Ok(0) => break,
+                Ok(read_size) => full_size += read_size,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(full_size)
+    }
+
+    fn read_block<R: Read + Seek>(
+        read: &mut R,
+        mut raw_data: Block,
+        validate_utf8: bool,
+    ) -> io::Result<Option<Self>> {
+        let full_size = Self::fill(read, &mut raw_data)?;
+
+        if full_size == 0 {
+            return Ok(None);
+        }
+
+        let size = if full_size < raw_data.len() {
+            // we hit the end of the input, so the rest belongs to this chunk
+            full_size
+        } else {
+            newline_aligned_boundary(&raw_data[0..full_size], true).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "line is longer than the block size of {} bytes",
+                        raw_data.len()
+                    ),
+                )
+            })?
+        };
+
+        if validate_utf8 {
+            from_utf8(&raw_data[0..size]).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+        }
+
+        if size < full_size {
+            read.seek(SeekFrom::Current(-((full_size - size) as i64)))?;
+        }
+
+        Ok(Some(StrBuffer {
+            raw_data: ManuallyDrop::new(raw_data),
+            size,
+            utf8: validate_utf8,
+            index: 0,
+            pool: None,
+        }))
+    }
+}
+
+impl Drop for StrBuffer {
+    fn drop(&mut self) {
+        // Safety: raw_data is never accessed again
+        let raw_data = unsafe { ManuallyDrop::take(&mut self.raw_data) };
+        if let Some(pool) = &self.pool {
+            // the producer might be done already, in which case the block is freed
+            let _ = pool.send(raw_data);
+        }
+    }
+}
+
+impl Deref for StrBuffer {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        assert!(self.utf8, "StrBuffer was read without utf8 validation");
+        let chunk = &self.raw_data[0..self.size];
+        debug_assert!(from_utf8(chunk).is_ok());
+        // Safety: we check that this is a valid utf8 str when we create the TextChunk
+        unsafe { from_utf8_unchecked(chunk) }
+    }
+}
+
+/// Expands directories in `paths` to the `*.txt` files they contain.
+///
+/// Files inside a directory are processed in sorted order. Subdirectories are only
+/// searched if `recursive` is set. Fails for inputs that are not regular files, like
+/// devices or pipes, which might never end. Missing files are reported once they
+/// are opened.
+fn collect_input_files(paths: &[PathBuf], recursive: bool) -> io::Result<Vec<PathBuf>> {
+    fn visit_dir(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        let mut entries = read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort_unstable();
+
+        for path in entries {
+            if path.is_dir() {
+                if recursive {
+                    visit_dir(&path, recursive, files)?;
+                }
+            } else if path.extension().is_some_and(|ext| ext == "txt") {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        if path.is_dir() {
+            visit_dir(path, recursive, &mut files)?;
+        } else {
+            files.push(path.clone());
+        }
+    }
+    if let Some(path) = files
+        .iter()
+        .find(|path| path.metadata().is_ok_and(|metadata| !metadata.is_file()))
+    {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{} is not a regular file, use `-` to read from stdin",
+                path.display()
+            ),
+        ));
+    }
+    Ok(files)
+}
+
+/// A file that can only be read up to a given position
+struct FileRange {
+    file: File,
+    position: u64,
+    end: u64,
+}
+
+impl FileRange {
+    /// The whole file
+    fn new(file: File) -> Self {
+        FileRange {
+            file,
+            position: 0,
+            end: u64::MAX,
+        }
+    }
+
+    /// The lines of `file` that start in `[offset, offset + length)`.
+    fn lines(mut file: File, offset: u64, length: u64) -> io::Result<Self> {
+        let start = if offset == 0 {
+            0
+        } else {
+            next_line_start(&mut file, offset - 1)?
+        };
+        let end = match length {
+            0 => start,
+            _ => next_line_start(&mut file, offset.saturating_add(length - 1))?.max(start),
+        };
+        file.seek(SeekFrom::Start(start))?;
+        Ok(FileRange {
+            file,
+            position: start,
+            end,
+        })
+    }
+
+    /// Skips a [BOM] if this is at the start of the file, so it does not become
+    /// part of the first city name.
+    fn skip_bom(&mut self) -> io::Result<()> {
+        if self.position != 0 {
+            return Ok(());
+        }
+        let mut start = Vec::with_capacity(BOM.len());
+        self.by_ref()
+            .take(BOM.len() as u64)
+            .read_to_end(&mut start)?;
+        if start != BOM {
+            self.seek(SeekFrom::Start(0))?;
+        }
+        Ok(())
+    }
+}
+
+/// The position directly after the last newline in `data`, or after the first one
+/// if `from_end` is false.
+///
+/// This is where `data` can be split so that no line is cut in half: with `from_end`
+/// everything before the boundary are complete lines, without it everything after
+/// the boundary starts at the beginning of a line. Returns `None` if `data` contains
+/// no newline.
+pub fn newline_aligned_boundary(data: &[u8], from_end: bool) -> Option<usize> {
+    let newline = if from_end {
+        data.iter().rposition(|&c| c == b'\n')
+    } else {
+        data.iter().position(|&c| c == b'\n')
+    };
+    newline.map(|nl| nl + 1)
+}
+
+/// The position after the first newline at or after `position`, or the end of `file`.
+fn next_line_start(file: &mut File, position: u64) -> io::Result<u64> {
+    let len = file.metadata()?.len();
+    if position >= len {
+        return Ok(len);
+    }
+    file.seek(SeekFrom::Start(position))?;
+
+    let mut buffer = [0; BLOCK_SIZE];
+    let mut start = position;
+    loop {
+        let size = file.read(&mut buffer)?;
+        if size == 0 {
+            return Ok(len);
+        }
+        if let Some(boundary) = newline_aligned_boundary(&buffer[..size], false) {
+            return Ok(start + boundary as u64);
+        }
+        start += size as u64;
+    }
+}
+
+impl Read for FileRange {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.end.saturating_sub(self.position);
+        let limit = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+        let size = self.file.read(&mut buf[..limit])?;
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl Seek for FileRange {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.file.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+type ReadChunk = fn(&mut FileRange, Block) -> io::Result<Option<StrBuffer>>;
+
+/// Sends `chunk` to the workers, recording it in `profile` if the pipeline is profiled.
+fn send_chunk(
+    sender: &Sender<StrBuffer>,
+    chunk: StrBuffer,
+    profile: Option<&Profile>,
+) -> Result<(), SendError<StrBuffer>> {
+    match profile {
+        Some(profile) => {
+            let size = chunk.size;
+            profile.send(sender, chunk, size)
+        }
+        None => sender.send(chunk),
+    }
+}
+
+/// Whether `deadline` is set and has passed, see `--max-seconds`
+fn deadline_passed(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// Reads the input in chunks and sends them to the workers.
+///
+/// Stops early once `deadline` has passed and returns whether it did. With
+/// `adaptive` set the size of every block is adapted to how many chunks are
+/// queued, see [AdaptiveBlockSize].
+fn produce_text_chunks(
+    in_paths: &[PathBuf],
+    byte_range: Option<(u64, u64)>,
+    sender: Sender<StrBuffer>,
+    read_chunk: ReadChunk,
+    profile: Option<&Profile>,
+    deadline: Option<Instant>,
+    adaptive: bool,
+) -> Result<bool, Error> {
+    let pool = BlockPool::new();
+    let mut block_size = AdaptiveBlockSize::new();
+    let mut next_block = || match adaptive {
+        true => pool.take_sized(block_size.adapt(sender.len(), sender.capacity())),
+        false => pool.take(),
+    };
+    let mut index = 0;
+    for in_path in in_paths {
+        let file = File::open(in_path).map_err(|e| Error::from_io(in_path, e))?;
+        let mut file = match byte_range {
+            Some((offset, length)) => {
+                FileRange::lines(file, offset, length).map_err(|e| Error::from_io(in_path, e))?
+            }
+            None => FileRange::new(file),
+        };
+
+        while let Some(mut chunk) =
+            read_chunk(&mut file, next_block()).map_err(|e| Error::from_io(in_path, e))?
+        {
+            if deadline_passed(deadline) {
+                return Ok(true);
+            }
+            chunk.index = index;
+            chunk.pool = Some(pool.returned.clone());
+            index += 1;
+            if send_chunk(&sender, chunk, profile).is_err() {
+                // all workers stopped early, they report their own error
+                return Ok(false);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Same as [produce_text_chunks], but sends the chunks from the end of the input
+/// to its start, see `--reverse`.
+///
+/// Chunks are indexed by their byte position in the input instead of being counted,
+/// so their indices still increase in file order and `--order first-seen` is not
+/// affected.
+fn produce_text_chunks_reversed(
+    in_paths: &[PathBuf],
+    byte_range: Option<(u64, u64)>,
+    sender: Sender<StrBuffer>,
+    read_chunk: ReadChunk,
+    profile: Option<&Profile>,
+    deadline: Option<Instant>,
+) -> Result<bool, Error> {
+    let pool = BlockPool::new();
+    let mut files = Vec::with_capacity(in_paths.len());
+    let mut file_start = 0;
+    for in_path in in_paths {
+        let file = File::open(in_path).map_err(|e| Error::from_io(in_path, e))?;
+        let len = file
+            .metadata()
+            .map_err(|e| Error::from_io(in_path, e))?
+            .len();
+        files.push((in_path, file, file_start, len));
+        file_start += len;
+    }
+
+    for (in_path, file, file_start, len) in files.into_iter().rev() {
+        let io_error = |e| Error::from_io(in_path, e);
+        let mut file = match byte_range {
+            Some((offset, length)) => FileRange::lines(file, offset, length).map_err(io_error)?,
+            None => FileRange::new(file),
+        };
+        let start = file.position;
+        let mut end = file.end.min(len);
+
+        while end > start {
+            // the lines from `chunk_start` to `end` fit into a single block
+            let chunk_start = previous_line_start(&mut file.file, start, end).map_err(io_error)?;
+            file.seek(SeekFrom::Start(chunk_start)).map_err(io_error)?;
+            file.end = end;
+            while let Some(mut chunk) = read_chunk(&mut file, pool.take()).map_err(io_error)? {
+                if deadline_passed(deadline) {
+                    return Ok(true);
+                }
+                chunk.index = (file_start + chunk_start) as usize;
+                chunk.pool = Some(pool.returned.clone());
+                if send_chunk(&sender, chunk, profile).is_err() {
+                    // all workers stopped early, they report their own error
+                    return Ok(false);
+                }
+            }
+            end = chunk_start;
+        }
+    }
+    Ok(false)
+}
+
+/// The start of the first line that begins less than [BLOCK_SIZE] bytes before
+/// `end`, or `start` if that is closer to `end`.
+///
+/// `end` has to be the start of a line or the end of `file`.
+fn previous_line_start(file: &mut File, start: u64, end: u64) -> io::Result<u64> {
+    let block_start = end.saturating_sub(BLOCK_SIZE as u64).max(start);
+    if block_start == start {
+        return Ok(start);
+    }
+    file.seek(SeekFrom::Start(block_start))?;
+
+    let mut buffer = [0; BLOCK_SIZE];
+    let size = (end - block_start) as usize;
+    file.read_exact(&mut buffer[..size])?;
+    // the last byte ends the line before `end`, which belongs to this chunk
+    match newline_aligned_boundary(&buffer[..size - 1], false) {
+        Some(boundary) => Ok(block_start + boundary as u64),
+        None => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("line is longer than the block size of {BLOCK_SIZE} bytes"),
+        )),
+    }
+}
+
+type PartialResult = Result<(HashMap<String, CityEntry>, usize), Error>;
+
+/// The rows aggregated by a sink and the number of skipped lines
+type SinkResult<S> = Result<(S, usize), Error>;
+
+fn process_lines<S: RowSink>(
+    chunks: Receiver<StrBuffer>,
+    options: ParseOptions,
+    mut sink: S,
+) -> SinkResult<S> {
+    let mut skipped = 0;
+    while let Ok(chunk) = chunks.recv() {
+        sink.start_chunk(chunk.index);
+        skipped += process_text(&chunk, &options, &mut sink)?;
+    }
+    Ok((sink, skipped))
+}
+
+/// Aggregates the lines of a single chunk and returns the number of skipped lines.
+fn process_text<S: RowSink>(
+    chunk: &str,
+    options: &ParseOptions,
+    sink: &mut S,
+) -> Result<usize, Error> {
+    let mut skipped = 0;
+    for line in chunk.lines() {
+        if !options.line_length_in_bounds(line.len()) {
+            return Err(line_length_error(line.as_bytes(), options));
+        }
+        match options.in_window(line.as_bytes()) {
+            Some(true) => {}
+            Some(false) => continue,
+            None if options.lenient => {
+                skipped += 1;
+                continue;
+            }
+            None => return Err(Error::MalformedData(format!("malformed line: {line:?}"))),
+        }
+        if !S::PARSE_VALUES {
+            match parse_city(line, options) {
+                Some(city) => sink.accept(city.as_bytes(), f32::NAN),
+                None if options.lenient => skipped += 1,
+                None => return Err(Error::MalformedData(format!("malformed line: {line:?}"))),
+            }
+            continue;
+        }
+        let (city, value) = match parse_row(line, options) {
+            Some(parsed) => parsed,
+            None if options.lenient => {
+                skipped += 1;
+                continue;
+            }
+            None => return Err(Error::MalformedData(format!("malformed line: {line:?}"))),
+        };
+        if !options.in_bounds(value.as_f32()) {
+            return Err(Error::MalformedData(format!(
+                "value out of bounds: {line:?}"
+            )));
+        }
+
+        accept(sink, city.as_bytes(), value);
+    }
+    Ok(skipped)
+}
+
+/// The error for a `line` outside of [ParseOptions::line_length]
+fn line_length_error(line: &[u8], options: &ParseOptions) -> Error {
+    let (min, max) = options.line_length;
+    match line.len() < min {
+        true => Error::MalformedData(format!(
+            "line is shorter than {min} bytes: {:?}",
+            String::from_utf8_lossy(line)
+        )),
+        // the line could be a whole file of junk, so only its start is shown
+        false => Error::MalformedData(format!(
+            "line of {} bytes is longer than {max} bytes: {:?}...",
+            line.len(),
+            String::from_utf8_lossy(&line[..max.min(32)])
+        )),
+    }
+}
+
+fn accept<S: RowSink>(sink: &mut S, city: &[u8], value: Value) {
+    match value {
+        Value::Float(value) => sink.accept(city, value),
+        Value::Int(value) => sink.accept_int(city, value),
+    }
+}
+
+/// Same as [process_lines] but works on chunks that were not validated as utf8.
+fn process_lines_bytes<S: RowSink>(
+    chunks: Receiver<StrBuffer>,
+    options: ParseOptions,
+    mut sink: S,
+) -> SinkResult<S> {
+    let mut skipped = 0;
+    while let Ok(chunk) = chunks.recv() {
+        sink.start_chunk(chunk.index);
+        skipped += process_bytes(chunk.as_bytes(), &options, &mut sink)?;
+    }
+    Ok((sink, skipped))
+}
+
+/// Same as [process_text] for a chunk that was not validated as utf8.
+fn process_bytes<S: RowSink>(
+    chunk: &[u8],
+    options: &ParseOptions,
+    sink: &mut S,
+) -> Result<usize, Error> {
+    let mut skipped = 0;
+    let data = chunk.strip_suffix(b"\n").unwrap_or(chunk);
+    for line in data.split(|&c| c == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if !options.line_length_in_bounds(line.len()) {
+            return Err(line_length_error(line, options));
+        }
+        match options.in_window(line) {
+            Some(true) => {}
+            Some(false) => continue,
+            None if options.lenient => {
+                skipped += 1;
+                continue;
+            }
+            None => {
+                let line = String::from_utf8_lossy(line);
+                return Err(Error::MalformedData(format!("malformed line: {line:?}")));
+            }
+        }
+        if !S::PARSE_VALUES {
+            match parse_city_bytes(line, options) {
+                Some(city) => sink.accept(city, f32::NAN),
+                None if options.lenient => skipped += 1,
+                None => {
+                    let line = String::from_utf8_lossy(line);
+                    return Err(Error::MalformedData(format!("malformed line: {line:?}")));
+                }
+            }
+            continue;
+        }
+        let (city, value) = match parse_row_bytes(line, options) {
+            Some(parsed) => parsed,
+            None if options.lenient => {
+                skipped += 1;
+                continue;
+            }
+            None => {
+                let line = String::from_utf8_lossy(line);
+                return Err(Error::MalformedData(format!("malformed line: {line:?}")));
+            }
+        };
+        if !options.in_bounds(value.as_f32()) {
+            let line = String::from_utf8_lossy(line);
+            return Err(Error::MalformedData(format!(
+                "value out of bounds: {line:?}"
+            )));
+        }
+
+        accept(sink, city, value);
+    }
+    Ok(skipped)
+}
+
+/// Aggregates chunks of binary records. See [Format::Binary].
+///
+/// Only the bounds of `options` apply to records.
+fn process_records<S: RowSink>(
+    chunks: Receiver<StrBuffer>,
+    options: ParseOptions,
+    mut sink: S,
+) -> SinkResult<S> {
+    while let Ok(chunk) = chunks.recv() {
+        sink.start_chunk(chunk.index);
+        let mut data = chunk.as_bytes();
+        while let Some((&name_len, rest)) = data.split_first() {
+            let (city, rest) = rest.split_at(name_len as usize);
+            let (value, rest) = rest.split_at(2);
+            data = rest;
+            if !S::PARSE_VALUES {
+                sink.accept(city, f32::NAN);
+                continue;
+            }
+
+            let value = i16::from_le_bytes([value[0], value[1]]) as f32 / 10.0;
+            if !options.in_bounds(value) {
+                let city = String::from_utf8_lossy(city);
+                return Err(Error::MalformedData(format!(
+                    "value out of bounds: {city:?} {value:.1}"
+                )));
+            }
+            sink.accept(city, value);
+        }
+    }
+    Ok((sink, 0))
+}
+
+fn into_string_keys(
+    result: HashMap<Vec<u8>, CityEntry>,
+) -> Result<HashMap<String, CityEntry>, Error> {
+    result
+        .into_iter()
+        .map(|(city, entry)| {
+            let city = String::from_utf8(city)
+                .map_err(|e| Error::MalformedData(format!("city name is not valid utf8: {e}")))?;
+            entry.check_count(&city)?;
+            Ok((city, entry))
+        })
+        .collect()
+}
+
+/// Reads all `input_files` once and discards the data, to load them into the page cache.
+fn prime_cache(input_files: &[PathBuf], verbose: bool) -> Result<(), Error> {
+    let start = Instant::now();
+    let mut buffer = vec![0; 1024 * 1024];
+    for path in input_files {
+        let mut file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::fd::AsRawFd;
+            // only a hint for the readahead of the kernel, so the result can be ignored
+            // Safety: the file descriptor is valid as long as `file` lives
+            unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+        }
+
+        loop {
+            match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(Error::from_io(path, e)),
+            }
+        }
+    }
+    if verbose {
+        eprintln!("# primed page cache in {:?}", start.elapsed());
+    }
+    Ok(())
+}
+
+/// Returns the separator of `args`, detecting it from the first line of the input
+/// for [Separator::Auto].
+///
+/// Fails if the separator is the same as `--decimal-separator`.
+fn resolve_separator(args: &Args, input_files: &[PathBuf]) -> Result<u8, Error> {
+    let decimal_separator = args.decimal_separator.as_byte();
+    let first_file = match (args.separator, args.format, input_files.first()) {
+        (Separator::Char(separator), _, _) if separator == decimal_separator => {
+            return Err(Error::InvalidArguments(format!(
+                "the separator and the decimal separator are both {:?}",
+                separator as char
+            )))
+        }
+        (Separator::Char(separator), _, _) => return Ok(separator),
+        // binary records have no separator and an empty input has nothing to parse
+        (Separator::Auto, Format::Binary, _) | (Separator::Auto, _, None) => return Ok(b';'),
+        (Separator::Auto, Format::Text, Some(first_file)) => first_file,
+    };
+
+    let first_line = first_line(first_file)?;
+    let separator =
+        Separator::detect_except(&first_line, Some(decimal_separator)).ok_or_else(|| {
+            let first_line = String::from_utf8_lossy(&first_line);
+            Error::MalformedData(format!(
+                "no separator found in the first line of {}: {first_line:?}",
+                first_file.display()
+            ))
+        })?;
+    if args.verbose {
+        eprintln!("# detected separator {:?}", separator as char);
+    }
+    Ok(separator)
+}
+
+/// Fails if the mmap-zerocopy engine is used with an option it does not support.
+fn check_engine_options(args: &Args) -> Result<(), Error> {
+    if args.engine != Engine::MmapZerocopy {
+        return Ok(());
+    }
+    if args.format != Format::Text {
+        return Err(Error::InvalidArguments(
+            "the mmap-zerocopy engine only supports text input".to_string(),
+        ));
+    }
+    let unsupported_options = [
+        (args.byte_range().is_some(), "--offset and --length"),
+        (args.snapshot_every.is_some(), "--snapshot-every"),
+        (args.reverse, "--reverse"),
+        (args.adaptive_block_size, "--adaptive-block-size"),
+        (args.profile, "--profile"),
+        (args.max_seconds.is_some(), "--max-seconds"),
+    ];
+    match unsupported_options.iter().find(|(set, _)| *set) {
+        Some((_, option)) => Err(Error::InvalidArguments(format!(
+            "the mmap-zerocopy engine does not support {option}"
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Fails if `--city-column`, `--value-column` and `--timestamp-column` are not
+/// different or if the first line of the first input file does not have enough
+/// fields for them.
+fn check_columns(args: &Args, input_files: &[PathBuf], separator: u8) -> Result<(), Error> {
+    let Some((city_column, value_column)) = args.city_column.zip(args.value_column) else {
+        return Ok(());
+    };
+    if city_column == value_column {
+        return Err(Error::InvalidArguments(
+            "--city-column and --value-column must be different".to_string(),
+        ));
+    }
+    if args
+        .timestamp_column
+        .is_some_and(|column| column == city_column || column == value_column)
+    {
+        return Err(Error::InvalidArguments(
+            "--timestamp-column must be different from --city-column and --value-column"
+                .to_string(),
+        ));
+    }
+    let Some(first_file) = input_files.first().filter(|_| args.format == Format::Text) else {
+        return Ok(());
+    };
+
+    let first_line = first_line(first_file)?;
+    let first_line = first_line.strip_suffix(b"\r").unwrap_or(&first_line);
+    let fields = first_line.split(|&c| c == separator).count();
+    let timestamp_column = args
+        .timestamp_column
+        .map(|column| ("--timestamp-column", column));
+    for (option, column) in [
+        ("--city-column", city_column),
+        ("--value-column", value_column),
+    ]
+    .into_iter()
+    .chain(timestamp_column)
+    {
+        if column >= fields {
+            return Err(Error::MalformedData(format!(
+                "{option} {column} is out of range, the first line of {} has {fields} fields",
+                first_file.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The first line of `path`, or its first [BLOCK_SIZE] bytes if the line is longer
+fn first_line(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+    let mut start = vec![0; BLOCK_SIZE];
+    let size = file.read(&mut start).map_err(|e| Error::from_io(path, e))?;
+    start.truncate(size);
+    let line_end = start.iter().position(|&c| c == b'\n').unwrap_or(size);
+    start.truncate(line_end);
+    Ok(start)
+}
+
+/// The first input file that is zstd or gzip compressed.
+///
+/// Compressed input cannot be split into blocks, so it has to be read by the
+/// streaming reader instead.
+fn find_compressed(input_files: &[PathBuf]) -> Result<Option<&Path>, Error> {
+    for path in input_files {
+        if file_compression(path)?.is_some() {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// The compression of the file at `path`, see [compression]
+fn file_compression(path: &Path) -> Result<Option<&'static str>, Error> {
+    let file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+    let mut start = Vec::with_capacity(BGZF_HEADER.len());
+    file.take(BGZF_HEADER.len() as u64)
+        .read_to_end(&mut start)
+        .map_err(|e| Error::from_io(path, e))?;
+    Ok(compression(&start))
+}
+
+/// Opens `path` for the streaming reader, decompressing it if it is compressed.
+///
+/// The members of bgzip input are decompressed by up to `threads` workers.
+#[cfg_attr(not(feature = "compression"), allow(unused_variables))]
+fn open_stream(path: &Path, threads: usize) -> Result<Box<dyn Read + Send>, Error> {
+    match file_compression(path)? {
+        #[cfg(feature = "compression")]
+        Some("bgzip") => Ok(Box::new(
+            gzip::MemberReader::open(path, threads).map_err(|e| Error::from_io(path, e))?,
+        )),
+        compression => {
+            let file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+            decompressed(file, compression, path.display())
+        }
+    }
+}
+
+/// Wraps `input` into the decoder of its `compression`, see [compression].
+///
+/// Without the compression feature, compressed input would be reported as a
+/// malformed first line, so it fails instead.
+fn decompressed<R: Read + Send + 'static>(
+    input: R,
+    compression: Option<&str>,
+    name: impl Display,
+) -> Result<Box<dyn Read + Send>, Error> {
+    match compression {
+        None => Ok(Box::new(input)),
+        #[cfg(feature = "compression")]
+        Some("zstd") => Ok(Box::new(zstd::Decoder::new(input)?)),
+        // a plain gzip file can still consist of several members, e.g. from `cat`
+        #[cfg(feature = "compression")]
+        Some("gzip" | "bgzip") => Ok(Box::new(flate2::read::MultiGzDecoder::new(input))),
+        Some(compression) => Err(compressed_input(name, compression)),
+    }
+}
+
+/// The start of a gzip member written by bgzip, with `?` for the bytes that vary.
+///
+/// The member has an extra field with the `BC` subfield, which holds the size of the
+/// member. A bgzip file is a series of such members, which can be decompressed
+/// independently of each other.
+const BGZF_HEADER: &[u8; 16] = b"\x1f\x8b\x08\x04??????\x06\x00BC\x02\x00";
+
+/// Whether `start` is the start of a gzip member written by bgzip, see [BGZF_HEADER]
+fn is_bgzf_member(start: &[u8]) -> bool {
+    start.len() >= BGZF_HEADER.len()
+        && BGZF_HEADER
+            .iter()
+            .zip(start)
+            .all(|(&expected, &byte)| expected == b'?' || expected == byte)
+}
+
+/// The compression of data that starts with `start`, if it is zstd or gzip compressed
+fn compression(start: &[u8]) -> Option<&'static str> {
+    const MAGIC: [(&str, &[u8]); 2] =
+        [("zstd", &[0x28, 0xb5, 0x2f, 0xfd]), ("gzip", &[0x1f, 0x8b])];
+
+    if is_bgzf_member(start) {
+        return Some("bgzip");
+    }
+    MAGIC
+        .iter()
+        .find(|(_, magic)| start.starts_with(magic))
+        .map(|(name, _)| *name)
+}
+
+fn compressed_input(input: impl Display, compression: &str) -> Error {
+    let hint = match compression {
+        "bgzip" => ", e.g. in parallel with `bgzip --decompress --threads N`",
+        _ => "",
+    };
+    Error::Io(io::Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "{input} is {compression} compressed, decompress it first{hint} \
+             or build with `--features compression` to read it"
+        ),
+    ))
+}
+
+/// Caps the number of workers to the number of blocks in the input.
+///
+/// Each worker processes whole blocks, so any additional worker would never receive any work.
+fn worker_count(requested: usize, input_files: &[PathBuf]) -> Result<usize, Error> {
+    let mut input_size = 0;
+    for file in input_files {
+        input_size += metadata(file).map_err(|e| Error::from_io(file, e))?.len();
+    }
+    let blocks = input_size.div_ceil(BLOCK_SIZE as u64);
+    Ok(requested.min(blocks as usize).max(1))
+}
+
+/// Processes the input of `args` and writes the result to `output`.
+///
+/// The output is buffered, see `--output-buffer-size`, and flushed before
+/// returning, so write errors of the last bytes are not lost when it is dropped.
+/// It is flushed after an error as well, as some modes like `--compare` write
+/// their report before failing.
+pub fn calculate<OWrite: Write>(args: Args, output: OWrite) -> Result<(), Error> {
+    let mut output = BufWriter::with_capacity(args.output_buffer_size.get(), output);
+    let calculated = calculate_buffered(args, &mut output);
+    let flushed = output.flush();
+    calculated?;
+    Ok(flushed?)
+}
+
+fn calculate_buffered<OWrite: Write>(args: Args, mut output: OWrite) -> Result<(), Error> {
+    if let Some(files) = &args.compare {
+        let tolerance = match args.decimals {
+            Some(decimals) => Tolerance::Decimals(decimals),
+            None => Tolerance::Absolute(args.tolerance),
+        };
+        return compare::compare_files(
+            &files[0],
+            &files[1],
+            tolerance,
+            args.fail_on_rounding,
+            output,
+        );
+    }
+    if let Some(files) = &args.merge_results {
+        return merge::merge_result_files(files, args.show_count, output);
+    }
+    if let Some(baseline) = &args.perf_baseline {
+        perf::check_inputs(&args.input)?;
+        return perf::check_baseline(&args, baseline, output);
+    }
+    let (min_line_length, max_line_length) = args.line_length();
+    if min_line_length > max_line_length {
+        return Err(Error::InvalidArguments(
+            "--min-line-length must not be larger than --max-line-length".to_string(),
+        ));
+    }
+    if args.format == Format::Binary
+        && (args.min_line_length.is_some() || args.max_line_length.is_some())
+    {
+        return Err(Error::InvalidArguments(
+            "--min-line-length and --max-line-length require text input".to_string(),
+        ));
+    }
+    check_engine_options(&args)?;
+    if args
+        .input
+        .iter()
+        .any(|path| path.as_os_str() == stream::STDIN)
+    {
+        return stream::calculate_stream(&args, io::stdin(), output);
+    }
+    if let Some(url) = args
+        .input
+        .iter()
+        .find_map(|path| path.to_str().filter(|path| http::is_url(path)))
+    {
+        let body = http::open(url)?;
+        return stream::calculate_stream(&args, body, output);
+    }
+    if args.emit_every.is_some() {
+        let path = args.input.first().expect("--emit-every requires an input");
+        return stream::calculate_stream(&args, open_stream(path, args.threads)?, output);
+    }
+
+    let input_files = collect_input_files(&args.input, args.recursive)?;
+    if args.hash_input {
+        let hash = hash::hash_files(&input_files)?;
+        writeln!(output, "{hash:016x}")?;
+        return Ok(());
+    }
+    if args.format == Format::Text {
+        if let Some(path) = find_compressed(&input_files)? {
+            let input = open_stream(path, args.threads)?;
+            if input_files.len() > 1 {
+                return Err(Error::InvalidArguments(format!(
+                    "{} is compressed, which is only supported for a single input file",
+                    path.display()
+                )));
+            }
+            return stream::calculate_stream(&args, input, output);
+        }
+    }
+    if let Some(count) = args.tail {
+        let Some(last_file) = input_files.last().filter(|_| args.format == Format::Text) else {
+            return Err(Error::InvalidArguments(
+                "--tail requires a text input file".to_string(),
+            ));
+        };
+        let separator = resolve_separator(&args, &input_files)?;
+        check_columns(&args, &input_files, separator)?;
+        return tail::write_tail(last_file, count, &args.parse_options(separator), output);
+    }
+    let threads = worker_count(args.threads, &input_files)?;
+    if args.explain {
+        let separator = resolve_separator(&args, &input_files)?;
+        explain::write_plan(&args, &input_files, threads, separator, io::stderr())?;
+    }
+    if let Some(phase) = args.bench {
+        return bench::run(&args, phase, input_files, threads, output);
+    }
+
+    if args.validate_utf8_once {
+        if args.format != Format::Text {
+            return Err(Error::InvalidArguments(
+                "--validate-utf8-once requires text input".to_string(),
+            ));
+        }
+        let start = Instant::now();
+        utf8::validate_files(&input_files)?;
+        if args.verbose {
+            eprintln!("# validated utf8 in {:?}", start.elapsed());
+        }
+    }
+    if args.prime_cache {
+        prime_cache(&input_files, args.verbose)?;
+    }
+    let start = Instant::now();
+
+    if let Some(bins) = args.histogram {
+        let (min, max) = args
+            .expect_min
+            .zip(args.expect_max)
+            .unwrap_or((-99.9, 99.9));
+        let empty = Histograms::new(bins.get(), min, max);
+        let write = |histograms: &Histograms, output: &mut File| histograms.write(output);
+        let snapshot = Snapshot::new(&args, &write);
+        let (histograms, skipped) =
+            calculate_chunked(&args, input_files, threads, empty, snapshot)?;
+        if args.lenient {
+            eprintln!("# skipped {skipped} malformed lines");
+        }
+        return histograms.write(output);
+    }
+
+    if args.dedup_values {
+        if !(0.0..=1.0).contains(&args.dedup_threshold) {
+            return Err(Error::InvalidArguments(
+                "--dedup-threshold must be between 0 and 1".to_string(),
+            ));
+        }
+        let (min, max) = args
+            .expect_min
+            .zip(args.expect_max)
+            .unwrap_or((-99.9, 99.9));
+        let empty = RepeatedValues::new(min, max);
+        let threshold = args.dedup_threshold;
+        let write = |values: &RepeatedValues, output: &mut File| values.write(threshold, output);
+        let snapshot = Snapshot::new(&args, &write);
+        let (values, skipped) = calculate_chunked(&args, input_files, threads, empty, snapshot)?;
+        if args.lenient {
+            eprintln!("# skipped {skipped} malformed lines");
+        }
+        return values.write(threshold, output);
+    }
+
+    if args.keys_only {
+        let write = |names: &CityNames, output: &mut File| names.write(output);
+        let snapshot = Snapshot::new(&args, &write);
+        let (names, skipped) =
+            calculate_chunked(&args, input_files, threads, CityNames::default(), snapshot)?;
+        if args.lenient {
+            eprintln!("# skipped {skipped} malformed lines");
+        }
+        return names.write(output);
+    }
+
+    if let Some(dir) = args.external_merge.as_deref() {
+        if args.engine != Engine::Chunked
+            || args.value_type != ValueType::Float
+            || args.order != Order::Sorted
+            || args.normalize_names != NormalizeNames::None
+        {
+            return Err(Error::InvalidArguments(
+                "--external-merge requires the chunked engine, float values, \
+                 sorted output and no --normalize-names"
+                    .to_string(),
+            ));
+        }
+        let empty = SpillingMap::new(dir, args.spill_cities.get());
+        let (cities, skipped) = calculate_chunked(&args, input_files, threads, empty, None)?;
+        if args.lenient {
+            eprintln!("# skipped {skipped} malformed lines");
+        }
+        return cities.write(args.show_stddev, output);
+    }
+
+    if args.sorted_merge {
+        if args.engine != Engine::Chunked
+            || args.value_type != ValueType::Float
+            || args.order != Order::Sorted
+            || args.normalize_names != NormalizeNames::None
+        {
+            return Err(Error::InvalidArguments(
+                "--sorted-merge requires the chunked engine, float values, \
+                 sorted output and no --normalize-names"
+                    .to_string(),
+            ));
+        }
+        let write = |cities: &SortedCityMap, output: &mut File| {
+            cities.write(args.show_stddev, args.show_count, output)
+        };
+        let snapshot = Snapshot::new(&args, &write);
+        let (cities, skipped) = calculate_chunked(
+            &args,
+            input_files,
+            threads,
+            SortedCityMap::default(),
+            snapshot,
+        )?;
+        if args.lenient {
+            eprintln!("# skipped {skipped} malformed lines");
+        }
+        check_line_count(args.expect_lines, cities.rows(), skipped)?;
+        return cities.write(args.show_stddev, args.show_count, output);
+    }
+
+    if args.spec_strict {
+        if args.engine != Engine::Chunked || args.format != Format::Text {
+            return Err(Error::InvalidArguments(
+                "--spec-strict requires the chunked engine and text input".to_string(),
+            ));
+        }
+        let write = |cities: &IntCityMap, output: &mut File| cities.clone().write_spec(output);
+        let snapshot = Snapshot::new(&args, &write);
+        let (cities, skipped) =
+            calculate_chunked(&args, input_files, threads, IntCityMap::default(), snapshot)?;
+        if args.lenient {
+            eprintln!("# skipped {skipped} malformed lines");
+        }
+        check_line_count(args.expect_lines, cities.rows(), skipped)?;
+        return cities.write_spec(output);
+    }
+
+    if args.value_type == ValueType::Int {
+        if args.engine != Engine::Chunked || args.format != Format::Text {
+            return Err(Error::InvalidArguments(
+                "integer values require the chunked engine and text input".to_string(),
+            ));
+        }
+        if args.top.is_some()
+            || args.bottom.is_some()
+            || args.normalize_names != NormalizeNames::None
+            || args.show_stddev
+            || args.show_count
+            || args.sort_order != SortOrder::Bytewise
+            || args.interactive
+            || args.limit_cities.is_some()
+            || args.group_by_prefix.is_some()
+        {
+            return Err(Error::InvalidArguments(
+                "integer values do not support --top, --bottom, --normalize-names, --show-stddev, \
+                 --show-count, --sort-order unicode, --interactive, --limit-cities and \
+                 --group-by-prefix"
+                    .to_string(),
+            ));
+        }
+        let write =
+            |cities: &IntCityMap, output: &mut File| cities.clone().write(args.order, output);
+        let snapshot = Snapshot::new(&args, &write);
+        let (cities, skipped) =
+            calculate_chunked(&args, input_files, threads, IntCityMap::default(), snapshot)?;
+        if args.lenient {
+            eprintln!("# skipped {skipped} malformed lines");
+        }
+        check_line_count(args.expect_lines, cities.rows(), skipped)?;
+        return cities.write(args.order, output);
+    }
+
+    let (result, skipped) = match args.engine {
+        Engine::Chunked => {
+            let write = |cities: &CityMap, output: &mut File| {
+                let result = normalize_names(cities.clone().into_result()?, args.normalize_names);
+                write_cities(result, &args, output)
+            };
+            let snapshot = Snapshot::new(&args, &write);
+            let (cities, skipped) =
+                calculate_chunked(&args, input_files, threads, CityMap::default(), snapshot)?;
+            (cities.into_result()?, skipped)
+        }
+        Engine::MmapZerocopy => {
+            let separator = resolve_separator(&args, &input_files)?;
+            check_columns(&args, &input_files, separator)?;
+            let options = ParseOptions {
+                window: window::resolve(&args, &input_files, separator)?,
+                ..args.parse_options(separator)
+            };
+            mmap::calculate_zero_copy(&input_files, threads, options)?
+        }
+    };
+
+    if args.verbose {
+        eprintln!("# processed input in {:?}", start.elapsed());
+    }
+    let rows = result.values().map(|city| city.count).sum();
+    check_line_count(args.expect_lines, rows, skipped)?;
+
+    let result = normalize_names(result, args.normalize_names);
+
+    if args.lenient {
+        eprintln!("# skipped {skipped} malformed lines");
+    }
+
+    if args.interactive {
+        let results = ordered_results(result, &args)
+            .limit_cities(args.limit_cities)
+            .show_stddev(args.show_stddev);
+        eprintln!(
+            "# loaded {} cities, enter a city name, a filter like `mean>30` or `quit`",
+            results.len()
+        );
+        return query::run(&results, io::stdin().lock(), output);
+    }
+    write_cities(result, &args, output)
+}
+
+/// Fails with [Error::VerificationMismatch] if `rows` and `skipped` lines do not add up
+/// to the `expected` lines of `--expect-lines`.
+fn check_line_count(expected: Option<u64>, rows: usize, skipped: usize) -> Result<(), Error> {
+    let lines = rows as u64 + skipped as u64;
+    match expected {
+        Some(expected) if expected != lines => Err(Error::VerificationMismatch(format!(
+            "processed {lines} lines, but the input should have {expected}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// The cities of `result` in the output order of `args`
+fn ordered_results(result: HashMap<String, CityEntry>, args: &Args) -> Results {
+    let results = Results::new(result, args.order);
+    match (args.order, args.sort_order) {
+        (Order::Sorted, SortOrder::Unicode) => results.collated(),
+        _ => results,
+    }
+}
+
+/// Writes the result as a ranking or in the challenge format, depending on `args`.
+fn write_cities<OWrite: Write>(
+    result: HashMap<String, CityEntry>,
+    args: &Args,
+    mut output: OWrite,
+) -> Result<(), Error> {
+    // normalized names can merge cities
+    for (name, city) in &result {
+        city.check_count(name)?;
+    }
+    if args.top.is_some() || args.bottom.is_some() {
+        write_ranking(&result, args.top, args.bottom, output)?;
+    } else {
+        let groups = args
+            .group_by_prefix
+            .as_deref()
+            .map(|separator| group_by_prefix(&result, separator));
+        let results = ordered_results(result, args).limit_cities(args.limit_cities);
+        let mut sink = TextSink::new(&mut output, args.show_stddev).show_count(args.show_count);
+        results.emit_to(&mut sink)?;
+
+        if let Some(groups) = groups {
+            writeln!(output)?;
+            let mut sink = TextSink::new(&mut output, args.show_stddev).show_count(args.show_count);
+            ordered_results(groups, args).emit_to(&mut sink)?;
+        }
+    }
+    Ok(())
+}
+
+/// Aggregates the input of `args` into a custom [RowSink].
+///
+/// This reuses the parallel reader of the chunked engine, regardless of `args.engine`.
+/// Every worker starts with a clone of `empty`.
+/// Returns the merged sink of all workers and the number of skipped lines.
+pub fn aggregate<S: RowSink + Clone>(args: &Args, empty: S) -> Result<(S, usize), Error> {
+    let input_files = collect_input_files(&args.input, args.recursive)?;
+    let threads = worker_count(args.threads, &input_files)?;
+    calculate_chunked(args, input_files, threads, empty, None)
+}
+
+/// Aggregates rows that are already in memory, e.g. `b"Hamburg;12.0\n"`, with the
+/// default [ParseOptions].
+///
+/// `data` is split into line aligned segments for `threads` workers, like the
+/// `mmap-zerocopy` engine does with a mapped file.
+pub fn aggregate_slice(data: &[u8], threads: usize) -> Result<Results, Error> {
+    let (result, _) = mmap::calculate_slices(&[data], threads.max(1), ParseOptions::default())?;
+    Ok(Results::new(result, Order::Sorted))
+}
+
+/// Writes the partial result of [calculate_chunked] to a file at a fixed interval.
+struct Snapshot<'a, S> {
+    path: &'a Path,
+    every: Duration,
+    write: &'a dyn Fn(&S, &mut File) -> Result<(), Error>,
+}
+
+impl<'a, S> Snapshot<'a, S> {
+    fn new(args: &'a Args, write: &'a dyn Fn(&S, &mut File) -> Result<(), Error>) -> Option<Self> {
+        let (path, every) = args.snapshot_target()?;
+        Some(Snapshot { path, every, write })
+    }
+
+    /// Replaces the snapshot file with `partial`.
+    ///
+    /// The snapshot is written to a temporary file first, so that the previous
+    /// snapshot survives if the process dies while writing.
+    fn write(&self, partial: &S) -> Result<(), Error> {
+        let mut temp_path = self.path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let mut file = File::create(&temp_path)?;
+        (self.write)(partial, &mut file)?;
+        drop(file);
+        Ok(std::fs::rename(temp_path, self.path)?)
+    }
+}
+
+/// Wraps the sink of a worker and sends what it aggregated so far to the
+/// combining thread once every `every`, so that snapshots can include it.
+struct Flushing<S> {
+    sink: S,
+    empty: S,
+    every: Option<Duration>,
+    last_flush: Instant,
+    sender: Sender<SinkResult<S>>,
+    /// The number of chunks this worker processed, for `--profile`
+    chunks: usize,
+}
+
+impl<S: RowSink + Clone> RowSink for Flushing<S> {
+    const PARSE_VALUES: bool = S::PARSE_VALUES;
+
+    fn accept(&mut self, city: &[u8], value: f32) {
+        self.sink.accept(city, value);
+    }
+
+    fn accept_int(&mut self, city: &[u8], value: i64) {
+        self.sink.accept_int(city, value);
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.sink.merge(other.sink);
+    }
+
+    fn start_chunk(&mut self, index: usize) {
+        // flush between chunks, so that no row is split from its chunk
+        if let Some(every) = self.every {
+            if self.last_flush.elapsed() >= every {
+                let flushed = std::mem::replace(&mut self.sink, self.empty.clone());
+                // combining stops at the first error, so the receiver might be gone already
+                let _ = self.sender.send(Ok((flushed, 0)));
+                self.last_flush = Instant::now();
+            }
+        }
+        self.chunks += 1;
+        self.sink.start_chunk(index);
+    }
+}
+
+/// Calculates the result by reading the input in blocks that are distributed to the workers.
+fn calculate_chunked<S: RowSink + Clone>(
+    args: &Args,
+    input_files: Vec<PathBuf>,
+    threads: usize,
+    empty: S,
+    snapshot: Option<Snapshot<S>>,
+) -> SinkResult<S> {
+    let byte_range = args.byte_range();
+    if byte_range.is_some() && (input_files.len() != 1 || args.format != Format::Text) {
+        return Err(Error::InvalidArguments(
+            "--offset and --length require a single text input file".to_string(),
+        ));
+    }
+
+    if args.reverse && args.format != Format::Text {
+        return Err(Error::InvalidArguments(
+            "--reverse requires text input".to_string(),
+        ));
+    }
+
+    let separator = resolve_separator(args, &input_files)?;
+    check_columns(args, &input_files, separator)?;
+    let window = window::resolve(args, &input_files, separator)?;
+    let (chunk_sender, chunk_receiver) = bounded(CHANNEL_CAPACITY);
+
+    let read_chunk: ReadChunk = match (args.format, args.skip_utf8_validation()) {
+        (Format::Binary, _) => StrBuffer::read_records,
+        (Format::Text, true) => StrBuffer::read_ascii,
+        (Format::Text, false) => StrBuffer::read_text,
+    };
+    let (reverse, adaptive) = (args.reverse, args.adaptive_block_size);
+    let profile = args.profile.then(|| Arc::new(Profile::new()));
+    let capacity = chunk_sender.capacity().unwrap_or(0);
+    let producer_profile = profile.clone();
+    let max_duration = args
+        .max_seconds
+        .map(|seconds| Duration::from_secs(seconds.get()));
+    let deadline = max_duration.map(|duration| Instant::now() + duration);
+    let producer = thread::spawn(move || {
+        let profile = producer_profile.as_deref();
+        let produced = match reverse {
+            true => produce_text_chunks_reversed(
+                &input_files,
+                byte_range,
+                chunk_sender,
+                read_chunk,
+                profile,
+                deadline,
+            ),
+            false => produce_text_chunks(
+                &input_files,
+                byte_range,
+                chunk_sender,
+                read_chunk,
+                profile,
+                deadline,
+                adaptive,
+            ),
+        };
+        if let Some(profile) = profile {
+            profile.producer_done();
+        }
+        produced
+    });
+
+    // workers send their partial result as soon as they are done, so that merging
+    // can start while other workers are still processing. Workers that finish
+    // also merge the results of earlier workers, so merging runs in parallel and
+    // only a few partial results are alive at any time.
+    let (partial_sender, partial_receiver) = unbounded();
+    let mut worker_handles = Vec::with_capacity(threads);
+    for _ in 0..threads {
+        let chunk_receiver = chunk_receiver.clone();
+        let partial_sender = partial_sender.clone();
+        let finished = partial_receiver.clone();
+        let options = ParseOptions {
+            window,
+            ..args.parse_options(separator)
+        };
+        let (format, skip_utf8_validation) = (args.format, args.skip_utf8_validation());
+        let sink = Flushing {
+            sink: empty.clone(),
+            empty: empty.clone(),
+            every: snapshot.as_ref().map(|snapshot| snapshot.every),
+            last_flush: Instant::now(),
+            sender: partial_sender.clone(),
+            chunks: 0,
+        };
+        let profile = profile.clone();
+        let handle = thread::spawn(move || {
+            let partial = match (format, skip_utf8_validation) {
+                (Format::Binary, _) => process_records(chunk_receiver, options, sink),
+                (Format::Text, true) => process_lines_bytes(chunk_receiver, options, sink),
+                (Format::Text, false) => process_lines(chunk_receiver, options, sink),
+            };
+            let partial = partial.map(|(flushing, skipped)| {
+                if let Some(profile) = &profile {
+                    profile.worker_done(flushing.chunks);
+                }
+                (flushing.sink, skipped)
+            });
+            let partial = merge_finished(partial, &finished);
+            // combining stops at the first error, so the receiver might be gone already
+            let _ = partial_sender.send(partial);
+        });
+        worker_handles.push(handle);
+    }
+    drop(partial_sender);
+
+    let result = combine_results(partial_receiver, empty, snapshot)?;
+
+    // a worker that panicked never sends its result, so make sure none are missing
+    for handle in worker_handles {
+        if handle.join().is_err() {
+            panic!("process lines failed");
+        }
+    }
+    let time_limited = match producer.join() {
+        Ok(produced) => produced?,
+        Err(_) => panic!("produce text chunks failed"),
+    };
+    if let Some(duration) = max_duration.filter(|_| time_limited) {
+        eprintln!(
+            "# time-limited: stopped reading after {duration:?}, the result is partial \
+             and only contains the rows read so far"
+        );
+    }
+    if let Some(profile) = profile {
+        profile.report(capacity, io::stderr())?;
+    }
+
+    Ok(result)
+}
+
+/// Merges all cities whose names start with the same prefix before `separator` into
+/// a group named after the prefix, see `--group-by-prefix`.
+///
+/// Cities without `separator` are not part of any group.
+fn group_by_prefix(
+    result: &HashMap<String, CityEntry>,
+    separator: &str,
+) -> HashMap<String, CityEntry> {
+    let mut groups = HashMap::<String, CityEntry>::new();
+    for (city, entry) in result {
+        let Some((prefix, _)) = city.split_once(separator) else {
+            continue;
+        };
+        match groups.get_mut(prefix) {
+            Some(group) => group.merge(entry),
+            None => {
+                groups.insert(prefix.to_string(), entry.clone());
+            }
+        }
+    }
+    groups
+}
+
+/// Merges all cities whose names are the same after normalization.
+///
+/// This runs once on the merged result, so it does not slow down the workers.
+fn normalize_names(
+    result: HashMap<String, CityEntry>,
+    mode: NormalizeNames,
+) -> HashMap<String, CityEntry> {
+    match mode {
+        NormalizeNames::None => result,
+        NormalizeNames::Lower => {
+            let mut normalized = HashMap::<String, CityEntry>::with_capacity(result.len());
+            for (city, entry) in result {
+                normalized
+                    .entry(city.to_lowercase())
+                    .and_modify(|merged| merged.merge(&entry))
+                    .or_insert(entry);
+            }
+            normalized
+        }
+        NormalizeNames::FirstSeen => {
+            let mut normalized =
+                HashMap::<String, (String, CityEntry)>::with_capacity(result.len());
+            for (city, entry) in result {
+                match normalized.entry(city.to_lowercase()) {
+                    Entry::Occupied(mut group) => {
+                        let (name, merged) = group.get_mut();
+                        // first_seen is the position in the input, so this does not
+                        // depend on the order of the cities in `result`
+                        if entry.first_seen < merged.first_seen {
+                            *name = city;
+                        }
+                        merged.merge(&entry);
+                    }
+                    Entry::Vacant(group) => {
+                        group.insert((city, entry));
+                    }
+                }
+            }
+            normalized.into_values().collect()
+        }
+    }
+}
+
+/// Writes a human readable table of the cities with the highest and/or lowest mean.
+fn write_ranking<OWrite: Write>(
+    result: &HashMap<String, CityEntry>,
+    top: Option<usize>,
+    bottom: Option<usize>,
+    mut output: OWrite,
+) -> io::Result<()> {
+    let mut by_mean: Vec<_> = result
+        .iter()
+        .map(|(name, city)| (name, city, city.sum / city.count as f32))
+        .collect();
+    by_mean.sort_unstable_by(|a, b| b.2.total_cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+
+    if let Some(top) = top {
+        writeln!(output, "top {top} by mean")?;
+        write_table(&mut output, by_mean.iter().take(top))?;
+    }
+    if let Some(bottom) = bottom {
+        writeln!(output, "bottom {bottom} by mean")?;
+        write_table(&mut output, by_mean.iter().rev().take(bottom))?;
+    }
+    Ok(())
+}
+
+fn write_table<'a, OWrite: Write>(
+    output: &mut OWrite,
+    rows: impl Iterator<Item = &'a (&'a String, &'a CityEntry, f32)>,
+) -> io::Result<()> {
+    writeln!(
+        output,
+        "{:<20} {:>7} {:>7} {:>7} {:>12}",
+        "city", "min", "mean", "max", "count"
+    )?;
+    for (name, city, mean) in rows {
+        writeln!(
+            output,
+            "{:<20} {:>7.1} {:>7.1} {:>7.1} {:>12}",
+            name, city.min, mean, city.max, city.count
+        )?;
+    }
+    Ok(())
+}
+
+/// Merges all results that are currently waiting to be combined into `partial`.
+fn merge_finished<S: RowSink>(
+    partial: SinkResult<S>,
+    finished: &Receiver<SinkResult<S>>,
+) -> SinkResult<S> {
+    let (mut sink, mut skipped) = partial?;
+    while let Ok(other) = finished.try_recv() {
+        let (other, other_skipped) = other?;
+        skipped += other_skipped;
+        sink.merge(other);
+    }
+    Ok((sink, skipped))
+}
+
+fn combine_results<S: RowSink>(
+    partial_results: Receiver<SinkResult<S>>,
+    empty: S,
+    snapshot: Option<Snapshot<S>>,
+) -> SinkResult<S> {
+    let mut result = empty;
+    let mut skipped = 0;
+    let mut next_snapshot = snapshot
+        .as_ref()
+        .map(|snapshot| Instant::now() + snapshot.every);
+    loop {
+        let partial = match (&snapshot, next_snapshot) {
+            (Some(snapshot), Some(deadline)) => match partial_results.recv_deadline(deadline) {
+                Ok(partial) => partial,
+                Err(RecvTimeoutError::Timeout) => {
+                    snapshot.write(&result)?;
+                    next_snapshot = Some(deadline + snapshot.every);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            _ => match partial_results.recv() {
+                Ok(partial) => partial,
+                Err(_) => break,
+            },
+        };
+        let (partial, partial_skipped) = partial?;
+        skipped += partial_skipped;
+        result.merge(partial);
+    }
+    Ok((result, skipped))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        ffi::OsStr,
+        fs::File,
+        io::{self, Cursor, ErrorKind, Read, Seek, Write},
+        path::{Path, PathBuf},
+        str::from_utf8,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use clap::Parser;
+    use crossbeam::channel::unbounded;
+    use hashbrown::HashMap;
+
+    use crate::{
+        aggregate, aggregate_slice, calculate, combine_results, compressed_input, compression,
+        into_string_keys, merge_finished, new_block, newline_aligned_boundary, normalize_names,
+        process_lines, process_records, produce_text_chunks, worker_count, AdaptiveBlockSize, Args,
+        BlockPool, CityEntry, CityMap, Error, NormalizeNames, Order, ParseOptions, PartialResult,
+        Results, RowSink, SinkResult, Snapshot, StrBuffer, BLOCK_SIZE, MAX_BLOCK_SIZE,
+    };
+
+    fn process_str(data: &str, lenient: bool) -> PartialResult {
+        let (cities, skipped) = process_str_into::<CityMap>(data, lenient)?;
+        Ok((cities.into_result()?, skipped))
+    }
+
+    fn process_str_into<S: RowSink + Default>(data: &str, lenient: bool) -> SinkResult<S> {
+        let (sender, receiver) = unbounded();
+        let mut cursor = Cursor::new(data.as_bytes());
+        while let Some(chunk) = StrBuffer::read_from(&mut cursor).unwrap() {
+            sender.send(chunk).unwrap();
+        }
+        drop(sender);
+
+        let options = ParseOptions {
+            lenient,
+            ..ParseOptions::default()
+        };
+        process_lines(receiver, options, S::default())
+    }
+
+    /// The default args for `in_path` running on a single thread
+    fn test_args(in_path: &Path, extra_args: &[&str]) -> Args {
+        let mut args = vec![OsStr::new("one-billion-rows"), OsStr::new("--threads=1")];
+        args.extend(extra_args.iter().map(OsStr::new));
+        args.push(in_path.as_os_str());
+        Args::parse_from(args)
+    }
+
+    fn check(in_path: PathBuf, expected_path: PathBuf) {
+        check_with_args(test_args(&in_path, &[]), expected_path);
+    }
+
+    fn check_with_args(args: Args, expected_path: PathBuf) {
+        let mut result = Vec::new();
+
+        calculate(args, &mut result).unwrap();
+
+        let mut expected = Vec::new();
+        let mut file = File::open(expected_path).unwrap();
+        file.read_to_end(&mut expected).unwrap();
+
+        assert_eq!(from_utf8(&expected).unwrap(), from_utf8(&result).unwrap());
+    }
+
+    /// Keeps everything written to it, and counts the writes and flushes
+    #[derive(Debug, Default)]
+    struct RecordingWriter {
+        data: Vec<u8>,
+        writes: usize,
+        flushes: usize,
+    }
+
+    impl Write for RecordingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn check_output_buffer() {
+        let mut expected = Vec::new();
+        calculate(
+            test_args(Path::new("data/all_cities.txt"), &[]),
+            &mut expected,
+        )
+        .unwrap();
+
+        for (buffer_size, max_writes) in [("65536", expected.len() / 65536 + 1), ("16", usize::MAX)]
+        {
+            let mut output = RecordingWriter::default();
+            let extra_args = [&format!("--output-buffer-size={buffer_size}")[..]];
+            calculate(
+                test_args(Path::new("data/all_cities.txt"), &extra_args),
+                &mut output,
+            )
+            .unwrap();
+            // the output is complete without relying on the writer to be dropped
+            assert_eq!(output.data, expected);
+            assert!(output.writes <= max_writes, "{}", output.writes);
+            assert_eq!(output.flushes, 1);
+        }
+
+        // the report of a failed comparison is written as well
+        let left = std::env::temp_dir().join("one-billion-rows-buffer-left.txt");
+        let right = std::env::temp_dir().join("one-billion-rows-buffer-right.txt");
+        std::fs::write(&left, "{a=1.0/1.0/1.0}").unwrap();
+        std::fs::write(&right, "{a=2.0/2.0/2.0}").unwrap();
+        let args = Args::parse_from([
+            OsStr::new("one-billion-rows"),
+            OsStr::new("--compare"),
+            left.as_os_str(),
+            right.as_os_str(),
+        ]);
+        let mut output = RecordingWriter::default();
+        let compared = calculate(args, &mut output);
+        std::fs::remove_file(left).unwrap();
+        std::fs::remove_file(right).unwrap();
+
+        assert!(matches!(compared, Err(Error::VerificationMismatch(_))));
+        assert!(String::from_utf8(output.data)
+            .unwrap()
+            .ends_with("# 1 of 1 cities differ\n"),);
+    }
+
+    #[test]
+    fn check_against_test_data() {
+        check(
+            PathBuf::from("data/test.txt"),
+            PathBuf::from("data/test_res.txt"),
+        );
+    }
+
+    #[test]
+    fn check_against_test_data_assume_ascii() {
+        check_with_args(
+            test_args(Path::new("data/test.txt"), &["--assume-ascii"]),
+            PathBuf::from("data/test_res.txt"),
+        );
+    }
+
+    #[test]
+    fn check_validate_utf8_once() {
+        check_with_args(
+            test_args(Path::new("data/test.txt"), &["--validate-utf8-once"]),
+            PathBuf::from("data/test_res.txt"),
+        );
+
+        let path = std::env::temp_dir().join("one-billion-rows-validate-utf8-once.txt");
+        std::fs::write(&path, b"Z\xc3\xbcrich;1.0\na;2\xff.0\n").unwrap();
+        let result = calculate(test_args(&path, &["--validate-utf8-once"]), Vec::new());
+        std::fs::remove_file(path).unwrap();
+
+        match result {
+            Err(Error::MalformedData(message)) => {
+                assert!(message.ends_with("invalid utf8 at byte 15"), "{message}")
+            }
+            other => panic!("expected invalid utf8, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn check_against_test_data_mmap_zerocopy() {
+        check_with_args(
+            test_args(Path::new("data/test.txt"), &["--engine=mmap-zerocopy"]),
+            PathBuf::from("data/test_res.txt"),
+        );
+    }
+
+    #[test]
+    fn check_first_seen_order() {
+        let mut args = test_args(Path::new("data/test.txt"), &["--order=first-seen"]);
+        args.threads = 4;
+        let mut result = Vec::new();
+        calculate(args, &mut result).unwrap();
+        let result = String::from_utf8(result).unwrap();
+
+        let mut expected = Vec::new();
+        let data = std::fs::read_to_string("data/test.txt").unwrap();
+        for line in data.lines() {
+            let city = line.split_once(';').unwrap().0;
+            if !expected.contains(&city) {
+                expected.push(city);
+            }
+        }
+
+        let cities: Vec<_> = result[1..result.len() - 1]
+            .split(", ")
+            .map(|entry| entry.split_once('=').unwrap().0)
+            .collect();
+        assert_eq!(expected, cities);
+    }
+
+    #[test]
+    fn check_binary_records() {
+        let mut data = Vec::new();
+        for (city, tenths) in [("a", 10i16), ("bb", -25), ("a", 30)] {
+            data.push(city.len() as u8);
+            data.extend_from_slice(city.as_bytes());
+            data.extend_from_slice(&tenths.to_le_bytes());
+        }
+
+        let (sender, receiver) = unbounded();
+        let mut cursor = Cursor::new(&data);
+        while let Some(chunk) = StrBuffer::read_records_from(&mut cursor).unwrap() {
+            sender.send(chunk).unwrap();
+        }
+        drop(sender);
+        let (result, _) =
+            process_records(receiver, ParseOptions::default(), CityMap::default()).unwrap();
+        let result = result.into_result().unwrap();
+
+        assert_eq!(result["a"].count, 2);
+        assert_eq!(result["a"].sum, 4.0);
+        assert_eq!(result["bb"].min, -2.5);
+
+        data.pop();
+        let mut cursor = Cursor::new(&data);
+        let chunk = StrBuffer::read_records_from(&mut cursor).unwrap().unwrap();
+        assert_eq!(chunk.as_bytes().len(), data.len() - 3);
+        assert!(StrBuffer::read_records_from(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn check_worker_count_small_file() {
+        let path = std::env::temp_dir().join("one-billion-rows-worker-count.txt");
+        std::fs::write(&path, "a;1.0\n".repeat(1024 / 6)).unwrap();
+
+        let workers = worker_count(64, std::slice::from_ref(&path)).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(workers, 1);
+    }
+
+    #[test]
+    fn check_byte_range() {
+        let mut data = String::new();
+        File::open("data/test.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+
+        // a range starting in the middle of a line starts with the next line,
+        // a line starting in the range is included even if it ends after it
+        let line_starts: Vec<_> = data.match_indices('\n').map(|(i, _)| i + 1).collect();
+        let (first, last) = (line_starts[10], line_starts[20]);
+        let range_args = test_args(
+            Path::new("data/test.txt"),
+            &[
+                &format!("--offset={}", first - 3),
+                &format!("--length={}", last - first + 6),
+            ],
+        );
+
+        let path = std::env::temp_dir().join("one-billion-rows-byte-range.txt");
+        std::fs::write(&path, &data[first..line_starts[21]]).unwrap();
+        let mut expected = Vec::new();
+        let expected_result = calculate(test_args(&path, &[]), &mut expected);
+        std::fs::remove_file(path).unwrap();
+        expected_result.unwrap();
+
+        let mut result = Vec::new();
+        calculate(range_args, &mut result).unwrap();
+        assert_eq!(from_utf8(&result).unwrap(), from_utf8(&expected).unwrap());
+
+        let mut result = Vec::new();
+        calculate(
+            test_args(Path::new("data/test.txt"), &["--offset=0", "--length=0"]),
+            &mut result,
+        )
+        .unwrap();
+        assert_eq!(from_utf8(&result).unwrap(), "{}");
+    }
+
+    #[test]
+    fn check_reverse_matches_forward() {
+        for (extra_args, threads) in [
+            (&["--order=first-seen"][..], 1),
+            (&["--order=first-seen", "--assume-ascii"], 4),
+            (&["--order=first-seen", "--offset=1000", "--length=5000"], 1),
+        ] {
+            let mut outputs = Vec::new();
+            for reverse in [false, true] {
+                let mut args = test_args(Path::new("data/test.txt"), extra_args);
+                args.threads = threads;
+                args.reverse = reverse;
+                let mut output = Vec::new();
+                calculate(args, &mut output).unwrap();
+                outputs.push(String::from_utf8(output).unwrap());
+            }
+
+            assert_eq!(outputs[0], outputs[1]);
+        }
+    }
+
+    #[test]
+    fn check_adaptive_block_size() {
+        let mut block_size = AdaptiveBlockSize::new();
+        assert_eq!(block_size.adapt(0, Some(10)), 2 * BLOCK_SIZE);
+        assert_eq!(block_size.adapt(5, Some(10)), 2 * BLOCK_SIZE);
+        assert_eq!(block_size.adapt(10, Some(10)), BLOCK_SIZE);
+        assert_eq!(block_size.adapt(10, Some(10)), BLOCK_SIZE);
+        assert_eq!(block_size.adapt(5, None), BLOCK_SIZE);
+        for _ in 0..20 {
+            block_size.adapt(0, Some(10));
+        }
+        assert_eq!(block_size.size, MAX_BLOCK_SIZE);
+
+        let pool = BlockPool::new();
+        let _ = pool.returned.send(new_block(BLOCK_SIZE));
+        assert_eq!(pool.take_sized(2 * BLOCK_SIZE).len(), 2 * BLOCK_SIZE);
+        // the returned block has the wrong size and was freed
+        assert!(pool.free.is_empty());
+
+        // float sums depend on how the rows are split into chunks, exact sums do not
+        for (extra_args, threads) in [(&["--order=first-seen"][..], 1), (&["--spec-strict"], 4)] {
+            let mut outputs = Vec::new();
+            for adaptive in [false, true] {
+                let mut args = test_args(Path::new("data/all_cities.txt"), extra_args);
+                args.threads = threads;
+                args.adaptive_block_size = adaptive;
+                let mut output = Vec::new();
+                calculate(args, &mut output).unwrap();
+                outputs.push(String::from_utf8(output).unwrap());
+            }
+
+            assert_eq!(outputs[0], outputs[1]);
+        }
+    }
+
+    #[test]
+    fn check_bom() {
+        for extra_args in [
+            &[][..],
+            &["--reverse"],
+            &["--offset=0", "--length=100"],
+            &["--validate-utf8-once"],
+            &["--engine=mmap-zerocopy"],
+        ] {
+            let mut result = Vec::new();
+            calculate(
+                test_args(Path::new("data/bom.txt"), extra_args),
+                &mut result,
+            )
+            .unwrap();
+            let result = from_utf8(&result).unwrap();
+            assert!(result.starts_with("{1I="), "{extra_args:?}: {result}");
+            assert!(!result.contains('\u{feff}'), "{extra_args:?}: {result}");
+        }
+        check(
+            PathBuf::from("data/bom.txt"),
+            PathBuf::from("data/test_res.txt"),
+        );
+    }
+
+    #[test]
+    fn check_columns() {
+        let path = std::env::temp_dir().join("one-billion-rows-columns.txt");
+        let rows: String = std::fs::read_to_string("data/test.txt")
+            .unwrap()
+            .lines()
+            .enumerate()
+            .map(|(id, row)| {
+                let (city, value) = row.split_once(';').unwrap();
+                format!("{id},{value},{city},extra\n")
+            })
+            .collect();
+        std::fs::write(&path, rows).unwrap();
+
+        let columns = ["--separator=,", "--city-column=2", "--value-column=1"];
+        check_with_args(
+            test_args(&path, &columns),
+            PathBuf::from("data/test_res.txt"),
+        );
+        check_with_args(
+            test_args(&path, &[&columns[..], &["--engine=mmap-zerocopy"]].concat()),
+            PathBuf::from("data/test_res.txt"),
+        );
+        let out_of_range = calculate(
+            test_args(
+                &path,
+                &["--separator=,", "--city-column=4", "--value-column=1"],
+            ),
+            Vec::new(),
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        match out_of_range {
+            Err(Error::MalformedData(message)) => {
+                assert!(
+                    message.starts_with("--city-column 4 is out of range"),
+                    "{message}"
+                );
+                assert!(message.ends_with("has 4 fields"), "{message}");
+            }
+            other => panic!("expected malformed data, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn check_window() {
+        let path = std::env::temp_dir().join("one-billion-rows-window.txt");
+        let window = |rows: &str, extra_args: &[&str]| {
+            std::fs::write(&path, rows).unwrap();
+            let columns = ["--separator=,", "--city-column=2", "--value-column=1"];
+            let mut outputs = Vec::new();
+            for engine in ["--engine=chunked", "--engine=mmap-zerocopy"] {
+                let args = test_args(&path, &[&columns[..], extra_args, &[engine]].concat());
+                let mut output = Vec::new();
+                calculate(args, &mut output)?;
+                outputs.push(String::from_utf8(output).unwrap());
+            }
+            assert_eq!(outputs[0], outputs[1]);
+            Ok::<_, Error>(outputs.remove(0))
+        };
+
+        let rows = "100,1.0,a\n105,2.0,a\n110,3.0,b\n106,4.0,a\n";
+        let last_five = window(rows, &["--timestamp-column=0", "--window=5"]);
+        let all = window(rows, &["--timestamp-column=0", "--window=11"]);
+        let malformed = format!("{rows}now,5.0,c\n");
+        let strict = window(&malformed, &["--timestamp-column=0", "--window=5"]);
+        let lenient = window(
+            &malformed,
+            &["--timestamp-column=0", "--window=5", "--lenient"],
+        );
+        let same_column = window(rows, &["--timestamp-column=1", "--window=5"]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(last_five.unwrap(), "{a=4.0/4.0/4.0, b=3.0/3.0/3.0}");
+        assert_eq!(all.unwrap(), "{a=1.0/2.3/4.0, b=3.0/3.0/3.0}");
+        assert!(matches!(strict, Err(Error::MalformedData(_))));
+        assert_eq!(lenient.unwrap(), "{a=4.0/4.0/4.0, b=3.0/3.0/3.0}");
+        assert!(matches!(same_column, Err(Error::InvalidArguments(_))));
+    }
+
+    #[test]
+    fn check_compressed_input() {
+        // the header of an empty bgzip member
+        let bgzf = b"\x1f\x8b\x08\x04\0\0\0\0\0\xff\x06\0BC\x02\0\x1b\0";
+        assert_eq!(compression(bgzf), Some("bgzip"));
+        assert_eq!(compression(&bgzf[..12]), Some("gzip"));
+        assert_eq!(compression(b"\x1f\x8b\x08\x00\0\0\0\0\0\xff"), Some("gzip"));
+        let error = compressed_input("data.txt.gz", "bgzip").to_string();
+        assert!(error.ends_with(
+            "decompress it first, e.g. in parallel with `bgzip --decompress --threads N` \
+             or build with `--features compression` to read it"
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn check_compressed_input_without_feature() {
+        let path = std::env::temp_dir().join("one-billion-rows-compressed.txt");
+        for (start, compression) in [
+            (&[0x1f, 0x8b, 0x08, 0x00], "gzip"),
+            (&[0x28, 0xb5, 0x2f, 0xfd], "zstd"),
+        ] {
+            std::fs::write(&path, start).unwrap();
+            let result = calculate(test_args(&path, &[]), Vec::new());
+
+            match result {
+                Err(Error::Io(e)) => {
+                    assert_eq!(e.kind(), ErrorKind::Unsupported);
+                    assert!(e.to_string().ends_with(&format!(
+                        "is {compression} compressed, decompress it first \
+                         or build with `--features compression` to read it"
+                    )));
+                }
+                other => panic!("expected an unsupported error, got {other:?}"),
+            }
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn check_zstd_input() {
+        let dir = std::env::temp_dir().join("one-billion-rows-zstd-input");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.txt.zst");
+        let data = std::fs::read("data/test.txt").unwrap();
+        std::fs::write(&path, zstd::encode_all(&data[..], 3).unwrap()).unwrap();
+
+        check_with_args(test_args(&path, &[]), PathBuf::from("data/test_res.txt"));
+        let mut emitted = Vec::new();
+        calculate(test_args(&path, &["--emit-every=60"]), &mut emitted).unwrap();
+        let expected = std::fs::read_to_string("data/test_res.txt").unwrap();
+        assert_eq!(from_utf8(&emitted).unwrap(), format!("{expected}\n"));
+
+        // the rows of a compressed file cannot be split between the workers
+        let result = calculate(test_args(&path, &["data/test.txt"]), Vec::new());
+        std::fs::remove_dir_all(dir).unwrap();
+        assert!(matches!(result, Err(Error::InvalidArguments(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn check_gzip_input() {
+        use flate2::{write::GzEncoder, Compression, GzBuilder};
+
+        let dir = std::env::temp_dir().join("one-billion-rows-gzip-input");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = std::fs::read("data/test.txt").unwrap();
+
+        // members that end in the middle of a line, once with the plain gzip
+        // header and once with the header of bgzip
+        let mut gzip = Vec::new();
+        let mut bgzip = Vec::new();
+        for part in data.chunks(100) {
+            let mut encoder = GzEncoder::new(&mut gzip, Compression::default());
+            encoder.write_all(part).unwrap();
+            encoder.finish().unwrap();
+
+            let mut member = GzBuilder::new()
+                .extra(*b"BC\x02\0\0\0")
+                .write(Vec::new(), Compression::default());
+            member.write_all(part).unwrap();
+            let mut member = member.finish().unwrap();
+            let size = (member.len() - 1) as u16;
+            member[16..18].copy_from_slice(&size.to_le_bytes());
+            bgzip.extend(member);
+        }
+        assert_eq!(compression(&gzip), Some("gzip"));
+        assert_eq!(compression(&bgzip), Some("bgzip"));
+
+        for (name, compressed) in [("test.txt.gz", &gzip), ("test.txt.bgz", &bgzip)] {
+            let path = dir.join(name);
+            std::fs::write(&path, compressed).unwrap();
+            for threads in [1, 4] {
+                let mut args = test_args(&path, &[]);
+                args.threads = threads;
+                check_with_args(args, PathBuf::from("data/test_res.txt"));
+            }
+        }
+
+        // a member without the size of bgzip cannot be skipped without decompressing it
+        let path = dir.join("mixed.txt.bgz");
+        std::fs::write(&path, [&bgzip[..], &gzip[..]].concat()).unwrap();
+        let result = calculate(test_args(&path, &[]), Vec::new());
+        std::fs::remove_dir_all(dir).unwrap();
+        match result {
+            Err(Error::MalformedData(message)) => {
+                assert!(message.ends_with("the gzip member at byte 8582 is not a bgzip member"))
+            }
+            other => panic!("expected a malformed data error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn check_external_merge() {
+        let dir = std::env::temp_dir().join("one-billion-rows-external-merge");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut args = test_args(
+            Path::new("data/test.txt"),
+            &[
+                "--external-merge",
+                dir.to_str().unwrap(),
+                "--spill-cities=3",
+            ],
+        );
+        args.threads = 4;
+        check_with_args(args, PathBuf::from("data/test_res.txt"));
+        assert_eq!(dir.read_dir().unwrap().count(), 0, "all runs are removed");
+    }
+
+    #[test]
+    fn check_extra_field_is_malformed() {
+        let path = std::env::temp_dir().join("one-billion-rows-extra-field.txt");
+        std::fs::write(&path, "a;1.0;2.0\nb;3.0\n").unwrap();
+        let mut results = Vec::new();
+        for engine_args in [&[][..], &["--assume-ascii"], &["--engine=mmap-zerocopy"]] {
+            let strict = calculate(test_args(&path, engine_args), Vec::new());
+            let mut output = Vec::new();
+            let lenient_args = [engine_args, &["--lenient", "--expect-lines=2"]].concat();
+            let lenient = calculate(test_args(&path, &lenient_args), &mut output);
+            results.push((strict, lenient, output));
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        for (strict, lenient, output) in results {
+            match strict {
+                Err(Error::MalformedData(message)) => {
+                    assert_eq!(message, "malformed line: \"a;1.0;2.0\"")
+                }
+                other => panic!("expected malformed data, got {other:?}"),
+            }
+            lenient.unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), "{b=3.0/3.0/3.0}");
+        }
+    }
+
+    #[test]
+    fn check_sorted_merge() {
+        let mut args = test_args(Path::new("data/test.txt"), &["--sorted-merge"]);
+        args.threads = 4;
+        check_with_args(args, PathBuf::from("data/test_res.txt"));
+
+        // --show-count and --show-stddev cannot be used together
+        for show in ["--show-count", "--show-stddev"] {
+            let mut outputs = Vec::new();
+            for extra_args in [
+                &[show][..],
+                &[show, "--sorted-merge", "--expect-lines=200000"],
+            ] {
+                let mut output = Vec::new();
+                calculate(
+                    test_args(Path::new("data/all_cities.txt"), extra_args),
+                    &mut output,
+                )
+                .unwrap();
+                outputs.push(String::from_utf8(output).unwrap());
+            }
+            assert_eq!(outputs[0], outputs[1], "{show}");
+        }
+
+        let first_seen = test_args(
+            Path::new("data/test.txt"),
+            &["--sorted-merge", "--order=first-seen"],
+        );
+        assert!(matches!(
+            calculate(first_seen, Vec::new()),
+            Err(Error::InvalidArguments(_))
+        ));
+    }
+
+    #[test]
+    fn check_spec_strict() {
+        check_with_args(
+            test_args(Path::new("data/test.txt"), &["--spec-strict"]),
+            PathBuf::from("data/test_res.txt"),
+        );
+
+        let spec_strict = |name: &str, data: &str| {
+            let path = std::env::temp_dir().join(format!("one-billion-rows-spec-{name}.txt"));
+            std::fs::write(&path, data).unwrap();
+            let mut output = Vec::new();
+            let result = calculate(test_args(&path, &["--spec-strict"]), &mut output);
+            std::fs::remove_file(path).unwrap();
+            result.map(|_| String::from_utf8(output).unwrap())
+        };
+
+        // means are rounded half up, and never printed as -0.0
+        let data = "a;0.0\na;0.1\nb;-0.1\nb;0.0\nc;-0.1\nc;-0.2\nd;99.9\nd;-99.9\nd;0.1\n";
+        assert_eq!(
+            spec_strict("rounding", data).unwrap(),
+            "{a=0.0/0.1/0.1, b=-0.1/0.0/0.0, c=-0.2/-0.1/-0.1, d=-99.9/0.0/99.9}"
+        );
+        let long_name = "x".repeat(100);
+        assert!(spec_strict("long-name", &format!("{long_name};1.0\n")).is_ok());
+
+        for (name, data) in [
+            ("too-long-name", format!("{long_name}x;1.0\n")),
+            ("empty-name", ";1.0\n".to_string()),
+            ("out-of-range", "a;100.0\n".to_string()),
+            ("two-decimals", "a;1.25\n".to_string()),
+            ("no-decimal", "a;1\n".to_string()),
+        ] {
+            let result = spec_strict(name, &data);
+            assert!(
+                matches!(result, Err(Error::MalformedData(_))),
+                "{name}: {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn check_aggregate_slice() {
+        let data = std::fs::read("data/test.txt").unwrap();
+        let expected = std::fs::read_to_string("data/test_res.txt").unwrap();
+        for threads in [0, 1, 4] {
+            let results = aggregate_slice(&data, threads).unwrap();
+            assert_eq!(results.to_string(), expected);
+        }
+
+        let results = aggregate_slice(b"b;1.0\na;-2.5\r\nb;3.0", 2).unwrap();
+        assert_eq!(results.to_string(), "{a=-2.5/-2.5/-2.5, b=1.0/2.0/3.0}");
+        assert_eq!(results.get("b").unwrap().count(), 2);
+        assert!(aggregate_slice(b"", 1).unwrap().is_empty());
+        assert!(matches!(
+            aggregate_slice(b"a;x\n", 1),
+            Err(Error::MalformedData(_))
+        ));
+    }
+
+    #[test]
+    fn check_keys_only() {
+        let path = std::env::temp_dir().join("one-billion-rows-keys-only.txt");
+        // values are never parsed, so neither the invalid value nor the bounds matter
+        std::fs::write(&path, "b;3.0\na;not a value\nb;-4.0\nc;500.0\n").unwrap();
+
+        let mut output = Vec::new();
+        let args = ["--keys-only", "--expect-min=-10", "--expect-max=10"];
+        let result = calculate(test_args(&path, &args), &mut output);
+        std::fs::remove_file(path).unwrap();
+
+        result.unwrap();
+        assert_eq!(from_utf8(&output).unwrap(), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn check_int_values() {
+        let path = std::env::temp_dir().join("one-billion-rows-int-values.txt");
+        std::fs::write(&path, "b;3\na;9007199254740993\nb;-4\na;1\nb;2\n").unwrap();
+
+        let mut output = Vec::new();
+        let result = calculate(test_args(&path, &["--value-type=int"]), &mut output);
+        let float_result = calculate(test_args(&path, &[]), Vec::new());
+        let fraction_path = std::env::temp_dir().join("one-billion-rows-int-fraction.txt");
+        std::fs::write(&fraction_path, "a;1.5\n").unwrap();
+        let fraction_result =
+            calculate(test_args(&fraction_path, &["--value-type=int"]), Vec::new());
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(fraction_path).unwrap();
+
+        result.unwrap();
+        assert_eq!(
+            from_utf8(&output).unwrap(),
+            "{a=1/4503599627370497/9007199254740993, b=-4/0/3}"
+        );
+        float_result.unwrap();
+        assert!(matches!(fraction_result, Err(Error::MalformedData(_))));
+    }
+
+    #[test]
+    fn check_auto_separator() {
+        let path = std::env::temp_dir().join("one-billion-rows-auto-separator.txt");
+        std::fs::write(&path, "a,1.0\nb,2.0\na,3.0\n").unwrap();
+
+        let mut output = Vec::new();
+        let args = test_args(&path, &["--separator=auto"]);
+        let result = calculate(args, &mut output);
+        let mut strict_output = Vec::new();
+        let strict = calculate(test_args(&path, &[]), &mut strict_output);
+        std::fs::remove_file(path).unwrap();
+
+        result.unwrap();
+        assert_eq!(
+            from_utf8(&output).unwrap(),
+            "{a=1.0/2.0/3.0, b=2.0/2.0/2.0}"
+        );
+        assert!(matches!(strict, Err(Error::MalformedData(_))));
+    }
+
+    #[test]
+    #[ignore]
+    fn check_against_full_data() {
+        check(
+            PathBuf::from("data/all_cities.txt"),
+            PathBuf::from("data/all_cities_res.txt"),
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn check_against_cities400_data() {
+        check(
+            PathBuf::from("data/cities_400.txt"),
+            PathBuf::from("data/cities_400_res.txt"),
+        );
+    }
+
+    #[test]
+    fn check_read_str_buffer() {
+        let mut file =
+            File::open(PathBuf::from("data/test.txt")).expect("could not open input file");
+        let mut full_data = String::new();
+        while let Some(chunk) = StrBuffer::read_from(&mut file).expect("Failed to read TextChunk") {
+            full_data.push_str(&chunk);
+        }
+
+        file.seek(std::io::SeekFrom::Start(0)).unwrap();
+        let mut expected = String::new();
+        file.read_to_string(&mut expected).unwrap();
+
+        assert_eq!(expected, full_data);
+    }
+
+    #[test]
+    fn check_read_single_str_buffer() {
+        let mut file =
+            File::open(PathBuf::from("data/all_cities.txt")).expect("could not open input file");
+        let _chunk = StrBuffer::read_from(&mut file).unwrap().unwrap();
+        let mut b = [0u8];
+        file.read_exact(&mut b).unwrap();
+        assert_ne!(b[0], b'\n');
+    }
+
+    #[test]
+    fn check_lenient_skips_malformed_lines() {
+        let (result, skipped) =
+            process_str("a;1.0\nno separator\nb;not a number\na;3.0\n", true).unwrap();
+
+        assert_eq!(skipped, 2);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result["a"].count, 2);
+        assert_eq!(result["a"].sum, 4.0);
+    }
+
+    #[test]
+    fn check_min_max_of_single_sign_cities() {
+        let (result, _) = process_str("pos;5.0\nneg;-5.0\npos;7.5\nneg;-2.5\n", false).unwrap();
+
+        assert_eq!(result["pos"].min, 5.0);
+        assert_eq!(result["pos"].max, 7.5);
+        assert_eq!(result["neg"].min, -5.0);
+        assert_eq!(result["neg"].max, -2.5);
+    }
+
+    #[test]
+    fn check_normalize_names_lower() {
+        let (result, _) = process_str(
+            "Hamburg;1.0\nhamburg;3.0\nZÜRICH;-1.0\nHAMBURG;-1.0\nzürich;2.0\n",
+            false,
+        )
+        .unwrap();
+
+        let unchanged = normalize_names(result, NormalizeNames::None);
+        assert_eq!(unchanged.len(), 5);
+
+        let result = normalize_names(unchanged, NormalizeNames::Lower);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result["hamburg"].count, 3);
+        assert_eq!(result["hamburg"].min, -1.0);
+        assert_eq!(result["hamburg"].max, 3.0);
+        assert_eq!(result["hamburg"].first_seen, (0, 0));
+        assert_eq!(result["zürich"].count, 2);
+    }
+
+    #[test]
+    fn check_normalize_names_first_seen() {
+        let path = std::env::temp_dir().join("one-billion-rows-first-seen.txt");
+        // the casings alternate in every block, so every worker sees all of them
+        let mut data = String::from("zürich;0.0\n");
+        for i in 0..2000 {
+            let city = ["HAMBURG", "Hamburg", "hamburg", "ZÜRICH"][i % 4];
+            data.push_str(&format!("{city};{}.0\n", i % 10));
+        }
+        std::fs::write(&path, data).unwrap();
+
+        let outputs: Vec<_> = (0..8)
+            .map(|_| {
+                let mut args = test_args(&path, &["--normalize-names=first-seen"]);
+                args.threads = 4;
+                let mut output = Vec::new();
+                calculate(args, &mut output).map(|_| output)
+            })
+            .collect();
+        std::fs::remove_file(path).unwrap();
+
+        for output in outputs {
+            assert_eq!(
+                from_utf8(&output.unwrap()).unwrap(),
+                "{HAMBURG=0.0/4.3/9.0, zürich=0.0/5.0/9.0}"
+            );
+        }
+    }
+
+    #[test]
+    fn check_city_entry_accessors() {
+        let (result, _) = process_str("a;1.0\na;-2.0\na;4.0\n", false).unwrap();
+
+        let a = &result["a"];
+        assert_eq!(a.min(), -2.0);
+        assert_eq!(a.max(), 4.0);
+        assert_eq!(a.mean(), 1.0);
+        assert_eq!(a.count(), 3);
+        assert!((a.stddev() - 6f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn check_merge_count_at_capacity() {
+        let mut entry = CityEntry {
+            count: usize::MAX - 2,
+            ..CityEntry::default()
+        };
+        entry.merge(&CityEntry {
+            count: 2,
+            ..CityEntry::default()
+        });
+        assert_eq!(entry.count, usize::MAX);
+    }
+
+    #[test]
+    fn check_count_overflow() {
+        let overflow = |entry: &CityEntry| match entry.check_count("a") {
+            Err(Error::MalformedData(message)) => {
+                assert_eq!(message, "more than usize::MAX rows for city \"a\"")
+            }
+            other => panic!("expected an overflow, got {other:?}"),
+        };
+
+        let mut entry = CityEntry {
+            count: usize::MAX,
+            ..CityEntry::default()
+        };
+        entry.merge(&CityEntry {
+            count: 1,
+            ..CityEntry::default()
+        });
+        overflow(&entry);
+
+        let mut entry = CityEntry {
+            count: usize::MAX,
+            ..CityEntry::default()
+        };
+        entry.add(1.0);
+        overflow(&entry);
+        // the overflow is kept by later merges
+        let mut merged = CityEntry::default();
+        merged.merge(&entry);
+        overflow(&merged);
+
+        let cities = HashMap::from([(b"a".to_vec(), entry)]);
+        assert!(matches!(
+            into_string_keys(cities),
+            Err(Error::MalformedData(_))
+        ));
+    }
+
+    #[test]
+    fn check_merge_finished_results() {
+        let (sender, receiver) = unbounded();
+        sender
+            .send(process_str_into("a;1.0\nb;2.0\n", true))
+            .unwrap();
+        sender
+            .send(process_str_into("a;-1.0\nbad\n", true))
+            .unwrap();
+
+        let own = process_str_into::<CityMap>("c;3.0\n", true);
+        let (merged, skipped) = merge_finished(own, &receiver).unwrap();
+        let merged = merged.into_result().unwrap();
+        assert!(receiver.is_empty());
+        assert_eq!(skipped, 1);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged["a"].count, 2);
+
+        sender.send(process_str_into("bad\n", false)).unwrap();
+        let own = process_str_into::<CityMap>("c;3.0\n", false);
+        assert!(merge_finished(own, &receiver).is_err());
+    }
+
+    #[test]
+    fn check_against_test_data_many_threads() {
+        let mut args = test_args(Path::new("data/test.txt"), &[]);
+        args.threads = 8;
+        check_with_args(args, PathBuf::from("data/test_res.txt"));
+    }
+
+    #[test]
+    fn check_merge_of_partial_results() {
+        let (sender, receiver) = unbounded();
+        sender
+            .send(process_str_into("both;1.0\nboth;4.0\nfirst;-3.0\n", false))
+            .unwrap();
+        sender
+            .send(process_str_into(
+                "both;-2.0\nboth;-0.5\nsecond;2.0\n",
+                false,
+            ))
+            .unwrap();
+        drop(sender);
+
+        let (result, _) = combine_results(receiver, CityMap::default(), None).unwrap();
+        let result = result.into_result().unwrap();
+
+        let both = &result["both"];
+        assert_eq!(both.min, -2.0);
+        assert_eq!(both.max, 4.0);
+        assert_eq!(both.count, 4);
+        assert_eq!(both.sum, 2.5);
+
+        assert_eq!(result["first"].min, -3.0);
+        assert_eq!(result["first"].max, -3.0);
+        assert_eq!(result["second"].min, 2.0);
+        assert_eq!(result["second"].max, 2.0);
+    }
+
+    #[test]
+    fn check_newline_aligned_boundary() {
+        assert_eq!(newline_aligned_boundary(b"a;1.0\nb;2.0\n", true), Some(12));
+        assert_eq!(newline_aligned_boundary(b"a;1.0\nb;2.0\n", false), Some(6));
+        assert_eq!(newline_aligned_boundary(b"a;1.0\nb;2", true), Some(6));
+        assert_eq!(newline_aligned_boundary(b"a;1.0", true), None);
+        assert_eq!(newline_aligned_boundary(b"a;1.0", false), None);
+        assert_eq!(newline_aligned_boundary(b"", true), None);
+        assert_eq!(newline_aligned_boundary(b"\n", true), Some(1));
+        assert_eq!(newline_aligned_boundary(b"\n", false), Some(1));
+    }
+
+    #[test]
+    fn check_block_reuse() {
+        let pool = BlockPool::new();
+        let mut chunk = StrBuffer::read_from(&mut Cursor::new(b"a;1.0\n"))
+            .unwrap()
+            .unwrap();
+        chunk.pool = Some(pool.returned.clone());
+        let block = chunk.raw_data.as_ptr();
+        drop(chunk);
+
+        let reused = pool.take();
+        assert_eq!(reused.as_ptr(), block);
+        // the pool is empty again, so the next block is a new one
+        let new = pool.take();
+        assert_ne!(new.as_ptr(), block);
+    }
+
+    #[test]
+    fn check_snapshots() {
+        let path = std::env::temp_dir().join("one-billion-rows-snapshot.txt");
+        let write = |cities: &CityMap, output: &mut File| {
+            let results = Results::new(cities.clone().into_result()?, Order::Sorted);
+            Ok(write!(output, "{results}")?)
+        };
+        let snapshot = Snapshot {
+            path: &path,
+            every: Duration::from_millis(10),
+            write: &write,
+        };
+
+        let (sender, receiver) = unbounded();
+        let producer = thread::spawn(move || {
+            sender.send(process_str_into("a;1.0\n", false)).unwrap();
+            thread::sleep(Duration::from_millis(200));
+            sender.send(process_str_into("b;2.0\n", false)).unwrap();
+        });
+        let (result, _) = combine_results(receiver, CityMap::default(), Some(snapshot)).unwrap();
+        producer.join().unwrap();
+        let snapshot = std::fs::read_to_string(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        // the last snapshot is written before the second partial result arrives
+        assert_eq!(snapshot.unwrap(), "{a=1.0/1.0/1.0}");
+        assert_eq!(result.into_result().unwrap().len(), 2);
+    }
+
+    /// Counts the rows with a positive value
+    #[derive(Default, Clone)]
+    struct PositiveCount(usize);
+
+    impl RowSink for PositiveCount {
+        fn accept(&mut self, _city: &[u8], value: f32) {
+            if value > 0.0 {
+                self.0 += 1;
+            }
+        }
+
+        fn merge(&mut self, other: Self) {
+            self.0 += other.0;
+        }
+    }
+
+    #[test]
+    fn check_custom_row_sink() {
+        let (count, _) =
+            process_str_into::<PositiveCount>("a;1.0\nb;-1.0\na;0.0\nc;2.5\n", false).unwrap();
+        assert_eq!(count.0, 2);
+
+        let mut data = String::new();
+        File::open("data/test.txt")
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        let expected = data
+            .lines()
+            .filter(|line| line.rsplit_once(';').unwrap().1.parse::<f32>().unwrap() > 0.0)
+            .count();
+
+        let mut args = test_args(Path::new("data/test.txt"), &[]);
+        args.threads = 4;
+        let (count, _) = aggregate(&args, PositiveCount::default()).unwrap();
+        assert_eq!(count.0, expected);
+    }
+
+    #[test]
+    fn check_empty_city_is_malformed() {
+        let (result, skipped) = process_str("a;1.0\n;12.3\n", true).unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(result.len(), 1);
+
+        let path = std::env::temp_dir().join("one-billion-rows-empty-city.txt");
+        std::fs::write(&path, "a;1.0\n;12.3\n").unwrap();
+        let results: Vec<_> = [&[][..], &["--spec-strict"], &["--engine=mmap-zerocopy"]]
+            .into_iter()
+            .map(|extra| calculate(test_args(&path, extra), Vec::new()))
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        for result in results {
+            match result {
+                Err(Error::MalformedData(message)) => {
+                    assert_eq!(message, "malformed line: \";12.3\"")
+                }
+                other => panic!("expected a malformed line, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn check_strict_fails_on_malformed_line() {
+        let Err(error) = process_str("a;1.0\nno separator\n", false) else {
+            panic!("expected malformed line to fail");
+        };
+        assert!(matches!(error, Error::MalformedData(_)));
+    }
+
+    #[test]
+    fn check_expected_bounds() {
+        check_with_args(
+            test_args(
+                Path::new("data/test.txt"),
+                &["--expect-min=-99.9", "--expect-max=99.9"],
+            ),
+            PathBuf::from("data/test_res.txt"),
+        );
+
+        for engine in ["--engine=chunked", "--engine=mmap-zerocopy"] {
+            let args = test_args(
+                Path::new("data/test.txt"),
+                &[engine, "--lenient", "--expect-min=-10", "--expect-max=10"],
+            );
+            let Err(error) = calculate(args, Vec::new()) else {
+                panic!("expected out of bounds value to fail");
+            };
+            assert!(matches!(error, Error::MalformedData(_)), "{engine}");
+        }
+    }
+
+    #[test]
+    fn check_group_by_prefix() {
+        let path = std::env::temp_dir().join("one-billion-rows-group-by-prefix.txt");
+        std::fs::write(
+            &path,
+            "DE-Hamburg;10.0\nFR-Paris;-5.0\nDE-Hamburg;10.0\nSolo;1.0\nDE-Berlin;40.0\n\
+             FR-Paris;5.0\n",
+        )
+        .unwrap();
+        let output = |extra: &[&str]| {
+            let args = test_args(&path, &[extra, &["--group-by-prefix=-"]].concat());
+            let mut output = Vec::new();
+            calculate(args, &mut output).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let sorted = output(&[]);
+        let mmap = output(&["--engine=mmap-zerocopy"]);
+        let first_seen = output(&["--order=first-seen", "--show-count"]);
+        let limited = output(&["--limit-cities=1"]);
+        std::fs::remove_file(&path).unwrap();
+
+        // the mean of a group is weighted by the rows of its cities
+        assert_eq!(
+            sorted,
+            "{DE-Berlin=40.0/40.0/40.0, DE-Hamburg=10.0/10.0/10.0, FR-Paris=-5.0/0.0/5.0, \
+             Solo=1.0/1.0/1.0}\n{DE=10.0/20.0/40.0, FR=-5.0/0.0/5.0}"
+        );
+        assert_eq!(mmap, sorted);
+        assert!(
+            first_seen.ends_with("\n{DE=10.0/20.0/40.0/3, FR=-5.0/0.0/5.0/2}"),
+            "{first_seen}"
+        );
+        assert!(limited.ends_with("\n{DE=10.0/20.0/40.0, FR=-5.0/0.0/5.0}"));
+    }
+
+    #[test]
+    fn check_bins_merged_across_threads() {
+        // the histograms and value counts of the workers are added bin by bin
+        for (input, extra) in [
+            ("data/all_cities.txt", &["--histogram=16"][..]),
+            (
+                "data/all_cities.txt",
+                &["--histogram=7", "--expect-min=-100", "--expect-max=100"],
+            ),
+            // every city counts 1999 values, so only the few cities of the test data
+            (
+                "data/test.txt",
+                &["--dedup-values", "--dedup-threshold=0.01"],
+            ),
+        ] {
+            let output = |threads| {
+                let mut args = test_args(Path::new(input), extra);
+                args.threads = threads;
+                let mut output = Vec::new();
+                calculate(args, &mut output).unwrap();
+                String::from_utf8(output).unwrap()
+            };
+            assert_eq!(output(1), output(4), "{extra:?}");
+        }
+    }
+
+    #[test]
+    fn check_line_length() {
+        check_with_args(
+            test_args(
+                Path::new("data/test.txt"),
+                &["--min-line-length=5", "--max-line-length=106"],
+            ),
+            PathBuf::from("data/test_res.txt"),
+        );
+
+        let path = std::env::temp_dir().join("one-billion-rows-line-length.txt");
+        let long_name = "x".repeat(200);
+        let cases = [
+            ("a;1.0\nb;2", "line is shorter than 5 bytes: \"b;2\""),
+            (
+                &format!("a;1.0\n{long_name};2.0\n"),
+                "line of 204 bytes is longer than 106 bytes: \"xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx\"...",
+            ),
+        ];
+        for (data, expected) in cases {
+            std::fs::write(&path, data).unwrap();
+            for extra in [
+                &["--lenient"][..],
+                &["--assume-ascii"],
+                &["--engine=mmap-zerocopy"],
+            ] {
+                let args = test_args(
+                    &path,
+                    &[extra, &["--min-line-length=5", "--max-line-length=106"]].concat(),
+                );
+                match calculate(args, Vec::new()) {
+                    Err(Error::MalformedData(message)) => assert_eq!(message, expected),
+                    other => panic!("expected a malformed line for {extra:?}, got {other:?}"),
+                }
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let args = test_args(
+            Path::new("data/test.txt"),
+            &["--min-line-length=10", "--max-line-length=5"],
+        );
+        assert!(matches!(
+            calculate(args, Vec::new()),
+            Err(Error::InvalidArguments(_))
+        ));
+    }
+
+    #[test]
+    fn check_expect_lines() {
+        for extra in [&[][..], &["--engine=mmap-zerocopy"], &["--spec-strict"]] {
+            let args = test_args(
+                Path::new("data/test.txt"),
+                &[extra, &["--expect-lines=1000"]].concat(),
+            );
+            calculate(args, Vec::new()).unwrap();
+
+            let args = test_args(
+                Path::new("data/test.txt"),
+                &[extra, &["--expect-lines=1001"]].concat(),
+            );
+            match calculate(args, Vec::new()) {
+                Err(Error::VerificationMismatch(message)) => {
+                    assert_eq!(
+                        message,
+                        "processed 1000 lines, but the input should have 1001"
+                    )
+                }
+                other => panic!("expected a verification mismatch, got {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn check_max_seconds() {
+        let input = [PathBuf::from("data/test.txt")];
+        let (sender, receiver) = unbounded();
+        let read_chunk = StrBuffer::read_text;
+        let stopped =
+            produce_text_chunks(&input, None, sender, read_chunk, None, None, false).unwrap();
+        assert!(!stopped);
+        assert!(receiver.iter().count() > 0);
+
+        let (sender, receiver) = unbounded();
+        let deadline = Some(Instant::now());
+        let stopped =
+            produce_text_chunks(&input, None, sender, read_chunk, None, deadline, false).unwrap();
+        assert!(stopped);
+        assert_eq!(receiver.iter().count(), 0);
+
+        let args = test_args(Path::new("data/test.txt"), &["--max-seconds=60"]);
+        let mut output = Vec::new();
+        calculate(args, &mut output).unwrap();
+        let mut expected = Vec::new();
+        calculate(test_args(Path::new("data/test.txt"), &[]), &mut expected).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_non_regular_input_file() {
+        let args = test_args(Path::new("/dev/null"), &[]);
+        match calculate(args, Vec::new()) {
+            Err(Error::Io(e)) => {
+                assert_eq!(e.kind(), ErrorKind::InvalidInput);
+                assert_eq!(
+                    e.to_string(),
+                    "/dev/null is not a regular file, use `-` to read from stdin"
+                );
+            }
+            other => panic!("expected an invalid input error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn check_missing_input_file() {
+        let args = test_args(Path::new("data/does_not_exist.txt"), &[]);
+        let Err(error) = calculate(args, Vec::new()) else {
+            panic!("expected missing input file to fail");
+        };
+        assert!(matches!(error, Error::FileNotFound(_)));
+        assert_eq!(error.exit_code(), 3);
+    }
+
+    #[test]
+    fn check_invalid_arguments() {
+        let args = test_args(Path::new("data/test.txt"), &["--separator=."]);
+        let Err(error) = calculate(args, Vec::new()) else {
+            panic!("expected the same separators to fail");
+        };
+        assert_eq!(
+            error.to_string(),
+            "invalid arguments: the separator and the decimal separator are both '.'"
+        );
+        assert_eq!(error.exit_code(), 2);
+
+        for option in ["--reverse", "--profile", "--max-seconds=60", "--offset=10"] {
+            let args = test_args(
+                Path::new("data/test.txt"),
+                &["--engine=mmap-zerocopy", option],
+            );
+            match calculate(args, Vec::new()) {
+                Err(Error::InvalidArguments(message)) => {
+                    assert!(message.starts_with("the mmap-zerocopy engine does not support"))
+                }
+                other => panic!("expected {option} to be rejected, got {other:?}"),
+            }
+        }
+
+        let args = Args::try_parse_from(["one-billion-rows", "--emit-every=1"]);
+        assert!(args.is_err());
+    }
+
+    #[test]
+    fn check_crlf_line_endings() {
+        let (result, skipped) = process_str("a;1.0\r\nb;2.0\r\na;3.0\r\n", false).unwrap();
+
+        assert_eq!(skipped, 0);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result["a"].sum, 4.0);
+        assert_eq!(result["b"].sum, 2.0);
+    }
+
+    #[test]
+    fn check_read_without_trailing_newline() {
+        let data = "a;1.0\nb;2.0";
+        let mut cursor = Cursor::new(data.as_bytes());
+        let chunk = StrBuffer::read_from(&mut cursor).unwrap().unwrap();
+
+        assert_eq!(&*chunk, data);
+        assert!(StrBuffer::read_from(&mut cursor).unwrap().is_none());
+    }
+
+    #[test]
+    fn check_read_line_longer_than_block() {
+        let data = vec![b'a'; BLOCK_SIZE + 1];
+        let Err(error) = StrBuffer::read_from(&mut Cursor::new(data)) else {
+            panic!("expected reading a too long line to fail");
+        };
+
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{io::Write, path::PathBuf};
+
+use hashbrown::HashMap;
+
+use crate::{
+    compare::{read_result_file, result_entries},
+    CityStats, Error, ResultSink, TextSink,
+};
+
+/// The values of a city in a result written with `--show-count`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CountedStats {
+    min: f32,
+    mean: f32,
+    max: f32,
+    count: usize,
+}
+
+/// The folded values of a city over all merged results
+#[derive(Debug, Clone, Copy)]
+struct MergedCity {
+    min: f32,
+    max: f32,
+    /// The sum of the means weighted by their number of rows
+    weighted_sum: f64,
+    count: usize,
+}
+
+impl MergedCity {
+    fn new(stats: CountedStats) -> Self {
+        MergedCity {
+            min: stats.min,
+            max: stats.max,
+            weighted_sum: stats.mean as f64 * stats.count as f64,
+            count: stats.count,
+        }
+    }
+
+    fn merge(&mut self, stats: CountedStats) {
+        self.min = self.min.min(stats.min);
+        self.max = self.max.max(stats.max);
+        self.weighted_sum += stats.mean as f64 * stats.count as f64;
+        self.count += stats.count;
+    }
+
+    fn stats(&self) -> CityStats {
+        CityStats {
+            min: self.min,
+            mean: (self.weighted_sum / self.count as f64) as f32,
+            max: self.max,
+            // the results do not contain enough to combine standard deviations
+            stddev: f64::NAN,
+            count: self.count,
+        }
+    }
+}
+
+/// Folds the result files of independently processed shards into a single result,
+/// see `--merge-results`.
+///
+/// The results must be written with `--show-count`, so the means can be weighted by
+/// their number of rows. As the printed means are rounded, the merged mean can be
+/// off by up to the rounding of a single result. The cities are sorted by name.
+pub(crate) fn merge_result_files<OWrite: Write>(
+    paths: &[PathBuf],
+    show_count: bool,
+    output: OWrite,
+) -> Result<(), Error> {
+    let mut results = Vec::with_capacity(paths.len());
+    for path in paths {
+        results.push(read_result_file(path, parse_counted_result)?);
+    }
+    let merged = merge_results(results);
+
+    let mut sink = TextSink::new(output, false).show_count(show_count);
+    for (city, merged) in &merged {
+        sink.emit(city, merged.stats())?;
+    }
+    sink.finish()
+}
+
+fn merge_results(results: Vec<Vec<(String, CountedStats)>>) -> Vec<(String, MergedCity)> {
+    let mut cities: HashMap<String, MergedCity> = HashMap::new();
+    for (city, stats) in results.into_iter().flatten() {
+        match cities.get_mut(&city) {
+            Some(merged) => merged.merge(stats),
+            None => {
+                cities.insert(city, MergedCity::new(stats));
+            }
+        }
+    }
+    let mut cities: Vec<_> = cities.into_iter().collect();
+    cities.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    cities
+}
+
+/// Parses a result written with `--show-count`, `{city=min/mean/max/count, ...}`.
+fn parse_counted_result(text: &str) -> Result<Vec<(String, CountedStats)>, String> {
+    result_entries(text)?
+        .into_iter()
+        .map(|(city, values)| {
+            let mut parts = values.split('/');
+            let mut value = || parts.next().and_then(|value| value.parse::<f32>().ok());
+            let (min, mean, max) = (value(), value(), value());
+            let count = parts.next().and_then(|count| count.parse().ok());
+            match (min, mean, max, count, parts.next()) {
+                (Some(min), Some(mean), Some(max), Some(count), None) => {
+                    let stats = CountedStats {
+                        min,
+                        mean,
+                        max,
+                        count,
+                    };
+                    Ok((city.to_string(), stats))
+                }
+                _ => Err(format!(
+                    "expected `min/mean/max/count` in {:?}, write the results with --show-count",
+                    format!("{city}={values}")
+                )),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs};
+
+    use super::{merge_result_files, merge_results, parse_counted_result};
+    use crate::Error;
+
+    #[test]
+    fn check_merge_results() {
+        let first = parse_counted_result("{a=-1.0/2.0/5.0/3, b=1.0/1.0/1.0/1}\n").unwrap();
+        let second = parse_counted_result("{a=0.0/4.0/9.5/1, c=2.0/3.0/4.0/2}").unwrap();
+        let merged = merge_results(vec![first, second, Vec::new()]);
+
+        let stats: Vec<_> = merged
+            .iter()
+            .map(|(city, merged)| {
+                let stats = merged.stats();
+                (city.as_str(), stats.min, stats.mean, stats.max, stats.count)
+            })
+            .collect();
+        assert_eq!(
+            stats,
+            [
+                ("a", -1.0, 2.5, 9.5, 4),
+                ("b", 1.0, 1.0, 1.0, 1),
+                ("c", 2.0, 3.0, 4.0, 2)
+            ]
+        );
+
+        assert_eq!(parse_counted_result("{}").unwrap().len(), 0);
+        let error = parse_counted_result("{a=1.0/2.0/3.0}").unwrap_err();
+        assert!(
+            error.ends_with("write the results with --show-count"),
+            "{error}"
+        );
+        assert!(parse_counted_result("{a=1.0/2.0/3.0/1.5}").is_err());
+        assert!(parse_counted_result("{a=1.0/2.0/3.0/1/1}").is_err());
+    }
+
+    #[test]
+    fn check_merge_result_files() {
+        let first = temp_dir().join("one-billion-rows-merge-first.txt");
+        let second = temp_dir().join("one-billion-rows-merge-second.txt");
+        fs::write(&first, "{b=1.0/2.0/3.0/2, a=0.5/0.5/0.5/1}").unwrap();
+        fs::write(&second, "{b=-3.0/-1.0/1.0/2}").unwrap();
+        let paths = [first.clone(), second.clone()];
+
+        let mut output = Vec::new();
+        merge_result_files(&paths, false, &mut output).unwrap();
+        assert_eq!(output, b"{a=0.5/0.5/0.5, b=-3.0/0.5/3.0}");
+        let mut output = Vec::new();
+        merge_result_files(&paths, true, &mut output).unwrap();
+        assert_eq!(output, b"{a=0.5/0.5/0.5/1, b=-3.0/0.5/3.0/4}");
+
+        fs::write(&second, "{b=-3.0/-1.0/1.0}").unwrap();
+        assert!(matches!(
+            merge_result_files(&paths, false, Vec::new()),
+            Err(Error::MalformedData(_))
+        ));
+        fs::remove_file(first).unwrap();
+        fs::remove_file(second).unwrap();
+    }
+}
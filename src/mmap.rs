@@ -0,0 +1,270 @@
+//! The `mmap-zerocopy` engine.
+//!
+//! All input files are memory mapped, so city names can be borrowed straight from
+//! the mapping for the whole run. Workers use `&[u8]` keys and never allocate for
+//! a city name. Owned keys are only created once, after all partial results are merged.
+
+use std::{fs::File, io, path::PathBuf, str::from_utf8, thread};
+
+use crossbeam::channel::unbounded;
+use hashbrown::HashMap;
+
+use crate::{
+    line_length_error, newline_aligned_boundary, parse_row_bytes, CityEntry, Error, ParseOptions,
+    PartialResult, BOM,
+};
+
+/// The approximate size of the segments the input is split into for the workers
+pub(crate) const SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+/// A read only memory mapping of a whole file
+#[cfg(unix)]
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// Safety: the mapping is read only and never changes after creation
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+#[cfg(unix)]
+impl Mmap {
+    fn open(file: &File) -> io::Result<Self> {
+        use std::os::fd::AsRawFd;
+
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mapping an empty file is an error
+            return Ok(Mmap {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+
+        // Safety: we map the file read only and private, so the only way to invalidate
+        // the mapping is for another process to truncate the file while we run.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // Safety: ptr points to a mapping of len bytes that lives as long as self
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // Safety: ptr and len are the result of a successful mmap call
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+/// Fallback for platforms without mmap, that reads the whole file into memory instead
+#[cfg(not(unix))]
+struct Mmap(Vec<u8>);
+
+#[cfg(not(unix))]
+impl Mmap {
+    fn open(mut file: &File) -> io::Result<Self> {
+        use std::io::Read;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(Mmap(data))
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Splits `data` into segments of roughly `size` bytes, that end in a newline.
+fn split_segments(data: &[u8], size: usize) -> Vec<&[u8]> {
+    let mut segments = Vec::with_capacity(data.len() / size + 1);
+    let mut rest = data;
+    while !rest.is_empty() {
+        let end = match rest
+            .get(size..)
+            .and_then(|tail| newline_aligned_boundary(tail, false))
+        {
+            Some(boundary) => size + boundary,
+            None => rest.len(),
+        };
+        let (segment, remaining) = rest.split_at(end);
+        segments.push(segment);
+        rest = remaining;
+    }
+    segments
+}
+
+type BorrowedResult<'a> = Result<(HashMap<&'a [u8], CityEntry>, usize), Error>;
+
+fn process_segments<'a>(
+    segments: crossbeam::channel::Receiver<(usize, &'a [u8])>,
+    options: ParseOptions,
+) -> BorrowedResult<'a> {
+    let mut result = HashMap::<&[u8], CityEntry>::new();
+    let mut skipped = 0;
+    for (segment_index, segment) in segments {
+        let segment = segment.strip_suffix(b"\n").unwrap_or(segment);
+        for (line_index, line) in segment.split(|&c| c == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if !options.line_length_in_bounds(line.len()) {
+                return Err(line_length_error(line, &options));
+            }
+            match options.in_window(line) {
+                Some(true) => {}
+                Some(false) => continue,
+                None if options.lenient => {
+                    skipped += 1;
+                    continue;
+                }
+                None => {
+                    let line = String::from_utf8_lossy(line);
+                    return Err(Error::MalformedData(format!("malformed line: {line:?}")));
+                }
+            }
+            let (city, value) = match parse_row_bytes(line, &options) {
+                Some((city, value)) => (city, value.as_f32()),
+                None if options.lenient => {
+                    skipped += 1;
+                    continue;
+                }
+                None => {
+                    let line = String::from_utf8_lossy(line);
+                    return Err(Error::MalformedData(format!("malformed line: {line:?}")));
+                }
+            };
+            if !options.in_bounds(value) {
+                let line = String::from_utf8_lossy(line);
+                return Err(Error::MalformedData(format!(
+                    "value out of bounds: {line:?}"
+                )));
+            }
+
+            result
+                .entry(city)
+                .or_insert_with(|| CityEntry {
+                    first_seen: (segment_index, line_index),
+                    ..CityEntry::default()
+                })
+                .add(value);
+        }
+    }
+    Ok((result, skipped))
+}
+
+/// Calculates the result by memory mapping all `input_files`.
+pub(crate) fn calculate_zero_copy(
+    input_files: &[PathBuf],
+    threads: usize,
+    options: ParseOptions,
+) -> PartialResult {
+    let mut mappings = Vec::with_capacity(input_files.len());
+    for path in input_files {
+        let file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+        mappings.push(Mmap::open(&file).map_err(|e| Error::from_io(path, e))?);
+    }
+
+    let data: Vec<_> = mappings.iter().map(Mmap::as_bytes).collect();
+    calculate_slices(&data, threads, options)
+}
+
+/// Calculates the result of rows that are already in memory, as if every slice of
+/// `data` was a file.
+pub(crate) fn calculate_slices(
+    data: &[&[u8]],
+    threads: usize,
+    options: ParseOptions,
+) -> PartialResult {
+    let (segment_sender, segment_receiver) = unbounded();
+    let segments = data
+        .iter()
+        .map(|data| data.strip_prefix(BOM).unwrap_or(data))
+        .flat_map(|data| split_segments(data, SEGMENT_SIZE));
+    for segment in segments.enumerate() {
+        segment_sender
+            .send(segment)
+            .expect("Failed to send segment");
+    }
+    drop(segment_sender);
+
+    let partials: Vec<BorrowedResult> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let segment_receiver = segment_receiver.clone();
+                scope.spawn(move || process_segments(segment_receiver, options))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("process segments failed"))
+            .collect()
+    });
+
+    let mut merged = HashMap::<&[u8], CityEntry>::new();
+    let mut skipped = 0;
+    for partial in partials {
+        let (partial, partial_skipped) = partial?;
+        skipped += partial_skipped;
+        for (city, entry) in partial {
+            match merged.get_mut(city) {
+                Some(merged_entry) => merged_entry.merge(&entry),
+                None => {
+                    merged.insert(city, entry);
+                }
+            }
+        }
+    }
+
+    // only now are the keys copied out of the mapping
+    let result = merged
+        .into_iter()
+        .map(|(city, entry)| {
+            let city = from_utf8(city)
+                .map_err(|e| Error::MalformedData(format!("city name is not valid utf8: {e}")))?;
+            entry.check_count(city)?;
+            Ok((city.to_owned(), entry))
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok((result, skipped))
+}
+
+#[cfg(test)]
+mod test {
+    use super::split_segments;
+
+    #[test]
+    fn check_split_segments() {
+        let data = b"a;1.0\nbb;2.0\nccc;3.0\nd;4.0";
+        let segments = split_segments(data, 4);
+
+        assert_eq!(segments.concat(), data);
+        assert_eq!(segments[0], b"a;1.0\n");
+        for segment in &segments[..segments.len() - 1] {
+            assert_eq!(segment.last(), Some(&b'\n'));
+        }
+    }
+}
@@ -0,0 +1,669 @@
+use std::str::{from_utf8, FromStr};
+
+use clap::ValueEnum;
+
+/// The forms of values that are accepted.
+///
+/// Values are parsed by a fast integer based parser whenever possible, which is
+/// considerably faster than the float parser of the std-lib. The std-lib parser
+/// is only used by [NumericFormat::Lenient] for values the fast parser rejects.
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumericFormat {
+    /// Only accept the form used by the challenge: `-?\d+\.\d`.
+    ///
+    /// Every value is parsed by the fast parser.
+    Strict,
+    /// Accept everything that can be parsed as a float, e.g. `+12.3` or `1.0e1`.
+    ///
+    /// Values of the form `-?\d+(\.\d+)?` with at most 7 digits use the fast parser,
+    /// everything else falls back to the float parser of the std-lib.
+    #[default]
+    Lenient,
+}
+
+/// How the value of a row is interpreted
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValueType {
+    /// A decimal number, see [NumericFormat]
+    #[default]
+    Float,
+    /// An integer of the form `-?\d+`, which is aggregated without rounding
+    Int,
+    /// A value of the form `-?\d+\.\d` parsed as an integer number of tenths,
+    /// used by `--spec-strict`.
+    ///
+    /// [ParseOptions::bounds] are in tenths as well.
+    #[value(skip)]
+    Tenths,
+}
+
+/// The order of the fields in a row
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColumnOrder {
+    /// `city;value`
+    #[default]
+    CityValue,
+    /// `value;city`
+    ValueCity,
+}
+
+/// A parsed value of a row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Float(f32),
+    Int(i64),
+}
+
+impl Value {
+    pub fn as_f32(self) -> f32 {
+        match self {
+            Value::Float(value) => value,
+            Value::Int(value) => value as f32,
+        }
+    }
+}
+
+/// The character between the integer and the fractional digits of a value
+#[derive(Debug, ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecimalSeparator {
+    /// `12.3`
+    #[default]
+    #[value(name = ".")]
+    Point,
+    /// `12,3`, used by many european exports.
+    ///
+    /// The field separator must be a different character then, e.g. `;` or tab.
+    #[value(name = ",")]
+    Comma,
+}
+
+impl DecimalSeparator {
+    pub fn as_byte(self) -> u8 {
+        match self {
+            DecimalSeparator::Point => b'.',
+            DecimalSeparator::Comma => b',',
+        }
+    }
+}
+
+/// The character between the city name and the value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Separator {
+    /// Use the first of `;`, `,` and tab that appears in the first line of the input
+    Auto,
+    /// A single ascii character
+    Char(u8),
+}
+
+impl Separator {
+    /// The separators tried by [Separator::Auto], in order
+    const CANDIDATES: [u8; 3] = [b';', b',', b'\t'];
+
+    /// Picks the separator for [Separator::Auto] from the first line of the input.
+    pub fn detect(first_line: &[u8]) -> Option<u8> {
+        Self::detect_except(first_line, None)
+    }
+
+    /// Same as [Separator::detect] but never picks `except`, e.g. the decimal separator.
+    pub fn detect_except(first_line: &[u8], except: Option<u8>) -> Option<u8> {
+        Self::CANDIDATES
+            .into_iter()
+            .filter(|&separator| Some(separator) != except)
+            .find(|separator| first_line.contains(separator))
+    }
+}
+
+impl FromStr for Separator {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Separator::Auto),
+            "tab" => Ok(Separator::Char(b'\t')),
+            _ => match value.as_bytes() {
+                &[c] if c.is_ascii() && c != b'\n' && c != b'\r' => Ok(Separator::Char(c)),
+                _ => Err("expected a single ascii character, `tab` or `auto`".to_string()),
+            },
+        }
+    }
+}
+
+/// Options controlling how lines are parsed
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// Skip malformed lines instead of failing
+    pub lenient: bool,
+    pub numeric_format: NumericFormat,
+    pub value_type: ValueType,
+    pub column_order: ColumnOrder,
+    /// The indices of the city and the value field in rows with more than two
+    /// fields, which replace [ParseOptions::column_order].
+    ///
+    /// City names cannot contain the separator then.
+    pub columns: Option<(usize, usize)>,
+    /// The character between the city name and the value
+    pub separator: u8,
+    /// The character between the integer and the fractional digits of a value
+    pub decimal_separator: u8,
+    /// The inclusive range every value must be in.
+    ///
+    /// A value outside of this range is always an error, even if `lenient` is set.
+    pub bounds: Option<(f32, f32)>,
+    /// The inclusive range of the length in bytes of every line, without its line
+    /// ending.
+    ///
+    /// A line outside of this range is always an error, even if `lenient` is set.
+    pub line_length: (usize, usize),
+    /// The index of the timestamp field and the first timestamp of `--window`.
+    ///
+    /// Rows with an earlier timestamp are skipped without counting them as malformed.
+    pub window: Option<(usize, i64)>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            lenient: false,
+            numeric_format: NumericFormat::default(),
+            value_type: ValueType::default(),
+            column_order: ColumnOrder::default(),
+            columns: None,
+            separator: b';',
+            decimal_separator: b'.',
+            bounds: None,
+            line_length: (0, usize::MAX),
+            window: None,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Whether `value` is inside of [ParseOptions::bounds]
+    pub fn in_bounds(&self, value: f32) -> bool {
+        self.bounds
+            .is_none_or(|(min, max)| (min..=max).contains(&value))
+    }
+
+    /// Whether a line of `len` bytes is inside of [ParseOptions::line_length]
+    pub fn line_length_in_bounds(&self, len: usize) -> bool {
+        let (min, max) = self.line_length;
+        (min..=max).contains(&len)
+    }
+
+    /// Whether the timestamp of `line` is inside of [ParseOptions::window], `None`
+    /// if the line has no valid timestamp. Every line is inside without a window.
+    pub fn in_window(&self, line: &[u8]) -> Option<bool> {
+        match self.window {
+            Some((column, start)) => Some(parse_timestamp(line, self.separator, column)? >= start),
+            None => Some(true),
+        }
+    }
+}
+
+/// The integer timestamp in the field `column` of a line, `None` if the line has
+/// too few fields or the field is not an integer.
+pub(crate) fn parse_timestamp(line: &[u8], separator: u8, column: usize) -> Option<i64> {
+    parse_int(line.split(|&c| c == separator).nth(column)?)
+}
+
+/// Parses a value in the given format.
+pub fn parse_value(value: &[u8], format: NumericFormat) -> Option<f32> {
+    match format {
+        NumericFormat::Strict => parse_spec_value(value),
+        NumericFormat::Lenient => {
+            parse_decimal(value).or_else(|| from_utf8(value).ok()?.parse().ok())
+        }
+    }
+}
+
+/// Parses an integer of the form `-?\d+`, with at most 18 digits.
+pub fn parse_int(value: &[u8]) -> Option<i64> {
+    let (negative, digits) = match value.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, value),
+    };
+    if digits.is_empty() || digits.len() > 18 {
+        return None;
+    }
+
+    let mut int_value: i64 = 0;
+    for &c in digits {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        int_value = int_value * 10 + (c - b'0') as i64;
+    }
+    Some(if negative { -int_value } else { int_value })
+}
+
+/// Parses a value of the type given by `options`.
+fn parse_typed_value(value: &[u8], options: &ParseOptions) -> Option<Value> {
+    if options.decimal_separator != b'.' {
+        return parse_with_decimal_separator(value, options);
+    }
+    parse_point_value(value, options)
+}
+
+/// Same as [parse_typed_value] for values with [ParseOptions::decimal_separator]
+/// instead of a point.
+///
+/// Values are copied with the separator replaced by a point, so they are limited to
+/// 64 bytes.
+// kept out of line, so the common values with a point are not slowed down
+#[cold]
+fn parse_with_decimal_separator(value: &[u8], options: &ParseOptions) -> Option<Value> {
+    // the point is not the decimal separator, so it cannot be part of a value
+    if value.contains(&b'.') {
+        return None;
+    }
+    let mut buffer = [0; 64];
+    let buffer = buffer.get_mut(..value.len())?;
+    for (replaced, &c) in buffer.iter_mut().zip(value) {
+        *replaced = if c == options.decimal_separator {
+            b'.'
+        } else {
+            c
+        };
+    }
+    parse_point_value(buffer, options)
+}
+
+/// Parses a value with a point as the decimal separator, see [parse_typed_value].
+fn parse_point_value(value: &[u8], options: &ParseOptions) -> Option<Value> {
+    match options.value_type {
+        ValueType::Float => parse_value(value, options.numeric_format).map(Value::Float),
+        ValueType::Int => parse_int(value).map(Value::Int),
+        ValueType::Tenths => parse_spec_tenths(value).map(|tenths| Value::Int(tenths.into())),
+    }
+}
+
+/// Parses a value of the form `-?\d+\.\d`, with at most 7 digits.
+///
+/// A branchless SWAR variant, which loads the value into a `u64` and combines the
+/// digits with one multiplication, was measured to be 10% slower than this. The
+/// values are too short to make up for loading them from a slice of unknown length.
+fn parse_spec_value(value: &[u8]) -> Option<f32> {
+    parse_spec_tenths(value).map(|tenths| tenths as f32 / 10.0)
+}
+
+/// Same as [parse_spec_value] but returns the number of tenths.
+#[inline]
+fn parse_spec_tenths(value: &[u8]) -> Option<i32> {
+    let (negative, digits) = match value.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, value),
+    };
+
+    let [int_digits @ .., b'.', fraction] = digits else {
+        return None;
+    };
+    if int_digits.is_empty() || int_digits.len() > 6 || !fraction.is_ascii_digit() {
+        return None;
+    }
+
+    let mut tenths: u32 = 0;
+    for &c in int_digits {
+        if !c.is_ascii_digit() {
+            return None;
+        }
+        tenths = tenths * 10 + (c - b'0') as u32;
+    }
+    tenths = tenths * 10 + (fraction - b'0') as u32;
+
+    // at most 7 digits always fit into an i32
+    let tenths = tenths as i32;
+    Some(if negative { -tenths } else { tenths })
+}
+
+/// Parses a value of the form `-?\d+(\.\d+)?` or `-?\.\d+`, with at most 7 digits.
+fn parse_decimal(value: &[u8]) -> Option<f32> {
+    let (negative, digits) = match value.split_first() {
+        Some((b'-', rest)) => (true, rest),
+        _ => (false, value),
+    };
+
+    let mut int_value: u32 = 0;
+    let mut scale: u32 = 1;
+    let mut seen_point = false;
+    let mut digit_count = 0;
+    for &c in digits {
+        match c {
+            b'0'..=b'9' if digit_count < 7 => {
+                int_value = int_value * 10 + (c - b'0') as u32;
+                digit_count += 1;
+                if seen_point {
+                    scale *= 10;
+                }
+            }
+            b'.' if !seen_point => seen_point = true,
+            _ => return None,
+        }
+    }
+    if digit_count == 0 {
+        return None;
+    }
+
+    let value = int_value as f32 / scale as f32;
+    Some(if negative { -value } else { value })
+}
+
+/// Splits a line into the city name and its value.
+///
+/// Returns `None` if the line is missing the separator, the city name is empty or
+/// the value is not valid.
+pub fn parse_line(line: &str, separator: u8, format: NumericFormat) -> Option<(&str, f32)> {
+    let (city, value) = line.split_once(separator as char)?;
+    if city.is_empty() {
+        return None;
+    }
+    let value = parse_value(value.as_bytes(), format)?;
+    Some((city, value))
+}
+
+/// Splits a line of raw bytes into the city name and its value.
+///
+/// Same as [parse_line], the line is split at the first separator.
+pub fn parse_line_bytes(line: &[u8], separator: u8, format: NumericFormat) -> Option<(&[u8], f32)> {
+    let separator = line.iter().position(|&c| c == separator)?;
+    if separator == 0 {
+        return None;
+    }
+    let value = parse_value(&line[separator + 1..], format)?;
+    Some((&line[..separator], value))
+}
+
+/// Same as [parse_line] but parses the value as given by `options`.
+pub fn parse_row<'a>(line: &'a str, options: &ParseOptions) -> Option<(&'a str, Value)> {
+    let (city, value) = split_row(line, options)?;
+    let value = parse_typed_value(value.as_bytes(), options)?;
+    Some((city, value))
+}
+
+/// The city of a row, without parsing the value.
+pub(crate) fn parse_city<'a>(line: &'a str, options: &ParseOptions) -> Option<&'a str> {
+    split_row(line, options).map(|(city, _)| city)
+}
+
+/// Splits a row into the city and the unparsed value.
+///
+/// Rows with an empty city name like `;12.3` are malformed.
+fn split_row<'a>(line: &'a str, options: &ParseOptions) -> Option<(&'a str, &'a str)> {
+    if let Some(columns) = options.columns {
+        return select_fields(line.split(options.separator as char), columns)
+            .filter(|(city, _)| !city.is_empty());
+    }
+    let (first, second) = line.split_once(options.separator as char)?;
+    let row = match options.column_order {
+        ColumnOrder::CityValue => (first, second),
+        ColumnOrder::ValueCity => (second, first),
+    };
+    Some(row).filter(|(city, _)| !city.is_empty())
+}
+
+/// The city and the value field of a row, given by their index in [ParseOptions::columns].
+///
+/// Returns `None` if the row has too few fields.
+// kept out of line, so the common two field rows are not slowed down
+#[cold]
+fn select_fields<'a, T: ?Sized>(
+    fields: impl Iterator<Item = &'a T>,
+    (city_column, value_column): (usize, usize),
+) -> Option<(&'a T, &'a T)> {
+    let (mut city, mut value) = (None, None);
+    for (column, field) in fields.enumerate().take(city_column.max(value_column) + 1) {
+        if column == city_column {
+            city = Some(field);
+        } else if column == value_column {
+            value = Some(field);
+        }
+    }
+    Some((city?, value?))
+}
+
+/// Same as [parse_line_bytes] but parses the value as given by `options`.
+pub fn parse_row_bytes<'a>(line: &'a [u8], options: &ParseOptions) -> Option<(&'a [u8], Value)> {
+    let (city, value) = split_row_bytes(line, options)?;
+    let value = parse_typed_value(value, options)?;
+    Some((city, value))
+}
+
+/// Same as [parse_city] but works on lines that were not validated as utf8.
+pub(crate) fn parse_city_bytes<'a>(line: &'a [u8], options: &ParseOptions) -> Option<&'a [u8]> {
+    split_row_bytes(line, options).map(|(city, _)| city)
+}
+
+/// Same as [split_row] but works on lines that were not validated as utf8.
+fn split_row_bytes<'a>(line: &'a [u8], options: &ParseOptions) -> Option<(&'a [u8], &'a [u8])> {
+    if let Some(columns) = options.columns {
+        return select_fields(line.split(|&c| c == options.separator), columns)
+            .filter(|(city, _)| !city.is_empty());
+    }
+    // split at the first separator like `split_row`, so a row like `a;1.0;2.0`
+    // is malformed in both
+    let separator = line.iter().position(|&c| c == options.separator)?;
+    let (first, second) = (&line[..separator], &line[separator + 1..]);
+    let row = match options.column_order {
+        ColumnOrder::CityValue => (first, second),
+        ColumnOrder::ValueCity => (second, first),
+    };
+    Some(row).filter(|(city, _)| !city.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse_int, parse_line, parse_line_bytes, parse_row, parse_row_bytes, parse_value,
+        ColumnOrder, NumericFormat, ParseOptions, Separator, Value,
+    };
+
+    #[test]
+    fn check_column_order() {
+        let options = ParseOptions {
+            column_order: ColumnOrder::ValueCity,
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            parse_row("-1.5;New;York", &options),
+            Some(("New;York", Value::Float(-1.5)))
+        );
+        assert_eq!(
+            parse_row_bytes(b"-1.5;New;York", &options),
+            Some((&b"New;York"[..], Value::Float(-1.5)))
+        );
+        assert_eq!(parse_row("Hamburg;12.0", &options), None);
+        assert_eq!(
+            parse_row_bytes(b"Hamburg;12.0", &ParseOptions::default()),
+            Some((&b"Hamburg"[..], Value::Float(12.0)))
+        );
+    }
+
+    #[test]
+    fn check_extra_field() {
+        let default = ParseOptions::default();
+        assert_eq!(parse_row("a;1.0;2.0", &default), None);
+        assert_eq!(parse_row_bytes(b"a;1.0;2.0", &default), None);
+        assert_eq!(parse_line("a;1.0;2.0", b';', NumericFormat::Lenient), None);
+        assert_eq!(
+            parse_line_bytes(b"a;1.0;2.0", b';', NumericFormat::Lenient),
+            None
+        );
+    }
+
+    #[test]
+    fn check_empty_city() {
+        let default = ParseOptions::default();
+        assert_eq!(parse_row(";12.3", &default), None);
+        assert_eq!(parse_row_bytes(b";12.3", &default), None);
+        assert_eq!(parse_line(";12.3", b';', NumericFormat::Lenient), None);
+        assert_eq!(
+            parse_line_bytes(b";12.3", b';', NumericFormat::Lenient),
+            None
+        );
+        // the row is split at the first separator, so the city stays empty
+        assert_eq!(parse_row_bytes(b";;12.3", &default), None);
+
+        let value_city = ParseOptions {
+            column_order: ColumnOrder::ValueCity,
+            ..ParseOptions::default()
+        };
+        assert_eq!(parse_row("12.3;", &value_city), None);
+        assert_eq!(parse_row_bytes(b"12.3;", &value_city), None);
+        let columns = ParseOptions {
+            columns: Some((1, 2)),
+            ..ParseOptions::default()
+        };
+        assert_eq!(parse_row("x;;12.3", &columns), None);
+        assert_eq!(parse_row_bytes(b"x;;12.3", &columns), None);
+    }
+
+    #[test]
+    fn check_decimal_separator() {
+        let options = ParseOptions {
+            decimal_separator: b',',
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            parse_row("Hamburg;-12,3", &options),
+            Some(("Hamburg", Value::Float(-12.3)))
+        );
+        assert_eq!(
+            parse_row_bytes(b"St. Louis;0,5", &options),
+            Some((&b"St. Louis"[..], Value::Float(0.5)))
+        );
+        assert_eq!(
+            parse_row("Hamburg;7", &options),
+            Some(("Hamburg", Value::Float(7.0)))
+        );
+        assert_eq!(parse_row("Hamburg;12.3", &options), None);
+        assert_eq!(parse_row("Hamburg;1,2,3", &options), None);
+
+        assert_eq!(
+            Separator::detect_except(b"a,b\t1,5", Some(b',')),
+            Some(b'\t')
+        );
+        assert_eq!(Separator::detect_except(b"a,1", Some(b',')), None);
+    }
+
+    #[test]
+    fn check_columns() {
+        let options = ParseOptions {
+            columns: Some((2, 1)),
+            ..ParseOptions::default()
+        };
+        let row = "17;-1.5;Hamburg;2024-01-01";
+        assert_eq!(
+            parse_row(row, &options),
+            Some(("Hamburg", Value::Float(-1.5)))
+        );
+        assert_eq!(
+            parse_row_bytes(row.as_bytes(), &options),
+            Some((&b"Hamburg"[..], Value::Float(-1.5)))
+        );
+        assert_eq!(
+            parse_row("17;-1.5;Hamburg", &options),
+            Some(("Hamburg", Value::Float(-1.5)))
+        );
+        assert_eq!(parse_row("17;-1.5", &options), None);
+        assert_eq!(parse_row_bytes(b"17;-1.5", &options), None);
+    }
+
+    #[test]
+    fn check_window() {
+        assert_eq!(ParseOptions::default().in_window(b"x;1.0"), Some(true));
+        let options = ParseOptions {
+            columns: Some((2, 1)),
+            window: Some((0, 100)),
+            ..ParseOptions::default()
+        };
+        assert_eq!(options.in_window(b"100;-1.5;Hamburg"), Some(true));
+        assert_eq!(options.in_window(b"99;-1.5;Hamburg"), Some(false));
+        assert_eq!(options.in_window(b"-100;-1.5;Hamburg"), Some(false));
+        assert_eq!(options.in_window(b"1.5e2;-1.5;Hamburg"), None);
+        assert_eq!(options.in_window(b";-1.5;Hamburg"), None);
+    }
+
+    #[test]
+    fn check_parse_int() {
+        for value in ["0", "-0", "7", "-42", "123456789012345678", "-000123"] {
+            assert_eq!(parse_int(value.as_bytes()), value.parse().ok(), "{value}");
+        }
+        for value in ["", "-", "1.0", "+1", "--1", "1e3", "1234567890123456789"] {
+            assert_eq!(parse_int(value.as_bytes()), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn check_separator() {
+        assert_eq!("auto".parse(), Ok(Separator::Auto));
+        assert_eq!("tab".parse(), Ok(Separator::Char(b'\t')));
+        assert_eq!(",".parse(), Ok(Separator::Char(b',')));
+        assert!("ab".parse::<Separator>().is_err());
+        assert!("ä".parse::<Separator>().is_err());
+
+        assert_eq!(Separator::detect(b"a;1.0"), Some(b';'));
+        assert_eq!(Separator::detect(b"St. Louis, MO;1.0"), Some(b';'));
+        assert_eq!(Separator::detect(b"a,1.0"), Some(b','));
+        assert_eq!(Separator::detect(b"a\t1.0"), Some(b'\t'));
+        assert_eq!(Separator::detect(b"a 1.0"), None);
+    }
+
+    #[test]
+    fn check_parse_value_lenient() {
+        for value in [
+            "0.0",
+            "-0.0",
+            "12.3",
+            "-99.9",
+            "5",
+            "-7",
+            "1.25",
+            ".5",
+            "1e3",
+            "1.0e1",
+            "+12.3",
+            "0012.30",
+            "-00.5",
+            "123456789",
+        ] {
+            assert_eq!(
+                parse_value(value.as_bytes(), NumericFormat::Lenient),
+                value.parse().ok(),
+                "{value}"
+            );
+        }
+        for value in ["", "-", ".", "1.2.3", "abc", "--1", "+-1"] {
+            assert_eq!(
+                parse_value(value.as_bytes(), NumericFormat::Lenient),
+                None,
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn check_parse_value_strict() {
+        for (value, expected) in [
+            ("0.0", 0.0),
+            ("-0.0", -0.0),
+            ("12.3", 12.3),
+            ("-99.9", -99.9),
+            ("007.5", 7.5),
+        ] {
+            assert_eq!(
+                parse_value(value.as_bytes(), NumericFormat::Strict),
+                Some(expected),
+                "{value}"
+            );
+        }
+        for value in [
+            "", "-", ".", "5", "-7", "1.25", ".5", "5.", "1e3", "1.0e1", "+12.3", "12.30", "1.2.3",
+            "--1.0",
+        ] {
+            assert_eq!(
+                parse_value(value.as_bytes(), NumericFormat::Strict),
+                None,
+                "{value}"
+            );
+        }
+    }
+}
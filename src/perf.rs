@@ -0,0 +1,179 @@
+use std::{
+    fs::{read_to_string, write},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use crate::{calculate, collect_input_files, http, stream, Args, Error};
+
+/// Measures the throughput of processing the input of `args` and compares it to
+/// the baseline in `baseline`, see `--perf-baseline`.
+///
+/// The input is processed `--perf-warmup` times without measuring and then
+/// `--perf-runs` times. The median of the runs is compared, so single slow runs
+/// do not fail the check. With `--update-baseline` the median is written to
+/// `baseline` instead.
+pub(crate) fn check_baseline<OWrite: Write>(
+    args: &Args,
+    baseline: &Path,
+    mut output: OWrite,
+) -> Result<(), Error> {
+    if !(0.0..100.0).contains(&args.perf_tolerance) {
+        return Err(Error::InvalidArguments(
+            "--perf-tolerance must be at least 0 and below 100".to_string(),
+        ));
+    }
+    let input_files = collect_input_files(&args.input, args.recursive)?;
+    let mut bytes = 0;
+    for path in &input_files {
+        let metadata = path.metadata().map_err(|e| Error::from_io(path, e))?;
+        bytes += metadata.len();
+    }
+    let runs = args.perf_runs.get();
+
+    let run = || -> Result<f64, Error> {
+        let mut args = args.clone();
+        args.perf_baseline = None;
+        args.input = input_files.clone();
+        args.recursive = false;
+        let start = Instant::now();
+        calculate(args, io::sink())?;
+        Ok(start.elapsed().as_secs_f64())
+    };
+    for _ in 0..args.perf_warmup {
+        run()?;
+    }
+    let mut throughputs = Vec::with_capacity(runs);
+    for _ in 0..runs {
+        throughputs.push(megabytes(bytes) / run()?);
+    }
+    let median = median(&mut throughputs);
+
+    if args.update_baseline {
+        write(baseline, format!("{median:.1}\n")).map_err(|e| Error::from_io(baseline, e))?;
+        writeln!(
+            output,
+            "# wrote a baseline of {median:.1} MB/s over {runs} runs to {}",
+            baseline.display()
+        )?;
+        return Ok(());
+    }
+
+    let expected = read_baseline(baseline)?;
+    let change = (median / expected - 1.0) * 100.0;
+    writeln!(
+        output,
+        "# median throughput {median:.1} MB/s over {runs} runs, baseline {expected:.1} MB/s ({change:+.1}%)"
+    )?;
+    if change < -args.perf_tolerance {
+        return Err(Error::VerificationMismatch(format!(
+            "the throughput regressed by {:.1}%, more than the tolerance of {}%",
+            -change, args.perf_tolerance
+        )));
+    }
+    Ok(())
+}
+
+fn megabytes(bytes: u64) -> f64 {
+    bytes as f64 / 1_000_000.0
+}
+
+/// The median of `values`, the mean of the two middle values for an even number of values
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_unstable_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[middle - 1] + values[middle]) / 2.0,
+        _ => values[middle],
+    }
+}
+
+/// Reads the throughput in MB/s that is stored in `path`.
+fn read_baseline(path: &Path) -> Result<f64, Error> {
+    let text = read_to_string(path).map_err(|e| Error::from_io(path, e))?;
+    text.trim()
+        .parse()
+        .ok()
+        .filter(|baseline: &f64| *baseline > 0.0)
+        .ok_or_else(|| {
+            Error::MalformedData(format!(
+                "{}: expected a throughput in MB/s, found {:?}",
+                path.display(),
+                text.trim()
+            ))
+        })
+}
+
+/// Fails for inputs that cannot be read more than once.
+pub(crate) fn check_inputs(inputs: &[PathBuf]) -> Result<(), Error> {
+    match inputs
+        .iter()
+        .find(|path| path.as_os_str() == stream::STDIN || path.to_str().is_some_and(http::is_url))
+    {
+        Some(input) => Err(Error::InvalidArguments(format!(
+            "--perf-baseline reads the input several times, which {} does not support",
+            input.display()
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use clap::Parser;
+
+    use super::median;
+    use crate::{calculate, Args, Error};
+
+    #[test]
+    fn check_median() {
+        assert_eq!(median(&mut [3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&mut [4.0, 1.0, 3.0, 2.0]), 2.5);
+    }
+
+    #[test]
+    fn check_perf_baseline() {
+        let baseline = std::env::temp_dir().join("one-billion-rows-perf-baseline.txt");
+        let check_with = |baseline: &Path, extra: &[&str]| {
+            let mut args = vec![
+                "one-billion-rows",
+                "--perf-baseline",
+                baseline.to_str().unwrap(),
+                "--perf-runs=3",
+                "--perf-warmup=0",
+            ];
+            args.extend(extra);
+            args.push("data/test.txt");
+            calculate(Args::parse_from(args), Vec::new())
+        };
+        let check = |extra: &[&str]| check_with(&baseline, extra);
+
+        check(&["--update-baseline"]).unwrap();
+        let written: f64 = fs::read_to_string(&baseline)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        assert!(written > 0.0);
+
+        // no machine is a thousand times faster than it was a moment ago
+        fs::write(&baseline, format!("{}\n", written * 1000.0)).unwrap();
+        match check(&["--perf-tolerance=50"]) {
+            Err(Error::VerificationMismatch(_)) => {}
+            other => panic!("expected a regression, got {other:?}"),
+        }
+        fs::write(&baseline, format!("{}\n", written / 1000.0)).unwrap();
+        check(&[]).unwrap();
+
+        fs::write(&baseline, "fast\n").unwrap();
+        assert!(matches!(check(&[]), Err(Error::MalformedData(_))));
+        fs::remove_file(&baseline).unwrap();
+        assert!(matches!(
+            check_with(Path::new("data/missing"), &[]),
+            Err(Error::FileNotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{SendError, Sender};
+
+/// Runtime counters of the chunked engine, reported by `--profile`.
+///
+/// Only the producer sends chunks, so the counters are not contended. They are
+/// atomic so the workers and the report can share them.
+pub(crate) struct Profile {
+    start: Instant,
+    chunks: AtomicUsize,
+    bytes: AtomicU64,
+    /// The sum of the chunks that were already queued at every send
+    queued: AtomicUsize,
+    /// The number of sends that found the channel full
+    full: AtomicUsize,
+    /// The time the producer was blocked on a full channel
+    blocked_nanos: AtomicU64,
+    /// The time from the start until the producer sent its last chunk
+    producer_nanos: AtomicU64,
+    worker_chunks: Mutex<Vec<usize>>,
+}
+
+impl Profile {
+    pub(crate) fn new() -> Self {
+        Profile {
+            start: Instant::now(),
+            chunks: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            queued: AtomicUsize::new(0),
+            full: AtomicUsize::new(0),
+            blocked_nanos: AtomicU64::new(0),
+            producer_nanos: AtomicU64::new(0),
+            worker_chunks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Sends a chunk of `bytes` bytes to the workers and records how full the
+    /// channel was.
+    pub(crate) fn send<T>(
+        &self,
+        sender: &Sender<T>,
+        chunk: T,
+        bytes: usize,
+    ) -> Result<(), SendError<T>> {
+        self.chunks.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.queued.fetch_add(sender.len(), Ordering::Relaxed);
+        if sender.is_full() {
+            self.full.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let start = Instant::now();
+        let sent = sender.send(chunk);
+        self.blocked_nanos
+            .fetch_add(nanos(start.elapsed()), Ordering::Relaxed);
+        sent
+    }
+
+    /// Called by the producer after its last chunk was sent
+    pub(crate) fn producer_done(&self) {
+        let elapsed = nanos(self.start.elapsed());
+        self.producer_nanos.store(elapsed, Ordering::Relaxed);
+    }
+
+    /// Called by every worker with the number of chunks it processed
+    pub(crate) fn worker_done(&self, chunks: usize) {
+        self.worker_chunks.lock().unwrap().push(chunks);
+    }
+
+    /// Writes a readable summary of the counters, where `capacity` is the
+    /// capacity of the chunk channel.
+    pub(crate) fn report<W: Write>(&self, capacity: usize, mut output: W) -> io::Result<()> {
+        let elapsed = self.start.elapsed();
+        let chunks = self.chunks.load(Ordering::Relaxed);
+        let megabytes = self.bytes.load(Ordering::Relaxed) as f64 / 1e6;
+        let full = self.full.load(Ordering::Relaxed);
+        let blocked = Duration::from_nanos(self.blocked_nanos.load(Ordering::Relaxed));
+        let producer = Duration::from_nanos(self.producer_nanos.load(Ordering::Relaxed));
+        let queued = self.queued.load(Ordering::Relaxed) as f64 / chunks.max(1) as f64;
+
+        writeln!(
+            output,
+            "# profile: {chunks} chunks, {megabytes:.1} MB in {elapsed:.2?} ({:.1} MB/s)",
+            megabytes / elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            output,
+            "# producer: reading for {:.2?}, blocked on a full channel for {blocked:.2?} \
+             ({full} of {chunks} sends)",
+            producer.saturating_sub(blocked)
+        )?;
+        writeln!(
+            output,
+            "# channel: {queued:.1} of {capacity} chunks queued on average"
+        )?;
+        writeln!(
+            output,
+            "# chunk size: {:.0} bytes on average",
+            self.bytes.load(Ordering::Relaxed) as f64 / chunks.max(1) as f64
+        )?;
+        let mut worker_chunks = self.worker_chunks.lock().unwrap().clone();
+        worker_chunks.sort_unstable();
+        writeln!(output, "# chunks per worker: {worker_chunks:?}")?;
+
+        // a mostly full channel means the workers cannot keep up with the reads
+        let bottleneck = if queued >= capacity as f64 / 2.0 {
+            "workers"
+        } else {
+            "producer"
+        };
+        writeln!(output, "# bottleneck: {bottleneck}")
+    }
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_nanos().try_into().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use crossbeam::channel::bounded;
+
+    use super::Profile;
+
+    #[test]
+    fn check_profile_counters() {
+        let profile = Profile::new();
+        let (sender, receiver) = bounded(2);
+        profile.send(&sender, 1, 100).unwrap();
+        profile.send(&sender, 2, 50).unwrap();
+        receiver.recv().unwrap();
+        profile.send(&sender, 3, 50).unwrap();
+        profile.producer_done();
+        profile.worker_done(2);
+        profile.worker_done(1);
+
+        let mut output = Vec::new();
+        profile.report(2, &mut output).unwrap();
+        let output = from_utf8(&output).unwrap();
+
+        assert!(
+            output.starts_with("# profile: 3 chunks, 0.0 MB in "),
+            "{output}"
+        );
+        // 0, 1 and 1 chunks were queued at the three sends, the second one was not full
+        assert!(output.contains("(0 of 3 sends)"), "{output}");
+        assert!(
+            output.contains("# channel: 0.7 of 2 chunks queued"),
+            "{output}"
+        );
+        assert!(
+            output.contains("# chunk size: 67 bytes on average\n"),
+            "{output}"
+        );
+        assert!(output.contains("# chunks per worker: [1, 2]\n"), "{output}");
+        assert!(output.ends_with("# bottleneck: producer\n"), "{output}");
+    }
+}
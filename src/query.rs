@@ -0,0 +1,221 @@
+use std::io::{BufRead, Write};
+
+use crate::{CityEntry, CityStats, Error, Results};
+
+/// The value of a city that a [Filter] compares
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Min,
+    Mean,
+    Max,
+    Stddev,
+    Count,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "min" => Some(Field::Min),
+            "mean" => Some(Field::Mean),
+            "max" => Some(Field::Max),
+            "stddev" => Some(Field::Stddev),
+            "count" => Some(Field::Count),
+            _ => None,
+        }
+    }
+
+    /// The value of `city` as it is printed with one decimal, so that a filter
+    /// for a printed value matches it exactly.
+    fn of(self, city: &CityEntry) -> f64 {
+        let stats = CityStats::from(city);
+        let printed = |value: f64| {
+            format!("{value:.1}")
+                .parse()
+                .expect("a formatted float can be parsed")
+        };
+        match self {
+            Field::Min => printed(stats.min.into()),
+            Field::Mean => printed(stats.mean.into()),
+            Field::Max => printed(stats.max.into()),
+            Field::Stddev => printed(stats.stddev),
+            Field::Count => stats.count as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+/// A query like `mean>30` that selects the cities whose field compares to the value
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Filter {
+    field: Field,
+    comparison: Comparison,
+    value: f64,
+}
+
+impl Filter {
+    /// Parses `<field><comparison><value>`, where whitespace around the parts is
+    /// ignored. Returns `None` if `query` is not a filter.
+    fn parse(query: &str) -> Option<Self> {
+        let start = query.find(['<', '>', '='])?;
+        let (field, rest) = query.split_at(start);
+        let (comparison, value) = [
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+            ("=", Comparison::Equal),
+        ]
+        .into_iter()
+        .find_map(|(operator, comparison)| {
+            rest.strip_prefix(operator).map(|value| (comparison, value))
+        })?;
+
+        Some(Filter {
+            field: Field::parse(field.trim())?,
+            comparison,
+            value: value.trim().parse().ok()?,
+        })
+    }
+
+    fn matches(&self, city: &CityEntry) -> bool {
+        let value = self.field.of(city);
+        match self.comparison {
+            Comparison::Less => value < self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::Greater => value > self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+            Comparison::Equal => value == self.value,
+        }
+    }
+}
+
+/// Answers the queries of `--interactive`, one per line of `input`, until the
+/// input ends or a line is `quit`.
+///
+/// A query is either a city name, which prints the values of that city, or a
+/// filter like `mean>30`, which prints every matching city in output order.
+pub(crate) fn run<R: BufRead, W: Write>(
+    results: &Results,
+    input: R,
+    mut output: W,
+) -> Result<(), Error> {
+    for line in input.lines() {
+        let line = line?;
+        let query = line.trim();
+        if query.is_empty() {
+            continue;
+        }
+        if query == "quit" {
+            break;
+        }
+
+        // a city name takes precedence, in case a name looks like a filter
+        if let Some(city) = results.get(query) {
+            writeln!(output, "{}", results.city(query, city))?;
+        } else if let Some(filter) = Filter::parse(query) {
+            let mut matches = 0;
+            for (name, city) in results.iter().filter(|(_, city)| filter.matches(city)) {
+                writeln!(output, "{}", results.city(name, city))?;
+                matches += 1;
+            }
+            writeln!(output, "# {matches} cities")?;
+        } else {
+            writeln!(output, "# no city or filter {query:?}")?;
+        }
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::from_utf8;
+
+    use hashbrown::HashMap;
+
+    use super::{run, Comparison, Field, Filter};
+    use crate::{CityEntry, Order, Results};
+
+    #[test]
+    fn check_parse_filter() {
+        assert_eq!(
+            Filter::parse("mean > -3.5"),
+            Some(Filter {
+                field: Field::Mean,
+                comparison: Comparison::Greater,
+                value: -3.5
+            })
+        );
+        assert_eq!(
+            Filter::parse("count<=2"),
+            Some(Filter {
+                field: Field::Count,
+                comparison: Comparison::LessOrEqual,
+                value: 2.0
+            })
+        );
+        assert_eq!(Filter::parse("Hamburg"), None);
+        assert_eq!(Filter::parse("median>3"), None);
+        assert_eq!(Filter::parse("mean>"), None);
+        assert_eq!(Filter::parse("mean=>3"), None);
+    }
+
+    #[test]
+    fn check_printed_values() {
+        let mut city = CityEntry::default();
+        city.add(12.3);
+        city.add(12.5);
+        let results = Results::new(
+            HashMap::from([("a".to_string(), city.clone())]),
+            Order::Sorted,
+        );
+        assert_eq!(results.city("a", &city).to_string(), "a=12.3/12.4/12.5");
+
+        for query in [
+            "min=12.3",
+            "mean=12.4",
+            "max=12.5",
+            "mean<=12.4",
+            "min>=12.3",
+        ] {
+            let filter = Filter::parse(query).unwrap();
+            assert!(filter.matches(&city), "{query}");
+        }
+        for query in ["mean<12.4", "max>12.5"] {
+            let filter = Filter::parse(query).unwrap();
+            assert!(!filter.matches(&city), "{query}");
+        }
+    }
+
+    #[test]
+    fn check_queries() {
+        let mut result = HashMap::new();
+        for (name, values) in [("a", &[1.0, 3.0][..]), ("b", &[35.0]), ("c=1", &[40.0])] {
+            let mut entry = CityEntry::default();
+            values.iter().for_each(|&value| entry.add(value));
+            result.insert(name.to_string(), entry);
+        }
+        let results = Results::new(result, Order::Sorted);
+
+        let input = "a\n\nmean>30\n  count = 2 \nc=1\nz\nquit\nb\n";
+        let mut output = Vec::new();
+        run(&results, input.as_bytes(), &mut output).unwrap();
+
+        assert_eq!(
+            from_utf8(&output).unwrap(),
+            "a=1.0/2.0/3.0\n\
+             b=35.0/35.0/35.0\nc=1=40.0/40.0/40.0\n# 2 cities\n\
+             a=1.0/2.0/3.0\n# 1 cities\n\
+             c=1=40.0/40.0/40.0\n\
+             # no city or filter \"z\"\n"
+        );
+    }
+}
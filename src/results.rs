@@ -0,0 +1,439 @@
+use std::{
+    fmt::{self, Display},
+    io::Write,
+};
+
+use hashbrown::HashMap;
+
+use crate::{CityEntry, Error, Order};
+
+/// The name of the entry that holds the folded cities of [Results::limit_cities]
+const OTHER: &str = "(other)";
+
+/// The aggregated values of all cities in output order.
+///
+/// [Display] writes the challenge format `{city=min/mean/max, ...}` with the
+/// values rounded to one decimal.
+#[derive(Debug, Clone, Default)]
+pub struct Results {
+    cities: Vec<(String, CityEntry)>,
+    show_stddev: bool,
+}
+
+impl Results {
+    /// Orders the cities of `result` by `order`.
+    pub fn new(result: HashMap<String, CityEntry>, order: Order) -> Self {
+        let mut cities: Vec<_> = result.into_iter().collect();
+        match order {
+            Order::Sorted => cities.sort_unstable_by(|a, b| a.0.cmp(&b.0)),
+            Order::FirstSeen => cities.sort_unstable_by_key(|(_, entry)| entry.first_seen),
+        }
+        Results {
+            cities,
+            show_stddev: false,
+        }
+    }
+
+    /// Sorts the cities by a simplified collation of their names instead of by
+    /// their bytes, see [SortOrder::Unicode](crate::SortOrder::Unicode).
+    pub fn collated(mut self) -> Self {
+        self.cities
+            .sort_by_cached_key(|(name, _)| (collation_key(name), name.clone()));
+        self
+    }
+
+    /// Keeps the `limit` cities with the most rows in their order and folds all other
+    /// cities into a single `(other)` entry at the end, see `--limit-cities`.
+    ///
+    /// Ties in the number of rows are broken by name. Does nothing without a limit
+    /// or if there are at most `limit` cities.
+    pub fn limit_cities(mut self, limit: Option<usize>) -> Self {
+        let Some(limit) = limit.filter(|&limit| limit < self.cities.len()) else {
+            return self;
+        };
+
+        let mut by_count: Vec<_> = (0..self.cities.len()).collect();
+        by_count.sort_unstable_by(|&a, &b| {
+            let (a, b) = (&self.cities[a], &self.cities[b]);
+            b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(&b.0))
+        });
+        let mut kept = vec![false; self.cities.len()];
+        by_count[..limit].iter().for_each(|&city| kept[city] = true);
+
+        let mut other: Option<CityEntry> = None;
+        let mut kept = kept.into_iter();
+        self.cities.retain(|(_, city)| {
+            let keep = kept.next().unwrap();
+            if !keep {
+                match &mut other {
+                    Some(other) => other.merge(city),
+                    None => other = Some(city.clone()),
+                }
+            }
+            keep
+        });
+        self.cities
+            .extend(other.map(|other| (OTHER.to_string(), other)));
+        self
+    }
+
+    /// Whether [Display] appends the standard deviation as `city=min/mean/max/stddev`
+    pub fn show_stddev(mut self, show: bool) -> Self {
+        self.show_stddev = show;
+        self
+    }
+
+    /// The cities and their values in output order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &CityEntry)> {
+        self.cities
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry))
+    }
+
+    /// The values of the city called `name`
+    pub fn get(&self, name: &str) -> Option<&CityEntry> {
+        self.iter()
+            .find(|(city, _)| *city == name)
+            .map(|(_, entry)| entry)
+    }
+
+    /// A single city in the format of [Display], e.g. `city=min/mean/max`
+    pub(crate) fn city<'a>(&self, name: &'a str, city: &CityEntry) -> CityLine<'a> {
+        CityLine {
+            name,
+            stats: CityStats::from(city),
+            show_stddev: self.show_stddev,
+            show_count: false,
+        }
+    }
+
+    /// Passes every city to `sink` in output order and finishes it.
+    pub fn emit_to<S: ResultSink + ?Sized>(&self, sink: &mut S) -> Result<(), Error> {
+        for (name, city) in self.iter() {
+            // `--limit-cities` and `--group-by-prefix` merge cities
+            city.check_count(name)?;
+            sink.emit(name, CityStats::from(city))?;
+        }
+        sink.finish()
+    }
+
+    /// The number of cities
+    pub fn len(&self) -> usize {
+        self.cities.len()
+    }
+
+    /// Whether there are no cities
+    pub fn is_empty(&self) -> bool {
+        self.cities.is_empty()
+    }
+}
+
+impl Display for Results {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (name, city)) in self.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", self.city(name, city))?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// The lowercase `name` with the diacritics of Latin letters removed, e.g. `"ae"`
+/// for `"Æ"` and `"zurich"` for `"Zürich"`.
+fn collation_key(name: &str) -> String {
+    let mut key = String::with_capacity(name.len());
+    for c in name.chars() {
+        match fold_latin(c) {
+            Some(base) => key.push_str(base),
+            None => key.extend(c.to_lowercase()),
+        }
+    }
+    key
+}
+
+/// The lowercase base letters of a Latin-1 or Latin Extended-A letter with diacritics
+fn fold_latin(c: char) -> Option<&'static str> {
+    let base = match c {
+        'À'..='Å' | 'à'..='å' | '\u{100}'..='\u{105}' => "a",
+        'Æ' | 'æ' => "ae",
+        'Ç' | 'ç' | '\u{106}'..='\u{10D}' => "c",
+        'Ð' | 'ð' | '\u{10E}'..='\u{111}' => "d",
+        'È'..='Ë' | 'è'..='ë' | '\u{112}'..='\u{11B}' => "e",
+        '\u{11C}'..='\u{123}' => "g",
+        '\u{124}'..='\u{127}' => "h",
+        'Ì'..='Ï' | 'ì'..='ï' | '\u{128}'..='\u{131}' => "i",
+        '\u{132}' | '\u{133}' => "ij",
+        '\u{134}' | '\u{135}' => "j",
+        '\u{136}'..='\u{138}' => "k",
+        '\u{139}'..='\u{142}' => "l",
+        'Ñ' | 'ñ' | '\u{143}'..='\u{14B}' => "n",
+        'Ò'..='Ö' | 'Ø' | 'ò'..='ö' | 'ø' | '\u{14C}'..='\u{151}' => "o",
+        '\u{152}' | '\u{153}' => "oe",
+        '\u{154}'..='\u{159}' => "r",
+        'ß' => "ss",
+        '\u{15A}'..='\u{161}' | '\u{17F}' => "s",
+        'Þ' | 'þ' => "th",
+        '\u{162}'..='\u{167}' => "t",
+        'Ù'..='Ü' | 'ù'..='ü' | '\u{168}'..='\u{173}' => "u",
+        '\u{174}' | '\u{175}' => "w",
+        'Ý' | 'ý' | 'ÿ' | '\u{176}'..='\u{178}' => "y",
+        '\u{179}'..='\u{17E}' => "z",
+        _ => return None,
+    };
+    Some(base)
+}
+
+/// The final values of a city that are passed to a [ResultSink]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CityStats {
+    pub min: f32,
+    /// The mean as it is printed, which is summed and divided as `f32`
+    pub mean: f32,
+    pub max: f32,
+    /// The population standard deviation, see [CityEntry::stddev]
+    pub stddev: f64,
+    pub count: usize,
+}
+
+impl From<&CityEntry> for CityStats {
+    fn from(city: &CityEntry) -> Self {
+        CityStats {
+            min: city.min,
+            mean: city.sum / city.count as f32,
+            max: city.max,
+            stddev: city.stddev(),
+            count: city.count,
+        }
+    }
+}
+
+/// Receives the final values of every city in output order, so the result can be
+/// written somewhere other than the challenge output, e.g. into a database.
+///
+/// See [Results::emit_to]. [TextSink] writes the challenge output.
+pub trait ResultSink {
+    /// Receives the values of the next city
+    fn emit(&mut self, city: &str, stats: CityStats) -> Result<(), Error>;
+
+    /// Called once after the last city
+    fn finish(&mut self) -> Result<(), Error>;
+}
+
+/// A [ResultSink] that writes the challenge format `{city=min/mean/max, ...}`,
+/// the same as [Display] of [Results].
+pub struct TextSink<W> {
+    output: W,
+    show_stddev: bool,
+    show_count: bool,
+    cities: usize,
+}
+
+impl<W: Write> TextSink<W> {
+    /// Appends the standard deviation to every city if `show_stddev` is set.
+    pub fn new(output: W, show_stddev: bool) -> Self {
+        TextSink {
+            output,
+            show_stddev,
+            show_count: false,
+            cities: 0,
+        }
+    }
+
+    /// Whether the number of rows is appended last, as `city=min/mean/max/count`
+    pub fn show_count(mut self, show: bool) -> Self {
+        self.show_count = show;
+        self
+    }
+}
+
+impl<W: Write> ResultSink for TextSink<W> {
+    fn emit(&mut self, city: &str, stats: CityStats) -> Result<(), Error> {
+        let separator = if self.cities == 0 { "{" } else { ", " };
+        let line = CityLine {
+            name: city,
+            stats,
+            show_stddev: self.show_stddev,
+            show_count: self.show_count,
+        };
+        write!(self.output, "{separator}{line}")?;
+        self.cities += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Error> {
+        let start = if self.cities == 0 { "{" } else { "" };
+        write!(self.output, "{start}}}")?;
+        Ok(())
+    }
+}
+
+/// See [Results::city]
+pub(crate) struct CityLine<'a> {
+    pub(crate) name: &'a str,
+    pub(crate) stats: CityStats,
+    pub(crate) show_stddev: bool,
+    pub(crate) show_count: bool,
+}
+
+impl Display for CityLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = &self.stats;
+        write!(
+            f,
+            "{}={:.1}/{:.1}/{:.1}",
+            self.name, stats.min, stats.mean, stats.max
+        )?;
+        if self.show_stddev {
+            write!(f, "/{:.1}", stats.stddev)?;
+        }
+        if self.show_count {
+            write!(f, "/{}", stats.count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hashbrown::HashMap;
+
+    use super::{collation_key, CityStats, ResultSink, Results, TextSink};
+    use crate::{CityEntry, Error, Order};
+
+    #[test]
+    fn check_collated() {
+        assert_eq!(collation_key("Zürich"), "zurich");
+        assert_eq!(collation_key("Æbeltoft"), "aebeltoft");
+        assert_eq!(collation_key("Łódź"), "lodz");
+        assert_eq!(collation_key("東京"), "東京");
+
+        let names = [
+            "Zürich", "äpfel", "Apfel", "Ödland", "zebra", "Oslo", "Łódź",
+        ];
+        let result: HashMap<_, _> = names
+            .iter()
+            .map(|name| (name.to_string(), CityEntry::default()))
+            .collect();
+        let bytewise = Results::new(result.clone(), Order::Sorted);
+        let bytewise: Vec<_> = bytewise.iter().map(|(name, _)| name).collect();
+        assert_eq!(
+            bytewise,
+            ["Apfel", "Oslo", "Zürich", "zebra", "Ödland", "äpfel", "Łódź"]
+        );
+        let collated = Results::new(result, Order::Sorted).collated();
+        let collated: Vec<_> = collated.iter().map(|(name, _)| name).collect();
+        // equal keys are sorted by bytes, so the uppercase name comes first
+        assert_eq!(
+            collated,
+            ["Apfel", "äpfel", "Łódź", "Ödland", "Oslo", "zebra", "Zürich"]
+        );
+    }
+
+    #[test]
+    fn check_results_display() {
+        let mut result = HashMap::new();
+        for (name, values, first_seen) in [
+            ("b", [1.0, 2.25], (0, 1)),
+            ("a", [-0.04, -10.0], (1, 0)),
+            ("c", [99.9, 99.9], (0, 0)),
+        ] {
+            let mut entry = CityEntry {
+                first_seen,
+                ..CityEntry::default()
+            };
+            values.into_iter().for_each(|value| entry.add(value));
+            result.insert(name.to_string(), entry);
+        }
+
+        let sorted = Results::new(result.clone(), Order::Sorted);
+        assert_eq!(
+            sorted.to_string(),
+            "{a=-10.0/-5.0/-0.0, b=1.0/1.6/2.2, c=99.9/99.9/99.9}"
+        );
+        let first_seen = Results::new(result, Order::FirstSeen);
+        let names: Vec<_> = first_seen.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["c", "b", "a"]);
+        assert_eq!(
+            first_seen.show_stddev(true).to_string(),
+            "{c=99.9/99.9/99.9/0.0, b=1.0/1.6/2.2/0.6, a=-10.0/-5.0/-0.0/5.0}"
+        );
+
+        assert_eq!(Results::default().to_string(), "{}");
+    }
+
+    #[test]
+    fn check_result_sink() {
+        #[derive(Default)]
+        struct Collect {
+            cities: Vec<(String, usize)>,
+            finished: bool,
+        }
+
+        impl ResultSink for Collect {
+            fn emit(&mut self, city: &str, stats: CityStats) -> Result<(), Error> {
+                self.cities.push((city.to_string(), stats.count));
+                Ok(())
+            }
+
+            fn finish(&mut self) -> Result<(), Error> {
+                self.finished = true;
+                Ok(())
+            }
+        }
+
+        let mut result = HashMap::new();
+        for (name, values) in [("b", &[1.0, 2.25][..]), ("a", &[-0.04])] {
+            let mut entry = CityEntry::default();
+            values.iter().for_each(|&value| entry.add(value));
+            result.insert(name.to_string(), entry);
+        }
+        let results = Results::new(result, Order::Sorted).show_stddev(true);
+
+        let mut collect = Collect::default();
+        results.emit_to(&mut collect).unwrap();
+        assert_eq!(collect.cities, [("a".to_string(), 1), ("b".to_string(), 2)]);
+        assert!(collect.finished);
+
+        for results in [results, Results::default()] {
+            let mut text = Vec::new();
+            results
+                .emit_to(&mut TextSink::new(&mut text, true))
+                .unwrap();
+            assert_eq!(String::from_utf8(text).unwrap(), results.to_string());
+        }
+    }
+
+    #[test]
+    fn check_limit_cities() {
+        let mut result = HashMap::new();
+        for (name, values) in [
+            ("a", &[1.0][..]),
+            ("b", &[2.0, 4.0, 6.0]),
+            ("c", &[-5.0, 5.0]),
+            ("d", &[10.0, 20.0]),
+            ("e", &[-1.0, 0.0, 1.0]),
+        ] {
+            let mut entry = CityEntry::default();
+            values.iter().for_each(|&value| entry.add(value));
+            result.insert(name.to_string(), entry);
+        }
+        let results = Results::new(result, Order::Sorted);
+
+        // c and d have the same number of rows, so d is folded by its name
+        assert_eq!(
+            results.clone().limit_cities(Some(3)).to_string(),
+            "{b=2.0/4.0/6.0, c=-5.0/0.0/5.0, e=-1.0/0.0/1.0, (other)=1.0/10.3/20.0}"
+        );
+        let other = results.clone().limit_cities(Some(0));
+        assert_eq!(other.to_string(), "{(other)=-5.0/3.9/20.0}");
+        assert_eq!(other.get("(other)").unwrap().count(), 11);
+        assert_eq!(
+            results.clone().limit_cities(Some(5)).to_string(),
+            results.clone().limit_cities(None).to_string()
+        );
+        assert_eq!(results.limit_cities(Some(5)).len(), 5);
+    }
+}
@@ -0,0 +1,455 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    io::{self, Write},
+};
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::{
+    check_count, into_string_keys, CityEntry, CityStats, Error, Order, ResultSink, TextSink,
+};
+
+/// Receives the rows of the input.
+///
+/// Each worker aggregates into its own sink, and the sinks of all workers are merged
+/// once they are done. Implement this to reuse the parallel reader for custom
+/// aggregations, see [crate::aggregate].
+pub trait RowSink: Send + 'static {
+    /// Called once for every row of the input.
+    ///
+    /// City names are not validated as utf8 if the input is read with `--assume-ascii`
+    /// or in the binary format.
+    fn accept(&mut self, city: &[u8], value: f32);
+
+    /// Whether the values of the rows are parsed.
+    ///
+    /// If not, values are not checked against the expected bounds and
+    /// [RowSink::accept] receives `f32::NAN` for every row.
+    const PARSE_VALUES: bool = true;
+
+    /// Called instead of [RowSink::accept] for every row if the values are integers.
+    fn accept_int(&mut self, city: &[u8], value: i64) {
+        self.accept(city, value as f32);
+    }
+
+    /// Adds all rows seen by `other` to `self`.
+    fn merge(&mut self, other: Self);
+
+    /// Called before the rows of a chunk are passed to [RowSink::accept].
+    ///
+    /// Chunks are numbered in the order they appear in the input, but each
+    /// worker only sees some of them.
+    fn start_chunk(&mut self, _index: usize) {}
+}
+
+/// The min, mean and max of every city, used by the challenge output.
+#[derive(Debug, Default, Clone)]
+pub struct CityMap {
+    cities: HashMap<Vec<u8>, CityEntry>,
+    chunk: usize,
+    row: usize,
+}
+
+impl CityMap {
+    /// Converts the city names to strings.
+    ///
+    /// Fails if a city name is not valid utf8.
+    pub fn into_result(self) -> Result<HashMap<String, CityEntry>, Error> {
+        into_string_keys(self.cities)
+    }
+}
+
+impl RowSink for CityMap {
+    fn accept(&mut self, city: &[u8], value: f32) {
+        match self.cities.get_mut(city) {
+            Some(entry) => {
+                entry.add(value);
+                // chunks only arrive out of order with `--reverse`
+                if entry.first_seen.0 > self.chunk {
+                    entry.first_seen = (self.chunk, self.row);
+                }
+            }
+            None => {
+                let mut entry = CityEntry {
+                    first_seen: (self.chunk, self.row),
+                    ..CityEntry::default()
+                };
+                entry.add(value);
+                self.cities.insert(city.to_vec(), entry);
+            }
+        }
+        self.row += 1;
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (city, entry) in other.cities {
+            match self.cities.get_mut(&city) {
+                Some(merged) => merged.merge(&entry),
+                None => {
+                    self.cities.insert(city, entry);
+                }
+            }
+        }
+    }
+
+    fn start_chunk(&mut self, index: usize) {
+        self.chunk = index;
+        self.row = 0;
+    }
+}
+
+/// The min, mean and max of every city, kept sorted by name, see `--sorted-merge`.
+///
+/// Merging walks the maps in order, and the result is written while iterating
+/// the merged map, so the names are never collected and sorted at the end.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct SortedCityMap {
+    cities: BTreeMap<Vec<u8>, CityEntry>,
+}
+
+impl SortedCityMap {
+    /// The number of rows of all cities
+    pub(crate) fn rows(&self) -> usize {
+        self.cities.values().map(|entry| entry.count).sum()
+    }
+
+    /// Writes the result in the challenge format, sorted by name.
+    ///
+    /// Fails if a city name is not valid utf8 or a city has too many rows.
+    pub(crate) fn write<OWrite: Write>(
+        &self,
+        show_stddev: bool,
+        show_count: bool,
+        output: OWrite,
+    ) -> Result<(), Error> {
+        let mut sink = TextSink::new(output, show_stddev).show_count(show_count);
+        for (city, entry) in &self.cities {
+            let city = std::str::from_utf8(city)
+                .map_err(|e| Error::MalformedData(format!("city name is not valid utf8: {e}")))?;
+            entry.check_count(city)?;
+            sink.emit(city, CityStats::from(entry))?;
+        }
+        sink.finish()
+    }
+}
+
+impl RowSink for SortedCityMap {
+    fn accept(&mut self, city: &[u8], value: f32) {
+        match self.cities.get_mut(city) {
+            Some(entry) => entry.add(value),
+            None => {
+                let mut entry = CityEntry::default();
+                entry.add(value);
+                self.cities.insert(city.to_vec(), entry);
+            }
+        }
+    }
+
+    fn merge(&mut self, mut other: Self) {
+        // insert the cities of the smaller map into the larger one
+        if other.cities.len() > self.cities.len() {
+            std::mem::swap(self, &mut other);
+        }
+        for (city, entry) in other.cities {
+            match self.cities.get_mut(&city) {
+                Some(merged) => merged.merge(&entry),
+                None => {
+                    self.cities.insert(city, entry);
+                }
+            }
+        }
+    }
+}
+
+/// The aggregated integer values of a single city
+#[derive(Debug, Clone)]
+struct IntEntry {
+    min: i64,
+    max: i64,
+    sum: i128,
+    /// The number of values, see [CityEntry::count]
+    count: usize,
+    /// Whether `count` overflowed while adding or merging values
+    overflowed: bool,
+    /// The chunk index and row within that chunk where the city first appeared
+    first_seen: (usize, usize),
+}
+
+impl IntEntry {
+    fn new(value: i64, first_seen: (usize, usize)) -> Self {
+        IntEntry {
+            min: value,
+            max: value,
+            sum: value as i128,
+            count: 1,
+            overflowed: false,
+            first_seen,
+        }
+    }
+
+    fn add(&mut self, value: i64) {
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.sum += value as i128;
+        let (count, overflowed) = self.count.overflowing_add(1);
+        self.count = count;
+        self.overflowed |= overflowed;
+    }
+
+    fn merge(&mut self, other: &IntEntry) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+        let (count, overflowed) = self.count.overflowing_add(other.count);
+        self.count = count;
+        self.overflowed |= overflowed || other.overflowed;
+        self.first_seen = self.first_seen.min(other.first_seen);
+    }
+}
+
+/// The min, mean and max of every city for integer values.
+///
+/// Values are summed as integers, so the mean is exact up to the final division.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct IntCityMap {
+    cities: HashMap<Vec<u8>, IntEntry>,
+    chunk: usize,
+    row: usize,
+}
+
+impl IntCityMap {
+    /// The number of rows of all cities
+    pub(crate) fn rows(&self) -> usize {
+        self.cities.values().map(|entry| entry.count).sum()
+    }
+
+    /// Writes the result in the challenge format, with the mean rounded to an integer.
+    pub(crate) fn write<OWrite: Write>(
+        self,
+        order: Order,
+        mut output: OWrite,
+    ) -> Result<(), Error> {
+        let mut cities = self
+            .cities
+            .into_iter()
+            .map(|(city, entry)| {
+                let city = String::from_utf8(city).map_err(|e| {
+                    Error::MalformedData(format!("city name is not valid utf8: {e}"))
+                })?;
+                check_count(entry.overflowed, &city)?;
+                Ok((city, entry))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        match order {
+            Order::Sorted => cities.sort_unstable_by(|a, b| a.0.cmp(&b.0)),
+            Order::FirstSeen => cities.sort_unstable_by_key(|(_, entry)| entry.first_seen),
+        }
+
+        let write = |output: &mut OWrite| -> io::Result<()> {
+            write!(output, "{{")?;
+            for (i, (city, entry)) in cities.iter().enumerate() {
+                if i > 0 {
+                    write!(output, ", ")?;
+                }
+                let mean = (entry.sum as f64 / entry.count as f64).round();
+                write!(output, "{city}={}/{mean}/{}", entry.min, entry.max)?;
+            }
+            write!(output, "}}")
+        };
+        Ok(write(&mut output)?)
+    }
+}
+
+/// The limits of the original challenge, see `--spec-strict`
+const SPEC_MAX_CITY_BYTES: usize = 100;
+const SPEC_MAX_CITIES: usize = 10_000;
+
+impl IntCityMap {
+    /// Writes the result of values in tenths exactly like the reference implementation
+    /// of the original challenge, see `--spec-strict`.
+    ///
+    /// Cities are sorted by name and every value is rounded half up to one decimal,
+    /// like `Math.round(value * 10.0) / 10.0` in Java. The mean is rounded from the
+    /// exact sum, which matches the reference whenever its `double` sum is exact.
+    /// Fails if a city name is empty or longer than 100 bytes, or if there are
+    /// more than 10,000 cities.
+    pub(crate) fn write_spec<OWrite: Write>(self, mut output: OWrite) -> Result<(), Error> {
+        if self.cities.len() > SPEC_MAX_CITIES {
+            return Err(Error::MalformedData(format!(
+                "{} distinct cities, the challenge allows at most {SPEC_MAX_CITIES}",
+                self.cities.len()
+            )));
+        }
+        let mut cities = self
+            .cities
+            .into_iter()
+            .map(|(city, entry)| {
+                let city = String::from_utf8(city).map_err(|e| {
+                    Error::MalformedData(format!("city name is not valid utf8: {e}"))
+                })?;
+                check_count(entry.overflowed, &city)?;
+                if city.is_empty() || city.len() > SPEC_MAX_CITY_BYTES {
+                    return Err(Error::MalformedData(format!(
+                        "city name must have 1 to {SPEC_MAX_CITY_BYTES} bytes: {city:?}"
+                    )));
+                }
+                Ok((city, entry))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        cities.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let write = |output: &mut OWrite| -> io::Result<()> {
+            write!(output, "{{")?;
+            for (i, (city, entry)) in cities.iter().enumerate() {
+                if i > 0 {
+                    write!(output, ", ")?;
+                }
+                // floor(mean + 0.5) of the mean in tenths, without rounding the division
+                let count = entry.count as i128;
+                let mean = (2 * entry.sum + count).div_euclid(2 * count) as i64;
+                write!(
+                    output,
+                    "{city}={}/{}/{}",
+                    Tenths(entry.min),
+                    Tenths(mean),
+                    Tenths(entry.max)
+                )?;
+            }
+            write!(output, "}}")
+        };
+        Ok(write(&mut output)?)
+    }
+}
+
+/// Formats a number of tenths with one decimal, e.g. `-12` as `-1.2`
+struct Tenths(i64);
+
+impl Display for Tenths {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let tenths = self.0.unsigned_abs();
+        write!(f, "{sign}{}.{}", tenths / 10, tenths % 10)
+    }
+}
+
+impl RowSink for IntCityMap {
+    fn accept(&mut self, city: &[u8], value: f32) {
+        self.accept_int(city, value as i64);
+    }
+
+    fn accept_int(&mut self, city: &[u8], value: i64) {
+        match self.cities.get_mut(city) {
+            Some(entry) => {
+                entry.add(value);
+                if entry.first_seen.0 > self.chunk {
+                    entry.first_seen = (self.chunk, self.row);
+                }
+            }
+            None => {
+                let entry = IntEntry::new(value, (self.chunk, self.row));
+                self.cities.insert(city.to_vec(), entry);
+            }
+        }
+        self.row += 1;
+    }
+
+    fn merge(&mut self, other: Self) {
+        for (city, entry) in other.cities {
+            match self.cities.get_mut(&city) {
+                Some(merged) => merged.merge(&entry),
+                None => {
+                    self.cities.insert(city, entry);
+                }
+            }
+        }
+    }
+
+    fn start_chunk(&mut self, index: usize) {
+        self.chunk = index;
+        self.row = 0;
+    }
+}
+
+/// The distinct city names, without any statistics.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CityNames {
+    names: HashSet<Vec<u8>>,
+}
+
+impl CityNames {
+    /// Writes the city names sorted, one per line.
+    pub(crate) fn write<OWrite: Write>(&self, mut output: OWrite) -> Result<(), Error> {
+        let mut names = self
+            .names
+            .iter()
+            .map(|city| {
+                std::str::from_utf8(city)
+                    .map_err(|e| Error::MalformedData(format!("city name is not valid utf8: {e}")))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        names.sort_unstable();
+
+        for name in names {
+            writeln!(output, "{name}")?;
+        }
+        Ok(())
+    }
+}
+
+impl RowSink for CityNames {
+    const PARSE_VALUES: bool = false;
+
+    fn accept(&mut self, city: &[u8], _value: f32) {
+        if !self.names.contains(city) {
+            self.names.insert(city.to_vec());
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.names.extend(other.names);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hashbrown::HashMap;
+
+    use super::{IntCityMap, IntEntry};
+    use crate::{Error, Order, RowSink};
+
+    #[test]
+    fn check_int_count_overflow() {
+        let full = IntEntry {
+            count: usize::MAX,
+            ..IntEntry::new(1, (0, 0))
+        };
+        let mut added = full.clone();
+        added.add(2);
+        let mut merged = full.clone();
+        merged.merge(&IntEntry::new(2, (0, 1)));
+
+        for entry in [added, merged] {
+            let mut cities = IntCityMap {
+                cities: HashMap::from([(b"a".to_vec(), entry)]),
+                ..IntCityMap::default()
+            };
+            // the overflow is kept by later merges
+            cities.merge(IntCityMap {
+                cities: HashMap::from([(b"a".to_vec(), IntEntry::new(3, (1, 0)))]),
+                ..IntCityMap::default()
+            });
+            for result in [
+                cities.clone().write(Order::Sorted, Vec::new()),
+                cities.write_spec(Vec::new()),
+            ] {
+                match result {
+                    Err(Error::MalformedData(message)) => {
+                        assert_eq!(message, "more than usize::MAX rows for city \"a\"")
+                    }
+                    other => panic!("expected an overflow, got {other:?}"),
+                }
+            }
+        }
+    }
+}
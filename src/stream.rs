@@ -0,0 +1,343 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    mem::ManuallyDrop,
+    str::from_utf8,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{bounded, RecvTimeoutError, Sender};
+
+use crate::{
+    check_columns, newline_aligned_boundary, normalize_names, process_bytes, process_text,
+    resolve_separator, write_cities, Args, Block, BlockPool, CityMap, Engine, Error, Format,
+    RowSink, Separator, StrBuffer, ValueType, BLOCK_SIZE, BOM, CHANNEL_CAPACITY,
+};
+
+/// The input path that reads the rows from stdin
+pub(crate) const STDIN: &str = "-";
+
+/// Aggregates the rows of `input`, which does not have to be seekable or ever end,
+/// see `--emit-every`.
+///
+/// Rows are aggregated as soon as their line is complete. With `--emit-every` the
+/// result of all rows so far is printed on its own line at every interval and once
+/// more at the end of the input.
+pub(crate) fn calculate_stream<R: Read + Send + 'static, OWrite: Write>(
+    args: &Args,
+    input: R,
+    mut output: OWrite,
+) -> Result<(), Error> {
+    check_options(args)?;
+    if args.separator == Separator::Auto {
+        return Err(unsupported("--separator auto"));
+    }
+    let separator = resolve_separator(args, &[])?;
+    check_columns(args, &[], separator)?;
+    let options = args.parse_options(separator);
+    let validate_utf8 = !args.skip_utf8_validation();
+
+    let (chunk_sender, chunk_receiver) = bounded(CHANNEL_CAPACITY);
+    let reader = thread::spawn(move || read_chunks(input, chunk_sender, validate_utf8));
+
+    let every = args
+        .emit_every
+        .map(|every| Duration::from_secs(every.get()));
+    let mut next_emit = every.map(|every| Instant::now() + every);
+    let mut cities = CityMap::default();
+    let mut skipped = 0;
+    loop {
+        let chunk = match (every, next_emit) {
+            (Some(every), Some(deadline)) => match chunk_receiver.recv_deadline(deadline) {
+                Ok(chunk) => chunk,
+                Err(RecvTimeoutError::Timeout) => {
+                    emit(&cities, args, &mut output)?;
+                    next_emit = Some(deadline + every);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            _ => match chunk_receiver.recv() {
+                Ok(chunk) => chunk,
+                Err(_) => break,
+            },
+        };
+        cities.start_chunk(chunk.index);
+        skipped += match validate_utf8 {
+            true => process_text(&chunk, &options, &mut cities)?,
+            false => process_bytes(chunk.as_bytes(), &options, &mut cities)?,
+        };
+    }
+
+    match reader.join() {
+        Ok(read) => read?,
+        Err(_) => panic!("read chunks failed"),
+    }
+    if args.lenient {
+        eprintln!("# skipped {skipped} malformed lines");
+    }
+    match every {
+        Some(_) => emit(&cities, args, &mut output),
+        None => {
+            let result = normalize_names(cities.into_result()?, args.normalize_names);
+            write_cities(result, args, output)
+        }
+    }
+}
+
+/// Fails for the options that need to read the input more than once or to seek in it.
+fn check_options(args: &Args) -> Result<(), Error> {
+    let unsupported_options = [
+        (args.input.len() > 1, "more than one input"),
+        (args.format != Format::Text, "--format binary"),
+        (args.engine != Engine::Chunked, "--engine mmap-zerocopy"),
+        (args.value_type != ValueType::Float, "--value-type int"),
+        (args.spec_strict, "--spec-strict"),
+        (
+            args.offset.is_some() || args.length.is_some(),
+            "--offset and --length",
+        ),
+        (args.reverse, "--reverse"),
+        (args.adaptive_block_size, "--adaptive-block-size"),
+        (args.window.is_some(), "--window"),
+        (args.hash_input, "--hash-input"),
+        (args.tail.is_some(), "--tail"),
+        (args.expect_lines.is_some(), "--expect-lines"),
+        (args.max_seconds.is_some(), "--max-seconds"),
+        (args.prime_cache, "--prime-cache"),
+        (args.validate_utf8_once, "--validate-utf8-once"),
+        (args.keys_only, "--keys-only"),
+        (args.histogram.is_some(), "--histogram"),
+        (args.dedup_values, "--dedup-values"),
+        (args.external_merge.is_some(), "--external-merge"),
+        (args.sorted_merge, "--sorted-merge"),
+        (args.interactive, "--interactive"),
+        (args.snapshot_every.is_some(), "--snapshot-every"),
+        (args.profile, "--profile"),
+        (args.explain, "--explain"),
+        (args.bench.is_some(), "--bench"),
+    ];
+    match unsupported_options.iter().find(|(set, _)| *set) {
+        Some((_, option)) => Err(unsupported(option)),
+        None => Ok(()),
+    }
+}
+
+fn unsupported(option: &str) -> Error {
+    Error::InvalidArguments(format!(
+        "{option} is not supported for stdin, urls, compressed input or with --emit-every"
+    ))
+}
+
+/// Prints the result of all rows so far on its own line.
+fn emit<OWrite: Write>(cities: &CityMap, args: &Args, output: &mut OWrite) -> Result<(), Error> {
+    let result = normalize_names(cities.clone().into_result()?, args.normalize_names);
+    write_cities(result, args, &mut *output)?;
+    writeln!(output)?;
+    Ok(output.flush()?)
+}
+
+/// Sends the complete lines of `input` to `sender` as soon as a read returns them,
+/// instead of waiting for a full block like [StrBuffer::read_from].
+///
+/// Only complete lines are validated as utf8. The incomplete last line, which might
+/// end in the middle of a multibyte char, is carried over to the next block.
+fn read_chunks<R: Read>(
+    mut input: R,
+    sender: Sender<StrBuffer>,
+    validate_utf8: bool,
+) -> Result<(), Error> {
+    let pool = BlockPool::new();
+    let mut block = pool.take();
+    let mut filled = 0;
+    let mut index = 0;
+    loop {
+        let size = match input.read(&mut block[filled..]) {
+            Ok(size) => size,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if size == 0 {
+            break;
+        }
+        filled += size;
+
+        let Some(boundary) = newline_aligned_boundary(&block[..filled], true) else {
+            if filled == BLOCK_SIZE {
+                return Err(Error::Io(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line is longer than the block size of {BLOCK_SIZE} bytes"),
+                )));
+            }
+            continue;
+        };
+        // the incomplete last line is continued in the next block
+        let mut next = pool.take();
+        next[..filled - boundary].copy_from_slice(&block[boundary..filled]);
+        let chunk = new_chunk(block, boundary, index, validate_utf8, &pool)?;
+        if sender.send(chunk).is_err() {
+            // aggregating stopped early and reports its own error
+            return Ok(());
+        }
+        block = next;
+        filled -= boundary;
+        index += 1;
+    }
+
+    if filled > 0 {
+        let chunk = new_chunk(block, filled, index, validate_utf8, &pool)?;
+        // aggregating might have stopped early and reports its own error
+        let _ = sender.send(chunk);
+    }
+    Ok(())
+}
+
+/// A chunk of the first `size` bytes of `block`, without a leading [BOM] in the
+/// first chunk.
+fn new_chunk(
+    mut block: Block,
+    mut size: usize,
+    index: usize,
+    validate_utf8: bool,
+    pool: &BlockPool,
+) -> Result<StrBuffer, Error> {
+    if index == 0 && block[..size].starts_with(BOM) {
+        block.copy_within(BOM.len()..size, 0);
+        size -= BOM.len();
+    }
+    if validate_utf8 {
+        from_utf8(&block[..size])
+            .map_err(|e| Error::Io(io::Error::new(ErrorKind::InvalidData, e)))?;
+    }
+    Ok(StrBuffer {
+        raw_data: ManuallyDrop::new(block),
+        size,
+        utf8: validate_utf8,
+        index,
+        pool: Some(pool.returned.clone()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{self, Cursor, Read},
+        str::from_utf8,
+        thread,
+        time::Duration,
+    };
+
+    use clap::Parser;
+    use crossbeam::channel::unbounded;
+
+    use super::{calculate_stream, read_chunks};
+    use crate::{Args, BLOCK_SIZE};
+
+    /// Returns one part per read and sleeps for `delay` before every part after the first
+    struct Feed {
+        parts: Vec<&'static [u8]>,
+        delay: Duration,
+        read: usize,
+    }
+
+    impl Read for Feed {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some(part) = self.parts.get(self.read) else {
+                return Ok(0);
+            };
+            if self.read > 0 {
+                thread::sleep(self.delay);
+            }
+            buf[..part.len()].copy_from_slice(part);
+            self.read += 1;
+            Ok(part.len())
+        }
+    }
+
+    #[test]
+    fn check_read_chunks() {
+        let feed = Feed {
+            parts: vec![
+                b"\xEF\xBB\xBFa;1",
+                b".0\nb;2.0\nc",
+                b";3",
+                b".0\n",
+                b"d;4.0",
+            ],
+            delay: Duration::ZERO,
+            read: 0,
+        };
+        let (sender, receiver) = unbounded();
+        read_chunks(feed, sender, true).unwrap();
+
+        let chunks: Vec<_> = receiver
+            .into_iter()
+            .map(|chunk| (chunk.index, chunk.as_bytes().to_vec()))
+            .collect();
+        assert_eq!(
+            chunks,
+            [
+                (0, b"a;1.0\nb;2.0\n".to_vec()),
+                (1, b"c;3.0\n".to_vec()),
+                (2, b"d;4.0".to_vec())
+            ]
+        );
+    }
+
+    #[test]
+    fn check_split_multibyte_char() {
+        // `é` is split between two reads
+        let feed = Feed {
+            parts: vec![b"a;1.0\nb\xC3", b"\xA9;2.0\n"],
+            delay: Duration::ZERO,
+            read: 0,
+        };
+        let (sender, receiver) = unbounded();
+        read_chunks(feed, sender, true).unwrap();
+        let chunks: Vec<_> = receiver
+            .into_iter()
+            .map(|chunk| chunk.to_string())
+            .collect();
+        assert_eq!(chunks, ["a;1.0\n", "bé;2.0\n"]);
+
+        // `é` starts at the last byte of the first block
+        let mut data = "a;1.0\n".repeat(BLOCK_SIZE / 6);
+        data.push_str("xyzé;2.0\nb;3.0\n");
+        assert_eq!(data.find('é'), Some(BLOCK_SIZE - 1));
+        let (sender, receiver) = unbounded();
+        read_chunks(Cursor::new(data.clone()), sender, true).unwrap();
+        let chunks: Vec<_> = receiver
+            .into_iter()
+            .map(|chunk| chunk.to_string())
+            .collect();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks.concat(), data);
+
+        let args = Args::parse_from(["one-billion-rows", "-"]);
+        let mut output = Vec::new();
+        calculate_stream(&args, Cursor::new(data), &mut output).unwrap();
+        assert_eq!(
+            from_utf8(&output).unwrap(),
+            "{a=1.0/1.0/1.0, b=3.0/3.0/3.0, xyzé=2.0/2.0/2.0}"
+        );
+    }
+
+    #[test]
+    fn check_emit_every() {
+        let feed = Feed {
+            parts: vec![b"a;1.0\nb;", b"2.0\na;3.0\n"],
+            delay: Duration::from_millis(1500),
+            read: 0,
+        };
+        let args = Args::parse_from(["one-billion-rows", "--emit-every=1", "-"]);
+        let mut output = Vec::new();
+        calculate_stream(&args, feed, &mut output).unwrap();
+
+        let output = from_utf8(&output).unwrap();
+        let emissions: Vec<_> = output.lines().collect();
+        assert!(emissions.len() >= 2, "{output}");
+        // b is only complete after the delay
+        assert_eq!(emissions[0], "{a=1.0/1.0/1.0}");
+        assert_eq!(emissions.last().unwrap(), &"{a=1.0/2.0/3.0, b=2.0/2.0/2.0}");
+    }
+}
@@ -0,0 +1,135 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{parse_row_bytes, Error, ParseOptions, Value, ValueType, BLOCK_SIZE, BOM};
+
+/// Prints the city and value of the last `count` lines of `path`, see `--tail`.
+///
+/// Only the end of the file is read. Lines that cannot be parsed are printed as
+/// they are, and a missing newline at the end of the file is reported, as that is
+/// what a truncated file looks like.
+pub(crate) fn write_tail<OWrite: Write>(
+    path: &Path,
+    count: usize,
+    options: &ParseOptions,
+    mut output: OWrite,
+) -> Result<(), Error> {
+    let mut file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+    let (lines, terminated) = last_lines(&mut file, count).map_err(|e| Error::from_io(path, e))?;
+
+    for line in &lines {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        match parse_row_bytes(line, options) {
+            Some((city, value)) => {
+                let city = String::from_utf8_lossy(city);
+                match (value, options.value_type) {
+                    (Value::Int(tenths), ValueType::Tenths) => {
+                        writeln!(output, "{city}={:.1}", tenths as f64 / 10.0)?
+                    }
+                    (Value::Int(value), _) => writeln!(output, "{city}={value}")?,
+                    (Value::Float(value), _) => writeln!(output, "{city}={value:?}")?,
+                }
+            }
+            None => writeln!(
+                output,
+                "# malformed line: {:?}",
+                String::from_utf8_lossy(line)
+            )?,
+        }
+    }
+    if !terminated {
+        writeln!(
+            output,
+            "# {} does not end with a newline, the last line might be truncated",
+            path.display()
+        )?;
+    }
+    Ok(())
+}
+
+/// The last `count` lines of `file` without their newline, and whether the file
+/// ends with a newline.
+///
+/// Reads blocks from the end of the file until enough lines are complete. An
+/// empty file ends with a newline, as it has no truncated line.
+fn last_lines(file: &mut File, count: usize) -> io::Result<(Vec<Vec<u8>>, bool)> {
+    let mut position = file.metadata()?.len();
+    let mut data = Vec::new();
+    let mut terminated = true;
+    let mut newlines = 0;
+    while position > 0 {
+        let size = position.min(BLOCK_SIZE as u64);
+        position -= size;
+        file.seek(SeekFrom::Start(position))?;
+        let mut block = vec![0; size as usize];
+        file.read_exact(&mut block)?;
+
+        if data.is_empty() {
+            terminated = block.ends_with(b"\n");
+        }
+        newlines += block.iter().filter(|&&c| c == b'\n').count();
+        block.extend_from_slice(&data);
+        data = block;
+        // the newline at the end of the file does not start another line
+        if newlines - usize::from(terminated) >= count {
+            break;
+        }
+    }
+
+    if position == 0 && data.starts_with(BOM) {
+        data.drain(..BOM.len());
+    }
+    let data = match terminated {
+        true => data.strip_suffix(b"\n").unwrap_or(&data),
+        false => &data,
+    };
+    if data.is_empty() {
+        return Ok((Vec::new(), terminated));
+    }
+    // the first line is incomplete unless the start of the file was reached
+    let mut lines: Vec<_> = data
+        .rsplit(|&c| c == b'\n')
+        .take(count)
+        .map(<[u8]>::to_vec)
+        .collect();
+    lines.reverse();
+    Ok((lines, terminated))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use super::write_tail;
+    use crate::ParseOptions;
+
+    fn tail(path: &Path, count: usize) -> String {
+        let mut output = Vec::new();
+        write_tail(path, count, &ParseOptions::default(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn check_tail() {
+        let test_data = Path::new("data/test.txt");
+        assert_eq!(tail(test_data, 3), "Y=-23.4\n1I=-60.4\ne=-23.9\n");
+        assert_eq!(tail(test_data, 0), "");
+
+        let path = std::env::temp_dir().join("one-billion-rows-tail.txt");
+        fs::write(&path, "\u{feff}a;1.0\nb;2\nbroken\nc;-3").unwrap();
+        let all = tail(&path, 10);
+        assert_eq!(
+            all.lines().take(4).collect::<Vec<_>>(),
+            ["a=1.0", "b=2.0", "# malformed line: \"broken\"", "c=-3.0"]
+        );
+        assert!(all.ends_with("does not end with a newline, the last line might be truncated\n"));
+        assert_eq!(tail(&path, 1).lines().next(), Some("c=-3.0"));
+
+        fs::write(&path, "").unwrap();
+        assert_eq!(tail(&path, 5), "");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind, Read},
+    path::PathBuf,
+    str::from_utf8,
+};
+
+use crate::Error;
+
+/// Checks that all `files` are valid utf8, see `--validate-utf8-once`.
+pub(crate) fn validate_files(files: &[PathBuf]) -> Result<(), Error> {
+    let mut buffer = vec![0; 1024 * 1024];
+    for path in files {
+        let file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+        validate(file, &mut buffer).map_err(|e| Error::from_io(path, e))?;
+    }
+    Ok(())
+}
+
+/// Checks that everything read from `read` is valid utf8, reading it in blocks of
+/// the size of `buffer`.
+///
+/// A character can be split between two blocks, so the incomplete character at
+/// the end of a block is moved to the start of the buffer and completed by the
+/// next read.
+fn validate<R: Read>(mut read: R, buffer: &mut [u8]) -> io::Result<()> {
+    // the 4 bytes of the longest character always have to fit
+    assert!(buffer.len() >= 4, "the buffer cannot hold every character");
+
+    let mut offset = 0;
+    let mut carried = 0;
+    loop {
+        let size = match read.read(&mut buffer[carried..]) {
+            Ok(0) if carried == 0 => return Ok(()),
+            Ok(0) => return Err(invalid_at(offset)),
+            Ok(size) => carried + size,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        let valid = match from_utf8(&buffer[..size]) {
+            Ok(_) => size,
+            // the character might be completed by the next read
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(invalid_at(offset + e.valid_up_to() as u64)),
+        };
+        buffer.copy_within(valid..size, 0);
+        carried = size - valid;
+        offset += valid as u64;
+    }
+}
+
+fn invalid_at(offset: u64) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid utf8 at byte {offset}"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read};
+
+    use super::validate;
+
+    /// Returns at most `step` bytes per read, to split characters between reads
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let size = buf.len().min(self.step).min(self.data.len());
+            buf[..size].copy_from_slice(&self.data[..size]);
+            self.data = &self.data[size..];
+            Ok(size)
+        }
+    }
+
+    #[test]
+    fn check_validate_split_characters() {
+        let text = "Zürich;1.0\n東京;-2.5\n🦀;3.0\n".as_bytes();
+        for step in 1..8 {
+            for buffer_size in [4, 5, 7, 64] {
+                let read = Trickle { data: text, step };
+                assert!(
+                    validate(read, &mut vec![0; buffer_size]).is_ok(),
+                    "step {step}, buffer size {buffer_size}"
+                );
+            }
+        }
+
+        let mut invalid = text.to_vec();
+        invalid[11] = 0xff;
+        let read = Trickle {
+            data: &invalid,
+            step: 3,
+        };
+        let error = validate(read, &mut [0; 5]).unwrap_err();
+        assert_eq!(error.to_string(), "invalid utf8 at byte 11");
+
+        // a character that is cut off by the end of the input
+        let truncated = &text[..text.len() - 8];
+        let read = Trickle {
+            data: truncated,
+            step: 2,
+        };
+        let error = validate(read, &mut [0; 8]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("invalid utf8 at byte {}", truncated.len() - 1)
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+
+use crate::{parse::parse_timestamp, Args, Error, Format};
+
+/// The timestamp column and the first timestamp inside of the `--window` of `args`,
+/// or `None` without a window.
+///
+/// The window ends at the latest timestamp of the input, so all input files are
+/// read once to find it before any row is aggregated. Lines without a valid
+/// timestamp are ignored here, they are reported as malformed while aggregating.
+pub(crate) fn resolve(
+    args: &Args,
+    input_files: &[PathBuf],
+    separator: u8,
+) -> Result<Option<(usize, i64)>, Error> {
+    let Some((column, window)) = args.timestamp_column.zip(args.window) else {
+        return Ok(None);
+    };
+    if args.format != Format::Text {
+        return Err(Error::InvalidArguments(
+            "--window requires text input".to_string(),
+        ));
+    }
+
+    let mut latest = None;
+    for path in input_files {
+        let file = File::open(path).map_err(|e| Error::from_io(path, e))?;
+        for line in BufReader::new(file).split(b'\n') {
+            let line = line.map_err(|e| Error::from_io(path, e))?;
+            let line = line.strip_suffix(b"\r").unwrap_or(&line);
+            latest = latest.max(parse_timestamp(line, separator, column));
+        }
+    }
+    // without any timestamp there is no row inside of the window either
+    let start = latest.map_or(i64::MAX, |latest: i64| {
+        latest.saturating_sub_unsigned(window.get() - 1)
+    });
+    Ok(Some((column, start)))
+}